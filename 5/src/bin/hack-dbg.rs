@@ -0,0 +1,58 @@
+use std::io::{BufRead, Write};
+
+use assembler::Assembler;
+use emulator::debugger::Debugger;
+
+fn usage() -> ! {
+    eprintln!("usage: hack-dbg PROGRAM.asm");
+    std::process::exit(2)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(path), None) = (args.next(), args.next()) else {
+        usage()
+    };
+    if path == "-h" || path == "--help" {
+        usage();
+    }
+    let source = std::fs::read_to_string(&path)?;
+    let mut asm = Assembler::new(&source);
+    asm.set_file_name(&path);
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        asm.add_include_path(dir);
+    }
+    let mut debugger = Debugger::new(&mut asm).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    println!("{}", debugger.execute("where")?);
+    let mut last = String::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("(hack-dbg) ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        // an empty line repeats the last command
+        let command = match line.trim() {
+            "" => last.clone(),
+            command => command.to_string(),
+        };
+        if command.is_empty() {
+            continue;
+        }
+        if command == "quit" || command == "q" {
+            break;
+        }
+        match debugger.execute(&command) {
+            Ok(output) => println!("{}", output),
+            Err(message) => println!("error: {}", message),
+        }
+        last = command;
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use assembler::disasm::{self, SymbolMap};
+use assembler::{Assembler, Diagnostic, SymbolKind};
+use emulator::Machine;
+use emulator::profile::Profiler;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hack-prof [--cycles N] [--top N] [--folded OUT] \
+         (PROGRAM.asm | PROGRAM.hack --symbols FILE)"
+    );
+    std::process::exit(2)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    std::process::exit(1)
+}
+
+fn number(s: &str) -> u64 {
+    s.parse().unwrap_or_else(|_| usage())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cycles = 10_000_000;
+    let mut top = 20;
+    let mut folded_path = None;
+    let mut symbols_path = None;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--cycles" => cycles = number(&value()),
+            "--top" => top = number(&value()) as usize,
+            "--folded" => folded_path = Some(value()),
+            "--symbols" => symbols_path = Some(value()),
+            "-h" | "--help" => usage(),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let source = std::fs::read_to_string(&path)?;
+
+    // labels come from the assembler for a source and from a symbol file for a `.hack`
+    let (words, labels) = if path.ends_with(".asm") {
+        let mut asm = Assembler::new(&source);
+        asm.set_file_name(&path);
+        let words = asm.assemble()?;
+        let labels = asm
+            .symbols()?
+            .into_iter()
+            .filter(|(_, _, kind)| *kind == SymbolKind::Label)
+            .map(|(name, addr, _)| (addr as u16, name))
+            .collect::<Vec<_>>();
+        (words, labels)
+    } else {
+        let words = disasm::read_hack(&path, &source).unwrap_or_else(|d| report(&d));
+        let symbols_path = symbols_path.unwrap_or_else(|| usage());
+        let symbols = std::fs::read_to_string(&symbols_path)?;
+        let map = SymbolMap::parse(&symbols_path, &symbols).unwrap_or_else(|d| report(&d));
+        let labels = map
+            .labels()
+            .map(|(addr, name)| (addr, name.to_string()))
+            .collect();
+        (words, labels)
+    };
+
+    let mut machine = Machine::new();
+    machine.load_rom(&words);
+    let mut profiler = Profiler::new(&words, labels.iter().map(|(a, n)| (*a, n.as_str())));
+    profiler.run_for(&mut machine, cycles);
+
+    print!("{}", profiler.report(top));
+    if let Some(out) = folded_path {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(out)?);
+        profiler.write_folded(&mut file)?;
+    }
+    Ok(())
+}
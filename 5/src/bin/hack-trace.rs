@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use assembler::{Assembler, Diagnostic, SourceLine, disasm};
+use emulator::Machine;
+use emulator::keyboard::Script;
+use emulator::trace::{self, Reader, Recorder, Step};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hack-trace record [--cycles N] [--keys SCRIPT] [--ram ADDRESS=VALUE]... \
+         PROGRAM.asm|PROGRAM.hack OUT.trace\n       \
+         hack-trace diff [--source OLD.asm] [--new-source NEW.asm] OLD.trace NEW.trace"
+    );
+    std::process::exit(2)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    std::process::exit(1)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("record") => record(args),
+        Some("diff") => diff(args),
+        _ => usage(),
+    }
+}
+
+fn record(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cycles = 1_000_000u64;
+    let mut keys_path = None;
+    let mut ram = Vec::new();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--cycles" => cycles = value().parse().unwrap_or_else(|_| usage()),
+            "--keys" => keys_path = Some(value()),
+            "--ram" => {
+                let setting = value();
+                let (address, word) = setting.split_once('=').unwrap_or_else(|| usage());
+                let address = address.parse::<usize>().unwrap_or_else(|_| usage());
+                let word = word.parse::<i16>().unwrap_or_else(|_| usage());
+                ram.push((address, word as u16));
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let [program, out] = <[String; 2]>::try_from(paths).unwrap_or_else(|_| usage());
+
+    let source = std::fs::read_to_string(&program)?;
+    let words = if program.ends_with(".asm") {
+        let mut asm = Assembler::new(&source);
+        asm.set_file_name(&program);
+        if let Some(dir) = std::path::Path::new(&program).parent() {
+            asm.add_include_path(dir);
+        }
+        asm.assemble()?
+    } else {
+        disasm::read_hack(&program, &source).unwrap_or_else(|d| report(&d))
+    };
+    let mut script = match keys_path {
+        Some(keys) => Script::parse(&std::fs::read_to_string(&keys)?)
+            .map_err(|e| format!("{}: {}", keys, e))?,
+        None => Script::default(),
+    };
+    let mut machine = Machine::new();
+    machine.load_rom(&words);
+    for (address, word) in ram {
+        match machine.ram_mut().get_mut(address) {
+            Some(slot) => *slot = word,
+            None => usage(),
+        }
+    }
+
+    let mut recorder = Recorder::new(BufWriter::new(File::create(&out)?))?;
+    for _ in 0..cycles {
+        script.apply(&mut machine);
+        recorder.step(&mut machine)?;
+    }
+    recorder.into_inner().flush()?;
+    Ok(())
+}
+
+fn diff(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut old_source = None;
+    let mut new_source = None;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--source" => old_source = Some(args.next().unwrap_or_else(|| usage())),
+            "--new-source" => new_source = Some(args.next().unwrap_or_else(|| usage())),
+            _ => paths.push(arg),
+        }
+    }
+    let [old, new] = <[String; 2]>::try_from(paths).unwrap_or_else(|_| usage());
+    // both builds come from the same source unless told otherwise
+    let new_source = new_source.or_else(|| old_source.clone());
+
+    let open =
+        |path: &str| -> std::io::Result<_> { Reader::new(BufReader::new(File::open(path)?)) };
+    let Some(divergence) = trace::diff(open(&old)?, open(&new)?)? else {
+        println!("traces are the same");
+        return Ok(());
+    };
+
+    println!("traces differ at instruction {}", divergence.index);
+    let show = |step: Option<Step>| step.map_or("(trace ended)".to_string(), |s| s.to_string());
+    if let Some(common) = divergence.common {
+        println!("  both: {}", show(Some(common)));
+    }
+    println!("  old:  {}", show(divergence.left));
+    println!("  new:  {}", show(divergence.right));
+
+    for (name, source, step) in [
+        ("old", old_source, divergence.left),
+        ("new", new_source, divergence.right),
+    ] {
+        if let (Some(source), Some(step)) = (source, step.or(divergence.common)) {
+            println!("\n{} source:", name);
+            print!("{}", context(&source, step.pc)?);
+        }
+    }
+    std::process::exit(1)
+}
+
+/// The source lines around the one which assembled to ROM address `pc`
+fn context(path: &str, pc: u16) -> Result<String, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut asm = Assembler::new(&text);
+    asm.set_file_name(path);
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        asm.add_include_path(dir);
+    }
+    let lines = asm.source_lines()?;
+    let Some(SourceLine { file, line, .. }) = lines.get(usize::from(pc)) else {
+        return Ok(format!("  no source for ROM address {}\n", pc));
+    };
+    // the line may be in a file the source includes
+    let text = if file == path {
+        text
+    } else {
+        std::fs::read_to_string(file)?
+    };
+
+    let mut out = format!("  {}:{}\n", file, line);
+    let first = line.saturating_sub(3);
+    for (i, source) in text.lines().enumerate().skip(first).take(5) {
+        let marker = if i + 1 == *line { '>' } else { ' ' };
+        out.push_str(&format!("{} {:>5}  {}\n", marker, i + 1, source));
+    }
+    Ok(out)
+}
@@ -0,0 +1,96 @@
+use emulator::Machine;
+use emulator::keyboard::Script;
+use emulator::screen::Format;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hackrun [--cycles N] [--ram ADDRESS=VALUE]... [--keys SCRIPT] [--screen OUT.pbm|OUT.png] \
+         [--frames DIR --every N [--frame-format pbm|png]] PROGRAM.hack"
+    );
+    std::process::exit(2)
+}
+
+fn number(s: &str) -> u64 {
+    s.parse().unwrap_or_else(|_| usage())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cycles = 1_000_000;
+    let mut ram = Vec::new();
+    let mut keys_path = None;
+    let mut screen_path = None;
+    let mut frames_dir = None;
+    let mut every = None;
+    let mut frame_format = Format::Pbm;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--cycles" => cycles = number(&value()),
+            "--ram" => {
+                let setting = value();
+                let (address, word) = setting.split_once('=').unwrap_or_else(|| usage());
+                let address = usize::try_from(number(address)).unwrap_or_else(|_| usage());
+                let word = word.parse::<i16>().unwrap_or_else(|_| usage());
+                ram.push((address, word as u16));
+            }
+            "--keys" => keys_path = Some(value()),
+            "--screen" => screen_path = Some(value()),
+            "--frames" => frames_dir = Some(value()),
+            "--every" => every = Some(number(&value()).max(1)),
+            "--frame-format" => {
+                frame_format =
+                    Format::from_path(&format!(".{}", value())).unwrap_or_else(|| usage())
+            }
+            "-h" | "--help" => usage(),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    if frames_dir.is_some() != every.is_some() {
+        usage();
+    }
+
+    let source = std::fs::read_to_string(&path)?;
+    let words = assembler::disasm::read_hack(&path, &source).unwrap_or_else(|diagnostics| {
+        for d in diagnostics {
+            eprintln!("{}\n", d);
+        }
+        std::process::exit(1)
+    });
+    let mut script = match keys_path {
+        Some(keys) => Script::parse(&std::fs::read_to_string(&keys)?)
+            .map_err(|e| format!("{}: {}", keys, e))?,
+        None => Script::default(),
+    };
+    let mut machine = Machine::new();
+    machine.load_rom(&words);
+    for (address, word) in ram {
+        match machine.ram_mut().get_mut(address) {
+            Some(slot) => *slot = word,
+            None => usage(),
+        }
+    }
+
+    if let (Some(dir), Some(every)) = (&frames_dir, every) {
+        std::fs::create_dir_all(dir)?;
+        let mut done = 0;
+        while done < cycles {
+            let n = every.min(cycles - done);
+            script.run_for(&mut machine, n);
+            done += n;
+            let name = format!("{}/{:010}.{}", dir, done, frame_format.extension());
+            std::fs::write(name, frame_format.encode(machine.ram()))?;
+        }
+    } else {
+        script.run_for(&mut machine, cycles);
+    }
+
+    if let Some(out) = screen_path {
+        let format = Format::from_path(&out).ok_or("screen image must end in .pbm or .png")?;
+        std::fs::write(out, format.encode(machine.ram()))?;
+    }
+    Ok(())
+}
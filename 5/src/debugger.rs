@@ -0,0 +1,419 @@
+//! A debugger for assembled programs, driven by text commands
+//!
+//! Each command returns what it prints, and stopping the program shows the
+//! source line of the next instruction instead of its ROM address. [`HELP`]
+//! lists the commands.
+//!
+//! `SP`, `LCL`, `ARG`, `THIS` and `THAT` hold addresses, so `x/16 LCL` shows
+//! the words `LCL` points to.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use assembler::{Assembler, SourceLine, SymbolKind};
+
+use crate::Machine;
+
+/// Instructions `continue` and `next` execute before giving up
+const MAX_CYCLES: u64 = 10_000_000;
+
+/// Predefined symbols holding an address, which `x/N` follows
+const POINTERS: &[&str] = &["SP", "LCL", "ARG", "THIS", "THAT"];
+
+pub const HELP: &str = "\
+commands:
+  break LABEL | ADDRESS | :LINE    stop before that instruction
+  delete LABEL | ADDRESS | :LINE   remove a breakpoint
+  watch RAM[ADDRESS] | SYMBOL      stop when that word changes
+  step [N]                         execute N instructions
+  next                             execute the rest of the source line
+  continue                         run to a breakpoint, watch or halt
+  print D | A | M | PC | SYMBOL | RAM[ADDRESS]
+  x/N LOCATION                     show N words from LOCATION
+  where                            show the next source line
+  info                             list breakpoints and watches
+  reset                            start the program again
+  quit";
+
+pub struct Debugger {
+    machine: Machine,
+    /// Source line of each ROM word
+    lines: Vec<SourceLine>,
+    symbols: BTreeMap<String, (usize, SymbolKind)>,
+    breakpoints: BTreeSet<u16>,
+    /// Watched RAM addresses and their last values
+    watches: BTreeMap<usize, u16>,
+}
+
+impl Debugger {
+    /// Load the program of `asm`, assembling it if needed
+    pub fn new(asm: &mut Assembler) -> Result<Self, assembler::Error> {
+        let mut machine = Machine::new();
+        machine.load_rom(&asm.assemble()?);
+        let symbols = asm
+            .symbols()?
+            .into_iter()
+            .map(|(name, addr, kind)| (name, (addr, kind)))
+            .collect();
+        Ok(Self {
+            machine,
+            lines: asm.source_lines()?,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+        })
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Run one command, returning what it prints or what is wrong with it
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let command = command.trim();
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, rest)| (name, rest.trim()));
+        match name {
+            "break" | "b" => {
+                let pc = self.rom_address(argument)?;
+                self.breakpoints.insert(pc);
+                Ok(format!("breakpoint at {}", self.location(pc)))
+            }
+            "delete" | "d" => {
+                let pc = self.rom_address(argument)?;
+                if self.breakpoints.remove(&pc) {
+                    Ok(format!("deleted breakpoint at {}", self.location(pc)))
+                } else {
+                    Err(format!("no breakpoint at `{}`", argument))
+                }
+            }
+            "watch" | "w" => {
+                let address = self.ram_address(argument)?;
+                let value = self.machine.ram()[address];
+                self.watches.insert(address, value);
+                Ok(format!(
+                    "watching {} = {}",
+                    self.ram_name(address),
+                    value as i16
+                ))
+            }
+            "step" | "s" => {
+                let count = match argument {
+                    "" => 1,
+                    n => n
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("`{}` is not a count", n))?,
+                };
+                let mut left = count;
+                Ok(self.resume(|_| {
+                    left -= 1;
+                    left == 0
+                }))
+            }
+            "next" | "n" => {
+                let line = self.current_line();
+                Ok(self.resume(|d| line.is_none() || d.current_line() != line))
+            }
+            "continue" | "c" => Ok(self.resume(|_| false)),
+            "print" | "p" => self.print(argument),
+            "where" => Ok(self.location(self.machine.pc())),
+            "info" => Ok(self.info()),
+            "reset" => {
+                self.machine.reset();
+                Ok(self.location(self.machine.pc()))
+            }
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => match name.strip_prefix("x/") {
+                Some(count) => {
+                    let count = count
+                        .parse()
+                        .map_err(|_| format!("`{}` is not a count", count))?;
+                    self.examine(argument, count)
+                }
+                None => Err(format!("unknown command `{}`, try `help`", command)),
+            },
+        }
+    }
+
+    /// Step until `done` says so or the program reaches a breakpoint, changes a
+    /// watched word or halts, reporting why it stopped
+    fn resume(&mut self, mut done: impl FnMut(&Self) -> bool) -> String {
+        let mut report = Vec::new();
+        for _ in 0..MAX_CYCLES {
+            self.machine.step();
+            for (&address, last) in &mut self.watches {
+                let value = self.machine.ram()[address];
+                if value != *last {
+                    report.push(format!(
+                        "{} changed from {} to {}",
+                        ram_name(&self.symbols, address),
+                        *last as i16,
+                        value as i16
+                    ));
+                    *last = value;
+                }
+            }
+            let pc = self.machine.pc();
+            if self.breakpoints.contains(&pc) {
+                report.push("breakpoint".to_string());
+            }
+            if self.machine.halted() {
+                report.push("halted".to_string());
+            }
+            if !report.is_empty() || done(self) {
+                report.push(self.location(pc));
+                return report.join("\n");
+            }
+        }
+        format!(
+            "still running after {} instructions\n{}",
+            MAX_CYCLES,
+            self.location(self.machine.pc())
+        )
+    }
+
+    fn print(&self, argument: &str) -> Result<String, String> {
+        let m = &self.machine;
+        let value = match argument {
+            "D" => m.d(),
+            "A" => m.a(),
+            "PC" => m.pc(),
+            "M" => {
+                let address = usize::from(m.a());
+                let value = m.ram().get(address).copied().unwrap_or(0);
+                return Ok(format!("M = {} = {}", self.ram_name(address), value as i16));
+            }
+            _ => {
+                let address = self.ram_address(argument)?;
+                let value = m.ram()[address];
+                return Ok(format!("{} = {}", self.ram_name(address), value as i16));
+            }
+        };
+        Ok(format!("{} = {}", argument, value as i16))
+    }
+
+    /// `count` words from `location`, or from where it points for a pointer like `SP`
+    fn examine(&self, location: &str, count: usize) -> Result<String, String> {
+        let ram = self.machine.ram();
+        let mut start = self.ram_address(location)?;
+        if POINTERS.contains(&location) {
+            start = usize::from(ram[start]);
+        }
+        let end = start.saturating_add(count).min(ram.len());
+        let mut out = Vec::new();
+        for row in (start..end).step_by(8) {
+            let words = ram[row..(row + 8).min(end)]
+                .iter()
+                .map(|&w| format!("{:>6}", w as i16))
+                .collect::<String>();
+            out.push(format!("RAM[{}]:{}", row, words));
+        }
+        Ok(out.join("\n"))
+    }
+
+    fn info(&self) -> String {
+        let mut out = Vec::new();
+        for &pc in &self.breakpoints {
+            out.push(format!("breakpoint at {}", self.location(pc)));
+        }
+        for &address in self.watches.keys() {
+            out.push(format!("watching {}", self.ram_name(address)));
+        }
+        if out.is_empty() {
+            out.push("no breakpoints or watches".to_string());
+        }
+        out.join("\n")
+    }
+
+    fn current_line(&self) -> Option<(String, usize)> {
+        self.lines
+            .get(usize::from(self.machine.pc()))
+            .map(|l| (l.file.clone(), l.line))
+    }
+
+    /// The ROM address, labels there and source line of the instruction at `pc`
+    fn location(&self, pc: u16) -> String {
+        let labels = self
+            .symbols
+            .iter()
+            .filter(|(_, (addr, kind))| *kind == SymbolKind::Label && *addr == usize::from(pc))
+            .map(|(name, _)| format!(" ({})", name))
+            .collect::<String>();
+        match self.lines.get(usize::from(pc)) {
+            Some(line) => format!(
+                "{}{} {}:{}: {}",
+                pc, labels, line.file, line.line, line.text
+            ),
+            None => format!("{}{} (no source)", pc, labels),
+        }
+    }
+
+    /// The instruction named by a label, a ROM address or `:LINE` in the first input file
+    fn rom_address(&self, target: &str) -> Result<u16, String> {
+        if let Some(line) = target.strip_prefix(':') {
+            let line = line
+                .parse::<usize>()
+                .map_err(|_| format!("`{}` is not a line number", line))?;
+            let file = self.lines.first().map(|l| &l.file);
+            return self
+                .lines
+                .iter()
+                .position(|l| Some(&l.file) == file && l.line >= line)
+                .map(|pc| pc as u16)
+                .ok_or_else(|| format!("no instruction at or after line {}", line));
+        }
+        match (target.parse::<u16>(), self.symbols.get(target)) {
+            (Ok(pc), _) if usize::from(pc) < crate::ROM_SIZE => Ok(pc),
+            (_, Some(&(addr, SymbolKind::Label))) => Ok(addr as u16),
+            (_, Some(_)) => Err(format!("`{}` is not a label", target)),
+            _ => Err(format!(
+                "expected a label, ROM address or `:LINE`, found `{}`",
+                target
+            )),
+        }
+    }
+
+    /// The RAM address named by `RAM[ADDRESS]`, a number or a symbol
+    fn ram_address(&self, location: &str) -> Result<usize, String> {
+        let inner = location
+            .strip_prefix("RAM[")
+            .and_then(|l| l.strip_suffix(']'))
+            .unwrap_or(location)
+            .trim();
+        let address = match (inner.parse::<usize>(), self.symbols.get(inner)) {
+            (Ok(address), _) => address,
+            (_, Some(&(_, SymbolKind::Label))) => {
+                return Err(format!("`{}` is a label in ROM", inner));
+            }
+            (_, Some(&(address, _))) => address,
+            _ => {
+                return Err(format!(
+                    "expected a RAM address or symbol, found `{}`",
+                    location
+                ));
+            }
+        };
+        if address < self.machine.ram().len() {
+            Ok(address)
+        } else {
+            Err(format!("RAM[{}] is past the keyboard register", address))
+        }
+    }
+
+    fn ram_name(&self, address: usize) -> String {
+        ram_name(&self.symbols, address)
+    }
+}
+
+/// `RAM[address]` followed by the name of a symbol for it, preferring variables
+fn ram_name(symbols: &BTreeMap<String, (usize, SymbolKind)>, address: usize) -> String {
+    let name = symbols
+        .iter()
+        .filter(|(_, (addr, kind))| *addr == address && *kind != SymbolKind::Label)
+        .max_by_key(|(name, (_, kind))| (*kind == SymbolKind::Variable, !name.starts_with('R')))
+        .map(|(name, _)| name);
+    match name {
+        Some(name) => format!("RAM[{}] ({})", address, name),
+        None => format!("RAM[{}]", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(source: &str) -> Debugger {
+        let mut asm = Assembler::new(source);
+        asm.set_file_name("Max.asm");
+        Debugger::new(&mut asm).unwrap()
+    }
+
+    #[test]
+    fn test_break_and_step() {
+        let mut d = debugger(include_str!("../../6/asm/Max.asm"));
+        d.machine_mut().ram_mut()[..2].copy_from_slice(&[3, 9]);
+        assert_eq!(
+            d.execute("break OUTPUT_D").unwrap(),
+            "breakpoint at 12 (OUTPUT_D) Max.asm:26: @R2"
+        );
+        assert_eq!(
+            d.execute("continue").unwrap(),
+            "breakpoint\n12 (OUTPUT_D) Max.asm:26: @R2"
+        );
+        assert_eq!(d.execute("print D").unwrap(), "D = 9");
+        assert_eq!(d.execute("step").unwrap(), "13 Max.asm:27: M=D");
+        assert_eq!(d.execute("print M").unwrap(), "M = RAM[2] (ARG) = 0");
+        assert_eq!(
+            d.execute("step 2").unwrap(),
+            "halted\n14 (END) Max.asm:29: @END"
+        );
+        assert_eq!(d.execute("print R2").unwrap(), "RAM[2] (ARG) = 9");
+
+        d.execute("reset").unwrap();
+        assert_eq!(d.execute("next").unwrap(), "1 Max.asm:11: D=M");
+        assert_eq!(
+            d.execute("break :16").unwrap(),
+            "breakpoint at 5 Max.asm:16: D;JGT"
+        );
+        assert_eq!(
+            d.execute("delete 5").unwrap(),
+            "deleted breakpoint at 5 Max.asm:16: D;JGT"
+        );
+        assert!(d.execute("break R2").is_err());
+        assert!(d.execute("jump").is_err());
+    }
+
+    #[test]
+    fn test_step_into_include() {
+        let dir = std::env::temp_dir().join(format!("hack-dbg-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("double.asm"), "(DOUBLE)\nD=D+A\n@END\n0;JMP\n").unwrap();
+        let mut asm = Assembler::new(
+            "@3\nD=A\n@DOUBLE\n0;JMP\n.include \"double.asm\"\n(END)\n@END\n0;JMP\n",
+        );
+        asm.set_file_name("Main.asm");
+        asm.add_include_path(&dir);
+        let mut d = Debugger::new(&mut asm).unwrap();
+        let lib = dir.join("double.asm").display().to_string();
+        assert_eq!(
+            d.execute("break DOUBLE").unwrap(),
+            format!("breakpoint at 4 (DOUBLE) {}:2: D=D+A", lib)
+        );
+        d.execute("continue").unwrap();
+        assert_eq!(d.execute("step").unwrap(), format!("5 {}:3: @END", lib));
+        assert_eq!(
+            d.execute("step 2").unwrap(),
+            "halted\n7 (END) Main.asm:7: @END"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_and_examine() {
+        let mut d =
+            debugger("@261\nD=A\n@SP\nM=D\n@x\nM=-1\n@5\nD=A\n@SP\nA=M\nM=D\n(END)\n@END\n0;JMP\n");
+        assert_eq!(d.execute("watch x").unwrap(), "watching RAM[16] (x) = 0");
+        assert_eq!(
+            d.execute("c").unwrap(),
+            "RAM[16] (x) changed from 0 to -1\n6 Max.asm:7: @5"
+        );
+        d.execute("c").unwrap();
+        assert_eq!(d.execute("print SP").unwrap(), "RAM[0] (SP) = 261");
+        assert_eq!(
+            d.execute("x/10 SP").unwrap(),
+            "RAM[261]:     5     0     0     0     0     0     0     0\nRAM[269]:     0     0"
+        );
+        assert_eq!(d.execute("x/2 RAM[16]").unwrap(), "RAM[16]:    -1     0");
+        assert_eq!(
+            d.execute(&format!("x/{} RAM[24575]", usize::MAX)).unwrap(),
+            "RAM[24575]:     0     0"
+        );
+        assert_eq!(d.execute("info").unwrap(), "watching RAM[16] (x)");
+    }
+}
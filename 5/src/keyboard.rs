@@ -0,0 +1,256 @@
+//! Scripted keyboard input
+//!
+//! A script says when keys go down and up, one event per line or separated by
+//! `;`, with `#` starting a comment:
+//!
+//! ```text
+//! at cycle 1000 press 'A'
+//! at 50000 release
+//! at 60000 press left; at 70000 release
+//! ```
+//!
+//! Keys are a quoted printable character or one of the names in [`KEY_NAMES`],
+//! and pressing one replaces the key held before.
+
+use std::fmt;
+
+use crate::Machine;
+
+/// Names of the keys outside printable ASCII, and their codes in the Hack character set
+pub const KEY_NAMES: &[(&str, u16)] = &[
+    ("newline", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+    ("f1", 141),
+    ("f2", 142),
+    ("f3", 143),
+    ("f4", 144),
+    ("f5", 145),
+    ("f6", 146),
+    ("f7", 147),
+    ("f8", 148),
+    ("f9", 149),
+    ("f10", 150),
+    ("f11", 151),
+    ("f12", 152),
+];
+
+/// The Hack character code of `key`, a quoted character like `'A'` or a key name
+pub fn key_code(key: &str) -> Option<u16> {
+    if let Some(quoted) = key
+        .strip_prefix('\'')
+        .and_then(|k| k.strip_suffix('\''))
+        .or_else(|| key.strip_prefix('"').and_then(|k| k.strip_suffix('"')))
+    {
+        let mut chars = quoted.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c @ ' '..='~'), None) => Some(c as u16),
+            _ => None,
+        };
+    }
+    let key = key.to_ascii_lowercase();
+    KEY_NAMES
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, code)| *code)
+}
+
+/// A problem in a keyboard script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Setting the keyboard register to `key`, 0 for a release, before cycle `cycle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub cycle: u64,
+    pub key: u16,
+}
+
+/// Keyboard events in the order they happen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    events: Vec<Event>,
+    /// Index of the first event not yet applied
+    next: usize,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            for event in split_events(line)
+                .into_iter()
+                .filter(|e| !e.trim().is_empty())
+            {
+                events.push(parse_event(event).map_err(|message| ScriptError {
+                    line: i + 1,
+                    message,
+                })?);
+            }
+        }
+        // events at the same cycle keep the order they were written in
+        events.sort_by_key(|e| e.cycle);
+        Ok(Self { events, next: 0 })
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Run `machine` for `cycles` instructions, setting the keyboard register
+    /// as each event comes due
+    pub fn run_for(&mut self, machine: &mut Machine, cycles: u64) {
+        let end = machine.cycles() + cycles;
+        loop {
+            self.apply(machine);
+            let until = self.events.get(self.next).map_or(end, |e| e.cycle.min(end));
+            machine.run_for(until.saturating_sub(machine.cycles()));
+            if machine.cycles() >= end {
+                return;
+            }
+        }
+    }
+
+    /// Apply the events due at the current cycle of `machine`, for callers
+    /// which step it themselves
+    pub fn apply(&mut self, machine: &mut Machine) {
+        while let Some(event) = self.events.get(self.next)
+            && event.cycle <= machine.cycles()
+        {
+            machine.set_key(event.key);
+            self.next += 1;
+        }
+    }
+}
+
+/// The events on `line`, up to a comment, splitting at `;` outside quotes
+fn split_events(line: &str) -> Vec<&str> {
+    let mut quote = None;
+    let mut end = line.len();
+    let mut splits = vec![0];
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ';') => splits.push(i + 1),
+            (None, '#') => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    splits.push(end + 1);
+    let line = &line[..end];
+    splits.windows(2).map(|w| &line[w[0]..w[1] - 1]).collect()
+}
+
+/// `at [cycle] N press KEY` or `at [cycle] N release`
+fn parse_event(event: &str) -> Result<Event, String> {
+    let mut words = event.split_whitespace().peekable();
+    if words.next() != Some("at") {
+        return Err(format!(
+            "expected `at CYCLE press KEY` or `at CYCLE release`, found `{}`",
+            event.trim()
+        ));
+    }
+    words.next_if_eq(&"cycle");
+    let cycle = words
+        .next()
+        .and_then(|n| n.replace('_', "").parse().ok())
+        .ok_or("expected a cycle number after `at`")?;
+    let key = match words.next() {
+        Some("release") => 0,
+        Some("press") => {
+            // a quoted space is split into two words
+            let key = words.by_ref().collect::<Vec<_>>().join(" ");
+            return key_code(&key)
+                .map(|key| Event { cycle, key })
+                .ok_or_else(|| format!("unknown key `{}`", key));
+        }
+        _ => return Err("expected `press KEY` or `release`".to_string()),
+    };
+    match words.next() {
+        Some(extra) => Err(format!("unexpected `{}` after `release`", extra)),
+        None => Ok(Event { cycle, key }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KBD, SCREEN};
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(
+            "# start\nat cycle 1000 press 'A'; at 50_000 release\nat 10 press ' '\nat 20 press Left\n",
+        )
+        .unwrap();
+        let events = script
+            .events()
+            .iter()
+            .map(|e| (e.cycle, e.key))
+            .collect::<Vec<_>>();
+        assert_eq!(events, vec![(10, 32), (20, 130), (1000, 65), (50_000, 0)]);
+
+        for (source, line, message) in [
+            (
+                "\nwhen 1 press 'A'",
+                2,
+                "expected `at CYCLE press KEY` or `at CYCLE release`, found `when 1 press 'A'`",
+            ),
+            ("at x press 'A'", 1, "expected a cycle number after `at`"),
+            ("at 1 press 'AB'", 1, "unknown key `'AB'`"),
+            ("at 1 hold", 1, "expected `press KEY` or `release`"),
+            ("at 1 release 'A'", 1, "unexpected `'A'` after `release`"),
+            (
+                "at 1 press ';' # ok\nat 2 press '#'; at",
+                2,
+                "expected a cycle number after `at`",
+            ),
+        ] {
+            let error = Script::parse(source).unwrap_err();
+            assert_eq!((error.line, error.message.as_str()), (line, message));
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut machine = Machine::new();
+        let words = assembler::Assembler::new(include_str!("../../4/Fill.asm"))
+            .assemble()
+            .unwrap();
+        machine.load_rom(&words);
+        let mut script = Script::parse("at 1000 press 'A'\nat 300000 release\n").unwrap();
+
+        script.run_for(&mut machine, 300_000);
+        assert_eq!(machine.ram()[KBD], 65);
+        assert!(machine.ram()[SCREEN..KBD].iter().all(|&w| w == 0xffff));
+
+        script.run_for(&mut machine, 300_000);
+        assert_eq!(machine.ram()[KBD], 0);
+        assert!(machine.ram()[SCREEN..KBD].iter().all(|&w| w == 0));
+    }
+}
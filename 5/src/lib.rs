@@ -0,0 +1,346 @@
+//! An emulator of the Hack computer built in this project
+//!
+//! The CPU follows `CPU.hdl` and the data memory follows `Memory.hdl`: 16K words
+//! of RAM, the 8K word screen memory map at `SCREEN` and the keyboard register
+//! at `KBD`. Reads past `KBD` give 0 and writes there, or to `KBD`, are ignored.
+
+pub mod debugger;
+pub mod keyboard;
+pub mod profile;
+pub mod screen;
+pub mod trace;
+
+/// Words of instruction memory
+pub const ROM_SIZE: usize = 0x8000;
+/// Address of the screen memory map, 32 words for each of its 256 rows
+pub const SCREEN: usize = 0x4000;
+/// Address of the keyboard register, holding the code of the key pressed or 0
+pub const KBD: usize = 0x6000;
+
+/// A Hack computer with a program in its ROM
+#[derive(Debug, Clone)]
+pub struct Machine {
+    rom: Vec<u16>,
+    /// RAM, the screen memory map and the keyboard register
+    ram: Vec<u16>,
+    a: u16,
+    d: u16,
+    pc: u16,
+    cycles: u64,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    /// A machine with an empty ROM and cleared RAM
+    pub fn new() -> Self {
+        Self {
+            rom: vec![0; ROM_SIZE],
+            ram: vec![0; KBD + 1],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Replace the program with `words`, clearing the rest of the ROM, and reset
+    ///
+    /// # Panics
+    ///
+    /// If `words` does not fit in the ROM.
+    pub fn load_rom(&mut self, words: &[u16]) {
+        assert!(
+            words.len() <= ROM_SIZE,
+            "{} words do not fit in the {} words of ROM",
+            words.len(),
+            ROM_SIZE
+        );
+        self.rom[..words.len()].copy_from_slice(words);
+        self.rom[words.len()..].fill(0);
+        self.reset();
+    }
+
+    /// A machine running the assembled `source`, with the RAM words in `ram`
+    /// set as a test script sets them before it runs
+    pub fn with_program(source: &str, ram: &[(usize, i16)]) -> Result<Self, assembler::Error> {
+        let mut machine = Self::new();
+        machine.load_rom(&assembler::Assembler::new(source).assemble()?);
+        for &(address, value) in ram {
+            machine.ram[address] = value as u16;
+        }
+        Ok(machine)
+    }
+
+    /// Start the program again from address 0, as the `reset` input of the CPU
+    ///
+    /// Registers and RAM keep their values.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    /// Execute the instruction at the program counter
+    pub fn step(&mut self) {
+        let instruction = self.rom[usize::from(self.pc) % ROM_SIZE];
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1) & 0x7fff;
+            return;
+        }
+
+        let y = if instruction & 0x1000 != 0 {
+            self.read(self.a)
+        } else {
+            self.a
+        };
+        // the prefix `101` marks a shift of the extended instruction set
+        let (out, jump) = if instruction & 0x6000 == 0x2000 {
+            let value = if instruction & 0x0400 != 0 { self.d } else { y };
+            let out = if instruction & 0x0800 != 0 {
+                value << 1
+            } else {
+                ((value as i16) >> 1) as u16
+            };
+            (out, instruction & 0x7)
+        } else {
+            (alu(self.d, y, instruction >> 6), instruction & 0x7)
+        };
+
+        // `M` is written at the address held in `A` before this instruction
+        if instruction & 0x08 != 0 {
+            self.write(self.a, out);
+        }
+        if instruction & 0x10 != 0 {
+            self.d = out;
+        }
+        let target = self.a;
+        if instruction & 0x20 != 0 {
+            self.a = out;
+        }
+
+        let out = out as i16;
+        let taken = (jump & 0b100 != 0 && out < 0)
+            || (jump & 0b010 != 0 && out == 0)
+            || (jump & 0b001 != 0 && out > 0);
+        self.pc = if taken {
+            target & 0x7fff
+        } else {
+            self.pc.wrapping_add(1) & 0x7fff
+        };
+    }
+
+    /// Execute `cycles` instructions
+    pub fn run_for(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    /// Run until the program reaches an `@X` / `0;JMP` loop to itself, the usual
+    /// end of a Hack program, returning the cycles taken or `None` after `max_cycles`
+    pub fn run_until_halt(&mut self, max_cycles: u64) -> Option<u64> {
+        let start = self.cycles;
+        while self.cycles - start < max_cycles {
+            if self.halted() {
+                return Some(self.cycles - start);
+            }
+            self.step();
+        }
+        None
+    }
+
+    /// Whether the next instructions are `@pc` then an unconditional jump
+    pub fn halted(&self) -> bool {
+        let pc = usize::from(self.pc);
+        let next = self.rom[(pc + 1) % ROM_SIZE];
+        // no dest, and a jump on every result of the ALU
+        self.rom[pc] == self.pc && next & 0xe03f == 0xe007
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    /// RAM followed by the screen memory map and the keyboard register
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    /// Address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_a(&mut self, a: u16) {
+        self.a = a;
+    }
+
+    pub fn set_d(&mut self, d: u16) {
+        self.d = d;
+    }
+
+    /// Instructions executed since the machine was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Set the keyboard register to the code of the key pressed, or 0 for none
+    ///
+    /// [`keyboard::Script`] sets it at given cycles.
+    pub fn set_key(&mut self, key: u16) {
+        self.ram[KBD] = key;
+    }
+
+    fn read(&self, address: u16) -> u16 {
+        self.ram.get(usize::from(address)).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        let address = usize::from(address);
+        if address < KBD {
+            self.ram[address] = value;
+        }
+    }
+}
+
+/// The Hack ALU with the 6 control bits `zx nx zy ny f no` as the low bits of `control`
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & (1 << n) != 0;
+    let x = if bit(5) { 0 } else { x };
+    let x = if bit(4) { !x } else { x };
+    let y = if bit(3) { 0 } else { y };
+    let y = if bit(2) { !y } else { y };
+    let out = if bit(1) { x.wrapping_add(y) } else { x & y };
+    if bit(0) { !out } else { out }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_rom(&Assembler::new(source).assemble().unwrap());
+        machine
+    }
+
+    fn hack(source: &str) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_rom(&assembler::disasm::read_hack("<fixture>", source).unwrap());
+        machine
+    }
+
+    #[test]
+    fn test_comps() {
+        let comps = [
+            ("0", 0),
+            ("1", 1),
+            ("-1", 0xffff),
+            ("D", 5),
+            ("A", 7),
+            ("!D", !5),
+            ("-A", 7u16.wrapping_neg()),
+            ("D+1", 6),
+            ("A-1", 6),
+            ("D+A", 12),
+            ("D-A", 5u16.wrapping_sub(7)),
+            ("A-D", 2),
+            ("D&A", 5),
+            ("D|A", 7),
+            ("M", 9),
+            ("D+M", 14),
+            ("M-D", 4),
+        ];
+        for (comp, expected) in comps {
+            let mut m = machine(&format!("@9\nD=A\n@7\nM=D\n@5\nD=A\n@7\nD={}\n", comp));
+            m.run_for(8);
+            assert_eq!(m.d(), expected, "D={}", comp);
+        }
+    }
+
+    #[test]
+    fn test_shifts() {
+        let mut asm = Assembler::new("@3\nD=A\nD=D<<\nA=-1\nA=A>>\nM=D<<\n");
+        asm.set_isa(assembler::Isa::Extended);
+        let mut m = Machine::new();
+        m.load_rom(&asm.assemble().unwrap());
+        m.run_for(6);
+        assert_eq!((m.d(), m.a()), (6, 0xffff));
+        assert_eq!(m.ram()[KBD], 0, "writes past the screen are ignored");
+    }
+
+    #[test]
+    fn test_jumps() {
+        // count down from 3, storing each value at 100 + value
+        let mut m = machine("@3\nD=A\n(LOOP)\n@100\nA=D+A\nM=D\nD=D-1\n@LOOP\nD;JGT\n");
+        m.run_for(2 + 3 * 6);
+        assert_eq!(&m.ram()[100..104], &[0, 1, 2, 3]);
+        assert_eq!(m.pc(), 8);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        // Add has no final loop
+        let mut m = hack(include_str!("../../6/asm/Add.hack"));
+        m.run_for(6);
+        assert_eq!(m.ram()[0], 5);
+
+        for source in [
+            include_str!("../../6/asm/Max.hack"),
+            include_str!("../../6/asm/MaxL.hack"),
+        ] {
+            let mut m = hack(source);
+            m.ram_mut()[..2].copy_from_slice(&[12, 30]);
+            m.run_until_halt(1000).unwrap();
+            assert_eq!(m.ram()[2], 30);
+        }
+
+        for source in [
+            include_str!("../../6/asm/Rect.hack"),
+            include_str!("../../6/asm/RectL.hack"),
+        ] {
+            let mut m = hack(source);
+            m.ram_mut()[0] = 4;
+            m.run_until_halt(1000).unwrap();
+            let column = (0..6)
+                .map(|row| m.ram()[SCREEN + 32 * row])
+                .collect::<Vec<_>>();
+            assert_eq!(column, vec![0xffff, 0xffff, 0xffff, 0xffff, 0, 0]);
+        }
+
+        // Pong never halts, but draws the paddle and ball before waiting for keys
+        let mut m = hack(include_str!("../../6/asm/Pong.hack"));
+        m.run_for(5_000_000);
+        assert!(m.ram()[SCREEN..KBD].iter().any(|&w| w != 0));
+    }
+
+    #[test]
+    fn test_mult() {
+        let mut m = machine(include_str!("../../4/Mult.asm"));
+        m.ram_mut()[..2].copy_from_slice(&[6, 7]);
+        m.run_until_halt(10_000).unwrap();
+        assert_eq!(m.ram()[2], 42);
+    }
+}
@@ -0,0 +1,394 @@
+//! Where a program spends its instructions
+//!
+//! Besides counting each ROM address, the profiler follows the calls of VM
+//! translator output. A call loads the address of its return label, named like
+//! `Main.main.RET_0`, `Main.main$ret.0` or `RET_ADDRESS_CALL0`, and then jumps
+//! to a function label; jumping back to a return label of an open call returns
+//! from it and from any call made since.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+
+use crate::{Machine, ROM_SIZE};
+
+/// Whether `name` is the label a call returns to
+pub fn is_return_label(name: &str) -> bool {
+    name.contains(".RET_") || name.contains("$ret.") || name.starts_with("RET_ADDRESS_CALL")
+}
+
+/// A function in the call tree, reached through the calls of its ancestors
+struct Node {
+    /// Address of the function label, or 0 for the program outside any call
+    function: u16,
+    parent: usize,
+    children: HashMap<u16, usize>,
+    cycles: u64,
+}
+
+pub struct Profiler {
+    rom: Vec<u16>,
+    /// Labels at each ROM address
+    labels: BTreeMap<u16, Vec<String>>,
+    /// Addresses of return labels
+    returns: BTreeSet<u16>,
+    /// First instruction of each basic block
+    leaders: BTreeSet<u16>,
+    /// Times the instruction at each ROM address ran
+    counts: Vec<u64>,
+    /// Calls of each function label
+    calls: BTreeMap<u16, u64>,
+    /// Returns to each return label
+    returned: BTreeMap<u16, u64>,
+    /// The call tree, with the program outside any call first
+    nodes: Vec<Node>,
+    /// Return address and call tree node of each open call
+    stack: Vec<(u16, usize)>,
+    /// Return address loaded for a call not yet made
+    pending: Option<u16>,
+}
+
+impl Profiler {
+    /// A profiler for the program `rom` with the given labels
+    pub fn new<'a>(rom: &[u16], labels: impl IntoIterator<Item = (u16, &'a str)>) -> Self {
+        let mut by_address = BTreeMap::<u16, Vec<String>>::new();
+        for (addr, name) in labels {
+            by_address.entry(addr).or_default().push(name.to_string());
+        }
+        let returns = by_address
+            .iter()
+            .filter(|(_, names)| names.iter().any(|n| is_return_label(n)))
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        // blocks start at labels, jump targets and after jumps
+        let mut leaders = by_address.keys().copied().collect::<BTreeSet<_>>();
+        leaders.insert(0);
+        for (i, pair) in rom.windows(2).enumerate() {
+            if is_jump(pair[1]) && pair[0] & 0x8000 == 0 {
+                leaders.insert(pair[0]);
+            }
+            if is_jump(pair[0]) {
+                leaders.insert(i as u16 + 1);
+            }
+        }
+
+        Self {
+            rom: rom.to_vec(),
+            labels: by_address,
+            returns,
+            leaders,
+            counts: vec![0; ROM_SIZE],
+            calls: BTreeMap::new(),
+            returned: BTreeMap::new(),
+            nodes: vec![Node {
+                function: 0,
+                parent: 0,
+                children: HashMap::new(),
+                cycles: 0,
+            }],
+            stack: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Execute one instruction of `machine`, which runs the program given to [`Profiler::new`]
+    pub fn step(&mut self, machine: &mut Machine) {
+        let pc = machine.pc();
+        let word = self.rom.get(usize::from(pc)).copied().unwrap_or(0);
+        self.counts[usize::from(pc)] += 1;
+        let current = self.stack.last().map_or(0, |&(_, node)| node);
+        self.nodes[current].cycles += 1;
+        if word & 0x8000 == 0 && self.returns.contains(&word) {
+            self.pending = Some(word);
+        }
+
+        machine.step();
+        let next = machine.pc();
+        if next == pc.wrapping_add(1) {
+            return;
+        }
+        // a label loaded as a value may be at the address of a return label,
+        // but a call never jumps to its own return label
+        if let Some(ret) = self.pending
+            && ret != next
+            && self.is_function(next)
+        {
+            self.pending = None;
+            *self.calls.entry(next).or_default() += 1;
+            let node = match self.nodes[current].children.get(&next) {
+                Some(&node) => node,
+                None => {
+                    self.nodes.push(Node {
+                        function: next,
+                        parent: current,
+                        children: HashMap::new(),
+                        cycles: 0,
+                    });
+                    let node = self.nodes.len() - 1;
+                    self.nodes[current].children.insert(next, node);
+                    node
+                }
+            };
+            self.stack.push((ret, node));
+        } else if let Some(i) = self.stack.iter().rposition(|&(ret, _)| ret == next) {
+            *self.returned.entry(next).or_default() += 1;
+            self.stack.truncate(i);
+        }
+    }
+
+    pub fn run_for(&mut self, machine: &mut Machine, cycles: u64) {
+        for _ in 0..cycles {
+            self.step(machine);
+        }
+    }
+
+    /// Instructions executed while profiling
+    pub fn total(&self) -> u64 {
+        self.nodes.iter().map(|n| n.cycles).sum()
+    }
+
+    /// Whether a label other than a return label is at `addr`
+    fn is_function(&self, addr: u16) -> bool {
+        self.labels
+            .get(&addr)
+            .is_some_and(|names| names.iter().any(|n| !is_return_label(n)))
+    }
+
+    /// The label naming `addr`, preferring one which is not a return label
+    fn label(&self, addr: u16) -> Option<&str> {
+        let names = self.labels.get(&addr)?;
+        names
+            .iter()
+            .find(|n| !is_return_label(n))
+            .or(names.first())
+            .map(String::as_str)
+    }
+
+    /// `addr` as the closest label at or before it and an offset
+    fn name(&self, addr: u16) -> String {
+        match self.labels.range(..=addr).next_back() {
+            Some((&start, _)) if start == addr => self.label(start).unwrap().to_string(),
+            Some((&start, _)) => format!("{}+{}", self.label(start).unwrap(), addr - start),
+            None => format!("{}", addr),
+        }
+    }
+
+    fn frame_name(&self, node: usize) -> String {
+        match node {
+            0 => self.label(0).unwrap_or("(start)").to_string(),
+            _ => self.name(self.nodes[node].function),
+        }
+    }
+
+    /// Instructions executed after each label before the next one, most first
+    pub fn by_label(&self) -> Vec<(String, u64)> {
+        let mut regions = BTreeMap::<u16, u64>::new();
+        for (addr, &count) in self.counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let start = self
+                .labels
+                .range(..=addr as u16)
+                .next_back()
+                .map_or(0, |(&start, _)| start);
+            *regions.entry(start).or_default() += count;
+        }
+        let mut regions = regions
+            .into_iter()
+            .map(|(start, count)| (self.label(start).unwrap_or("(start)").to_string(), count))
+            .collect::<Vec<_>>();
+        regions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        regions
+    }
+
+    /// Start address, times entered and instructions executed of each basic
+    /// block which ran, most instructions first
+    pub fn blocks(&self) -> Vec<(u16, u64, u64)> {
+        let mut blocks = Vec::new();
+        let mut leaders = self.leaders.iter().copied().peekable();
+        while let Some(start) = leaders.next() {
+            let end = leaders.peek().map_or(ROM_SIZE, |&e| usize::from(e));
+            let cycles = self.counts[usize::from(start)..end].iter().sum::<u64>();
+            if cycles > 0 {
+                blocks.push((start, self.counts[usize::from(start)], cycles));
+            }
+        }
+        blocks.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        blocks
+    }
+
+    /// Calls of each function, most first
+    pub fn calls(&self) -> Vec<(String, u64)> {
+        let mut calls = self
+            .calls
+            .iter()
+            .map(|(&addr, &n)| (self.name(addr), n))
+            .collect::<Vec<_>>();
+        calls.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        calls
+    }
+
+    /// Returns to each return label, most first
+    pub fn returns(&self) -> Vec<(String, u64)> {
+        let mut returns = self
+            .returned
+            .iter()
+            .map(|(&addr, &n)| {
+                let names = &self.labels[&addr];
+                let name = names.iter().find(|n| is_return_label(n)).unwrap();
+                (name.clone(), n)
+            })
+            .collect::<Vec<_>>();
+        returns.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        returns
+    }
+
+    /// The `top` entries of each table as text
+    pub fn report(&self, top: usize) -> String {
+        let total = self.total().max(1);
+        let mut out = format!("{} instructions\n", self.total());
+        out.push_str("\ninstructions by label\n");
+        for (label, count) in self.by_label().into_iter().take(top) {
+            let share = count as f64 * 100.0 / total as f64;
+            out.push_str(&format!("{:>12} {:>6.2}%  {}\n", count, share, label));
+        }
+        out.push_str("\nhottest basic blocks\n");
+        for (start, entries, cycles) in self.blocks().into_iter().take(top) {
+            out.push_str(&format!(
+                "{:>12} {:>10}x  {:>5}  {}\n",
+                cycles,
+                entries,
+                start,
+                self.name(start)
+            ));
+        }
+        out.push_str("\ncalls\n");
+        for (function, count) in self.calls().into_iter().take(top) {
+            out.push_str(&format!("{:>12}  {}\n", count, function));
+        }
+        out.push_str("\nreturns\n");
+        for (label, count) in self.returns().into_iter().take(top) {
+            out.push_str(&format!("{:>12}  {}\n", count, label));
+        }
+        out
+    }
+
+    /// Write the call stacks in the folded format of flamegraph tools, one
+    /// `outer;inner COUNT` line for the instructions run with each stack
+    pub fn write_folded(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut frames = vec![self.frame_name(i)];
+            let mut n = i;
+            while n != 0 {
+                n = self.nodes[n].parent;
+                frames.push(self.frame_name(n));
+            }
+            frames.reverse();
+            lines.push(format!("{} {}", frames.join(";"), node.cycles));
+        }
+        lines.sort();
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `word` is a C-instruction which may jump
+fn is_jump(word: u16) -> bool {
+    word & 0x8000 != 0 && word & 0x7 != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::{Assembler, SymbolKind};
+
+    fn profile(source: &str, cycles: u64) -> Profiler {
+        let mut asm = Assembler::new(source);
+        let words = asm.assemble().unwrap();
+        let mut machine = Machine::new();
+        machine.load_rom(&words);
+        let symbols = asm.symbols().unwrap();
+        let labels = symbols
+            .iter()
+            .filter(|(_, _, kind)| *kind == SymbolKind::Label)
+            .map(|(name, addr, _)| (*addr as u16, name.as_str()));
+        let mut profiler = Profiler::new(&words, labels);
+        profiler.run_for(&mut machine, cycles);
+        profiler
+    }
+
+    #[test]
+    fn test_calls() {
+        // Main.main calls Main.f twice, as the VM translator writes it
+        let source = "\
+(Main.main)
+@Main.main.RET_0
+D=A
+@Main.f
+0;JMP
+(Main.main.RET_0)
+@Main.main.RET_1
+D=A
+@Main.f
+0;JMP
+(Main.main.RET_1)
+(END)
+@END
+0;JMP
+(Main.f)
+@R13
+M=D
+(Main.f.LOOP)
+@R13
+A=M
+0;JMP
+";
+        let profiler = profile(source, 22);
+        assert_eq!(profiler.total(), 22);
+        assert_eq!(profiler.calls(), vec![("Main.f".to_string(), 2)]);
+        assert_eq!(
+            profiler.returns(),
+            vec![
+                ("Main.main.RET_0".to_string(), 1),
+                ("Main.main.RET_1".to_string(), 1)
+            ]
+        );
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "Main.main 12\nMain.main;Main.f 10\n"
+        );
+        assert_eq!(
+            profiler.by_label()[..3],
+            [
+                ("Main.f.LOOP".to_string(), 6),
+                ("END".to_string(), 4),
+                ("Main.f".to_string(), 4)
+            ]
+        );
+        assert_eq!(profiler.blocks()[0], (12, 2, 6));
+    }
+
+    #[test]
+    fn test_pong() {
+        let profiler = profile(include_str!("../../6/asm/Pong.asm"), 1_000_000);
+        let calls = profiler.calls();
+        assert!(calls.contains(&("sys.init".to_string(), 1)));
+        assert!(calls.contains(&("output.init".to_string(), 1)));
+        assert!(calls.contains(&("math.multiply".to_string(), 121)));
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.contains("\n(start);sys.init;output.init;output.initmap;output.create "));
+        let total = folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+            .sum::<u64>();
+        assert_eq!(total, 1_000_000);
+    }
+}
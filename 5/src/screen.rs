@@ -0,0 +1,186 @@
+//! Images of the screen memory map
+//!
+//! The screen has 256 rows of 512 pixels, each row 32 words, with the lowest
+//! bit of a word the leftmost of its 16 pixels and a set bit black.
+
+use crate::{KBD, SCREEN};
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+/// Image format of a screen capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pbm,
+    Png,
+}
+
+impl Format {
+    /// The format named by the extension of `path`
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "pbm" => Some(Self::Pbm),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Pbm => "pbm",
+            Self::Png => "png",
+        }
+    }
+
+    /// An image of the screen in `ram`, which starts at address 0
+    pub fn encode(self, ram: &[u16]) -> Vec<u8> {
+        match self {
+            Self::Pbm => pbm(ram),
+            Self::Png => png(ram),
+        }
+    }
+}
+
+/// Each row of the screen packed 8 pixels to a byte, leftmost in the highest
+/// bit, with black as 1
+fn rows(ram: &[u16]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    ram[SCREEN..KBD].chunks(WIDTH / 16).map(|row| {
+        row.iter()
+            .flat_map(|word| {
+                let [low, high] = word.to_le_bytes();
+                [low.reverse_bits(), high.reverse_bits()]
+            })
+            .collect()
+    })
+}
+
+/// The screen as a binary PBM (`P4`) image
+pub fn pbm(ram: &[u16]) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    rows(ram).for_each(|row| out.extend(row));
+    out
+}
+
+/// The screen as a 1-bit grayscale PNG image
+pub fn png(ram: &[u16]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // bit depth 1, grayscale, default compression, filter and no interlace
+    header.extend([1, 0, 0, 0, 0]);
+
+    // in grayscale 1 is white, and each row starts with filter type 0
+    let mut pixels = Vec::with_capacity(HEIGHT * (1 + WIDTH / 8));
+    for row in rows(ram) {
+        pixels.push(0);
+        pixels.extend(row.iter().map(|b| !b));
+    }
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib_stored(&pixels));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(u8::from(blocks.peek().is_none()));
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    fn run_hack(source: &str, cycles: u64, r0: u16) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_rom(&assembler::disasm::read_hack("<fixture>", source).unwrap());
+        machine.ram_mut()[0] = r0;
+        machine.run_for(cycles);
+        machine
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut ram = vec![0; KBD + 1];
+        ram[SCREEN] = 0b1000_0000_0000_0011;
+        let image = pbm(&ram);
+        let data = &image[b"P4\n512 256\n".len()..];
+        assert_eq!(data.len(), WIDTH * HEIGHT / 8);
+        assert_eq!(&data[..3], &[0b1100_0000, 0b0000_0001, 0]);
+    }
+
+    #[test]
+    fn test_png() {
+        let ram = vec![0; KBD + 1];
+        let image = png(&ram);
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&image[image.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+        let data = vec![7; 0x10000 + 3];
+        let stream = zlib_stored(&data);
+        assert_eq!(&stream[2..7], &[0, 0xff, 0xff, 0, 0]);
+        assert_eq!(stream.len(), 2 + 5 + 0xffff + 5 + 4 + 4);
+    }
+
+    /// The golden images are the output of
+    /// `hackrun --cycles N --screen golden/X.pbm --ram 0=R0 ../6/asm/X.hack`
+    #[test]
+    fn test_golden() {
+        let rect = run_hack(include_str!("../../6/asm/Rect.hack"), 10_000, 50);
+        assert!(pbm(rect.ram()) == include_bytes!("../golden/Rect.pbm"));
+
+        let pong = run_hack(include_str!("../../6/asm/Pong.hack"), 5_000_000, 0);
+        assert!(pbm(pong.ram()) == include_bytes!("../golden/Pong.pbm"));
+    }
+}
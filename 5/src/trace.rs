@@ -0,0 +1,296 @@
+//! Recording every instruction a program executes
+//!
+//! A trace file starts with `HTRC` and a version byte, followed by one record
+//! per instruction. A record is a flags byte and then, each only when its flag
+//! is set and in this order, little-endian words for the PC if it did not
+//! follow on from the last instruction, the new `A`, the new `D`, the address
+//! and value of a RAM write, and the keyboard register if it changed before
+//! the instruction.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::{KBD, Machine};
+
+const MAGIC: &[u8; 4] = b"HTRC";
+const VERSION: u8 = 1;
+
+const JUMPED: u8 = 0x01;
+const SET_A: u8 = 0x02;
+const SET_D: u8 = 0x04;
+const WRITE: u8 = 0x08;
+const KEY: u8 = 0x10;
+
+/// One executed instruction and the state it left
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Step {
+    /// Address of the instruction
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    /// RAM address and value written
+    pub write: Option<(u16, u16)>,
+    /// Keyboard register while the instruction ran
+    pub key: u16,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pc {} A={} D={}", self.pc, self.a as i16, self.d as i16)?;
+        if let Some((address, value)) = self.write {
+            write!(f, " RAM[{}]={}", address, value as i16)?;
+        }
+        if self.key != 0 {
+            write!(f, " key {}", self.key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the trace of a machine as it steps
+pub struct Recorder<W> {
+    writer: W,
+    last: Step,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self {
+            writer,
+            // the first instruction is recorded as following on from `pc` -1
+            last: Step {
+                pc: u16::MAX,
+                ..Step::default()
+            },
+        })
+    }
+
+    /// Execute one instruction of `machine` and record it
+    pub fn step(&mut self, machine: &mut Machine) -> io::Result<()> {
+        let pc = machine.pc();
+        let target = machine.a();
+        let word = machine.rom()[usize::from(pc)];
+        let key = machine.ram()[KBD];
+        machine.step();
+
+        // a C-instruction with `M` in its dest writes where `A` pointed before it
+        let write = (word & 0x8008 == 0x8008 && usize::from(target) < KBD)
+            .then(|| (target, machine.ram()[usize::from(target)]));
+        let step = Step {
+            pc,
+            a: machine.a(),
+            d: machine.d(),
+            write,
+            key,
+        };
+        self.write_step(step)?;
+        self.last = step;
+        Ok(())
+    }
+
+    fn write_step(&mut self, step: Step) -> io::Result<()> {
+        let last = self.last;
+        let mut record = vec![0];
+        let mut push = |flag: u8, words: &[u16]| {
+            record[0] |= flag;
+            for w in words {
+                record.extend(w.to_le_bytes());
+            }
+        };
+        if step.pc != last.pc.wrapping_add(1) {
+            push(JUMPED, &[step.pc]);
+        }
+        if step.a != last.a {
+            push(SET_A, &[step.a]);
+        }
+        if step.d != last.d {
+            push(SET_D, &[step.d]);
+        }
+        if let Some((address, value)) = step.write {
+            push(WRITE, &[address, value]);
+        }
+        if step.key != last.key {
+            push(KEY, &[step.key]);
+        }
+        self.writer.write_all(&record)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// The steps of a trace file
+pub struct Reader<R> {
+    reader: R,
+    last: Step,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a version 1 Hack trace",
+            ));
+        }
+        Ok(Self {
+            reader,
+            last: Step {
+                pc: u16::MAX,
+                ..Step::default()
+            },
+        })
+    }
+
+    fn word(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn read_step(&mut self) -> io::Result<Option<Step>> {
+        let mut flags = [0];
+        if self.reader.read(&mut flags)? == 0 {
+            return Ok(None);
+        }
+        let flags = flags[0];
+        let last = self.last;
+        let mut step = Step {
+            pc: last.pc.wrapping_add(1),
+            write: None,
+            ..last
+        };
+        if flags & JUMPED != 0 {
+            step.pc = self.word()?;
+        }
+        if flags & SET_A != 0 {
+            step.a = self.word()?;
+        }
+        if flags & SET_D != 0 {
+            step.d = self.word()?;
+        }
+        if flags & WRITE != 0 {
+            step.write = Some((self.word()?, self.word()?));
+        }
+        if flags & KEY != 0 {
+            step.key = self.word()?;
+        }
+        self.last = step;
+        Ok(Some(step))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Step>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_step().transpose()
+    }
+}
+
+/// Where two traces first differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of steps before the first which differs
+    pub index: u64,
+    /// The step before, which both traces share
+    pub common: Option<Step>,
+    /// The differing steps, with `None` where a trace ended
+    pub left: Option<Step>,
+    pub right: Option<Step>,
+}
+
+/// The first step where `left` and `right` differ, or `None` if they are the same
+pub fn diff(
+    left: impl IntoIterator<Item = io::Result<Step>>,
+    right: impl IntoIterator<Item = io::Result<Step>>,
+) -> io::Result<Option<Divergence>> {
+    let (mut left, mut right) = (left.into_iter(), right.into_iter());
+    let mut common = None;
+    for index in 0.. {
+        let (l, r) = (left.next().transpose()?, right.next().transpose()?);
+        if l != r {
+            return Ok(Some(Divergence {
+                index,
+                common,
+                left: l,
+                right: r,
+            }));
+        }
+        if l.is_none() {
+            break;
+        }
+        common = l;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn record(source: &str, r0: u16, cycles: u64) -> Vec<u8> {
+        let mut machine = Machine::new();
+        machine.load_rom(&Assembler::new(source).assemble().unwrap());
+        machine.ram_mut()[0] = r0;
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        for _ in 0..cycles {
+            recorder.step(&mut machine).unwrap();
+        }
+        recorder.into_inner()
+    }
+
+    fn read(trace: &[u8]) -> Reader<&[u8]> {
+        Reader::new(trace).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let trace = record("@5\nD=A\n@x\nM=D\n(END)\n@END\n0;JMP\n", 0, 7);
+        let steps = read(&trace).collect::<io::Result<Vec<_>>>().unwrap();
+        let expected = [
+            (0, 5, 0, None),
+            (1, 5, 5, None),
+            (2, 16, 5, None),
+            (3, 16, 5, Some((16, 5))),
+        ];
+        for (step, (pc, a, d, write)) in steps.iter().zip(expected) {
+            assert_eq!(
+                *step,
+                Step {
+                    pc,
+                    a,
+                    d,
+                    write,
+                    key: 0
+                }
+            );
+        }
+        assert_eq!(steps[6].pc, 4);
+        // a flags byte and only what changed
+        assert_eq!(&trace[5..11], &[SET_A, 5, 0, SET_D, 5, 0]);
+        assert!(Reader::new(&b"HTRC\x02"[..]).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let source = include_str!("../../6/asm/Rect.asm");
+        let (four, five) = (record(source, 4, 200), record(source, 5, 200));
+        assert_eq!(diff(read(&four), read(&four)).unwrap(), None);
+
+        let divergence = diff(read(&four), read(&five)).unwrap().unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.left.unwrap().d, 4);
+        assert_eq!(divergence.right.unwrap().d, 5);
+
+        let short = record(source, 4, 150);
+        let divergence = diff(read(&four), read(&short)).unwrap().unwrap();
+        assert_eq!((divergence.index, divergence.right), (150, None));
+    }
+}
@@ -0,0 +1,152 @@
+//! Time the streaming assembler against the whole-program one and against the
+//! line scanner the assembler used before the AST parser, on `Pong.asm` and
+//! on its first quarter and half to show how each grows with the source
+//!
+//! The old scanner copies the source once and only rescans runs of comment and
+//! blank lines, so it was linear, not quadratic, and it stays several times
+//! faster than either assembler here, as it neither tokenizes nor checks nor
+//! encodes. The streaming assembler is a little faster than the whole-program
+//! one; what it saves is memory, since only the encoded words are kept.
+//!
+//! Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use assembler::memory::MemoryMap;
+use assembler::{Assembler, Isa, stream};
+
+const PONG: &str = include_str!("../asm/Pong.asm");
+
+/// Average time of `f` over enough runs to take about a second
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+fn main() {
+    let all = PONG.lines().collect::<Vec<_>>();
+    for part in [4, 2, 1] {
+        let lines = all.len() / part;
+        let source = all[..lines].join("\n");
+        let old = time(|| {
+            legacy::assemble(&source);
+        });
+        let whole = time(|| {
+            Assembler::new(&source).assemble().unwrap();
+        });
+        let streamed = time(|| {
+            let map = MemoryMap::default();
+            stream::assemble("Pong.asm", source.as_bytes(), Isa::Standard, &map).unwrap();
+        });
+        println!(
+            "{:>7} lines: old scanner {:>10.2?}, whole program {:>10.2?}, streaming {:>10.2?}",
+            lines, old, whole, streamed
+        );
+    }
+}
+
+/// The parser of the first version of the assembler, driven through the same
+/// two passes, with the words looked up but not encoded
+mod legacy {
+    use std::collections::HashMap;
+    use std::hint::black_box;
+
+    struct Parser {
+        source: String,
+        pos: usize,
+        instruction_count: usize,
+    }
+
+    impl Parser {
+        fn new(source: &str) -> Self {
+            Self {
+                source: source.to_owned(),
+                pos: 0,
+                instruction_count: 0,
+            }
+        }
+
+        fn has_more_lines(&self) -> bool {
+            let skip = if self.instruction_count > 0 { 1 } else { 0 };
+            self.rest()
+                .split('\n')
+                .skip(skip)
+                .map(|line| line.trim_start_matches(' '))
+                .any(|line| !line.starts_with("//") && !line.is_empty())
+        }
+
+        fn advance(&mut self) {
+            let mut has_before = false;
+            loop {
+                let line = self.peek_line();
+                let trimmed = line.trim_start_matches(' ');
+                if trimmed.starts_with("//") || trimmed.is_empty() {
+                    self.pos += line.len() + 1;
+                    has_before = true;
+                    continue;
+                }
+                if self.instruction_count > 0 && !has_before {
+                    self.pos += line.len() + 1;
+                }
+                let next = self.peek_line().trim_start_matches(' ');
+                if !next.starts_with("//") && !next.is_empty() {
+                    break;
+                }
+            }
+            if !self.is_label() {
+                self.instruction_count += 1;
+            }
+        }
+
+        fn is_label(&self) -> bool {
+            self.line().starts_with('(')
+        }
+
+        fn line(&self) -> &str {
+            self.peek_line().trim_start_matches(' ')
+        }
+
+        fn rest(&self) -> &str {
+            self.source.get(self.pos..).unwrap_or("")
+        }
+
+        fn peek_line(&self) -> &str {
+            let rest = self.rest();
+            rest.find('\n').map_or(rest, |end| &rest[..end])
+        }
+    }
+
+    pub fn assemble(source: &str) {
+        let mut parser = Parser::new(source);
+        let mut table = HashMap::new();
+        while parser.has_more_lines() {
+            parser.advance();
+            if parser.is_label() {
+                let line = parser.line();
+                table.insert(
+                    line[1..line.len() - 1].to_string(),
+                    parser.instruction_count,
+                );
+            }
+        }
+        let mut parser = Parser::new(source);
+        let mut variables = 16;
+        while parser.has_more_lines() {
+            parser.advance();
+            let line = parser.line();
+            if let Some(symbol) = line.strip_prefix('@')
+                && symbol.parse::<u16>().is_err()
+                && !table.contains_key(symbol)
+            {
+                table.insert(symbol.to_string(), variables);
+                variables += 1;
+            }
+            black_box(line);
+        }
+    }
+}
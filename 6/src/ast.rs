@@ -0,0 +1,195 @@
+/// Index of a source file within one assembly
+pub type FileId = usize;
+
+/// Location of a piece of source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub file: FileId,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column of the first character
+    pub start: usize,
+    /// 1-based column just past the last character
+    pub end: usize,
+}
+
+impl Span {
+    /// A span in the first source file
+    pub fn new(line: usize, start: usize, end: usize) -> Self {
+        Self {
+            file: 0,
+            line,
+            start,
+            end,
+        }
+    }
+
+    pub fn in_file(self, file: FileId) -> Self {
+        Self { file, ..self }
+    }
+
+    /// The smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+    /// Macro calls this statement was expanded from, innermost first
+    pub expansion: Vec<Expansion>,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            expansion: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub call: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    /// @xxx
+    A(AInstruction),
+    /// dest=comp;jump
+    C(CInstruction),
+    /// (xxx)
+    Label(String),
+    /// .equ NAME value
+    Equ { name: String, value: Expr },
+    /// .include "path"
+    Include(String),
+    /// .macro NAME param, ...
+    MacroDef { name: String, params: Vec<String> },
+    /// .endm
+    EndMacro,
+    /// NAME arg, ...
+    MacroCall { name: String, args: Vec<Expr> },
+}
+
+impl StatementKind {
+    /// Whether the statement occupies a ROM word
+    pub fn is_instruction(&self) -> bool {
+        matches!(self, StatementKind::A(_) | StatementKind::C(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AInstruction {
+    /// @123
+    Literal(u16),
+    /// @LOOP
+    Symbol(String),
+    /// @SCREEN+32*4, resolved at assembly time
+    Expr(Expr),
+}
+
+impl AInstruction {
+    /// The simplest A-instruction loading `expr`
+    pub fn from_expr(expr: Expr) -> Self {
+        match expr {
+            // larger values are left to the range check of the assembler
+            Expr::Number(n) if (0..=crate::Assembler::MAX_VALUE).contains(&n) => {
+                AInstruction::Literal(n as u16)
+            }
+            Expr::Symbol(name, _) => AInstruction::Symbol(name),
+            expr => AInstruction::Expr(expr),
+        }
+    }
+}
+
+/// A constant expression over numbers, labels and constants
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String, Span),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    pub message: String,
+    /// The symbol which caused the error, if any
+    pub span: Option<Span>,
+}
+
+impl Expr {
+    /// Evaluate the expression, looking up the value of each symbol with `lookup`
+    pub fn eval(&self, lookup: &impl Fn(&str) -> Option<i64>) -> Result<i64, EvalError> {
+        let overflow = || EvalError {
+            message: "arithmetic overflow in expression".to_string(),
+            span: None,
+        };
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Symbol(name, span) => lookup(name).ok_or_else(|| EvalError {
+                message: format!("unknown symbol `{}` in expression", name),
+                span: Some(*span),
+            }),
+            Expr::Neg(e) => e.eval(lookup)?.checked_neg().ok_or_else(overflow),
+            Expr::Binary(op, lhs, rhs) => {
+                let (l, r) = (lhs.eval(lookup)?, rhs.eval(lookup)?);
+                match op {
+                    BinOp::Add => l.checked_add(r),
+                    BinOp::Sub => l.checked_sub(r),
+                    BinOp::Mul => l.checked_mul(r),
+                    BinOp::Div if r == 0 => {
+                        return Err(EvalError {
+                            message: "division by zero in expression".to_string(),
+                            span: None,
+                        });
+                    }
+                    BinOp::Div => l.checked_div(r),
+                }
+                .ok_or_else(overflow)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CInstruction {
+    pub dest: Option<Mnemonic>,
+    pub comp: Mnemonic,
+    pub jump: Option<Mnemonic>,
+}
+
+/// One field of a C-instruction, with whitespace removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    pub text: String,
+    pub span: Span,
+}
+
+impl Mnemonic {
+    pub fn new(text: &str, span: Span) -> Self {
+        Self {
+            text: text.to_string(),
+            span,
+        }
+    }
+}
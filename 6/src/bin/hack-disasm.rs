@@ -0,0 +1,77 @@
+use std::io::{Read, Write};
+
+use assembler::disasm::{self, SymbolMap};
+use assembler::{Diagnostic, Isa};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hack-disasm [--isa {}] [--symbols FILE] [INPUT.hack] [OUTPUT.asm]",
+        Isa::NAMES.join("|")
+    );
+    std::process::exit(2)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    std::process::exit(1)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut isa = Isa::Standard;
+    let mut symbols_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--isa" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                isa = name.parse()?;
+            }
+            "--symbols" => symbols_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() > 2 {
+        usage();
+    }
+
+    let (input_name, source) = match paths.first() {
+        Some(path) => (path.clone(), std::fs::read_to_string(path)?),
+        None => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            ("<stdin>".to_string(), source)
+        }
+    };
+    let words = disasm::read_hack(&input_name, &source).unwrap_or_else(|d| report(&d));
+
+    let symbols = match symbols_path {
+        Some(path) => {
+            let source = std::fs::read_to_string(&path)?;
+            Some(SymbolMap::parse(&path, &source).unwrap_or_else(|d| report(&d)))
+        }
+        None => None,
+    };
+
+    let disassembly = disasm::disassemble(&words, symbols.as_ref(), isa);
+    for (addr, word) in &disassembly.invalid {
+        eprintln!(
+            "error: invalid instruction {:016b} at ROM[{}] is left out",
+            word, addr
+        );
+    }
+
+    match paths.get(1) {
+        Some(path) => std::fs::write(path, &disassembly.source)?,
+        None => std::io::stdout().write_all(disassembly.source.as_bytes())?,
+    }
+    // the output is still written, but does not assemble to the same words
+    if !disassembly.invalid.is_empty() {
+        std::process::exit(1)
+    }
+
+    Ok(())
+}
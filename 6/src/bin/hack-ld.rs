@@ -0,0 +1,76 @@
+use std::io::Write;
+
+use assembler::memory::MemoryMap;
+use assembler::object::{self, Object};
+use assembler::{Diagnostic, OutputFormat};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hack-ld [--format {}] [--memory-map FILE] [-o OUTPUT] INPUT.hobj...",
+        OutputFormat::NAMES.join("|")
+    );
+    std::process::exit(2)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    std::process::exit(1)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut format = OutputFormat::Text;
+    let mut memory_map_path = None;
+    let mut out_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                format = name.parse()?;
+            }
+            "--memory-map" => memory_map_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-o" => out_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+
+    let memory_map = match &memory_map_path {
+        Some(path) => {
+            let source = std::fs::read_to_string(path)?;
+            MemoryMap::parse(path, &source).unwrap_or_else(|d| report(&d))
+        }
+        None => MemoryMap::default(),
+    };
+
+    let mut objects = Vec::new();
+    for path in &paths {
+        let source = std::fs::read_to_string(path)?;
+        objects.push(Object::parse(path, &source).unwrap_or_else(|d| report(&d)));
+    }
+
+    let words = match object::link(&objects, &memory_map) {
+        Ok(words) => words,
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("error: {}", e);
+            }
+            let s = if errors.len() == 1 { "" } else { "s" };
+            eprintln!("error: could not link due to {} error{}", errors.len(), s);
+            std::process::exit(1)
+        }
+    };
+
+    let mut out: Box<dyn Write> = match out_path {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    assembler::write_words(&mut out, &words, format)?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::io::{Read, Write};
+
+use assembler::{Diagnostic, fmt};
+
+fn usage() -> ! {
+    eprintln!("usage: hackfmt [--check] [INPUT.asm]...");
+    std::process::exit(2)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    std::process::exit(1)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+
+    // without files, format standard input to standard output
+    if paths.is_empty() {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        let formatted = fmt::format("<stdin>", &source).unwrap_or_else(|d| report(&d));
+        if check {
+            std::process::exit(i32::from(formatted != source));
+        }
+        std::io::stdout().write_all(formatted.as_bytes())?;
+        return Ok(());
+    }
+
+    let mut unformatted = 0;
+    for path in &paths {
+        let source = std::fs::read_to_string(path)?;
+        let formatted = fmt::format(path, &source).unwrap_or_else(|d| report(&d));
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted += 1;
+        } else {
+            std::fs::write(path, formatted)?;
+        }
+    }
+    if unformatted > 0 {
+        std::process::exit(1)
+    }
+    Ok(())
+}
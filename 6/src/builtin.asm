@@ -0,0 +1,31 @@
+// Macros available to every program. A program may define its own macro with
+// the same name to replace any of them.
+
+// RAM[dst] = RAM[src]
+.macro MOV src, dst
+    @src
+    D=M
+    @dst
+    M=D
+.endm
+
+// Push D onto the stack
+.macro PUSH_D
+    @SP
+    M=M+1
+    A=M-1
+    M=D
+.endm
+
+// Pop the top of the stack into D
+.macro POP_D
+    @SP
+    AM=M-1
+    D=M
+.endm
+
+// Jump to target if D is zero
+.macro JUMP_IF_ZERO target
+    @target
+    D;JEQ
+.endm
@@ -1,21 +1,94 @@
+use crate::ast::{CInstruction, Span};
+use crate::error;
+
 fn clean_str(s: &str) -> &str {
     s.trim()
 }
 
+/// Which instructions the assembler accepts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Isa {
+    /// The instructions of the course CPU
+    #[default]
+    Standard,
+    /// Also the shift comps such as `D<<` and `M>>`, encoded with the prefix `101`
+    Extended,
+}
+
+impl Isa {
+    pub const NAMES: &[&str] = &["standard", "extended"];
+}
+
+impl std::str::FromStr for Isa {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Isa::Standard),
+            "extended" => Ok(Isa::Extended),
+            _ => Err(format!(
+                "unknown instruction set `{}` (expected one of {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CodeGenError {
     InvalidInstruction,
 }
 
-pub fn dest(s: &str) -> Result<&str, CodeGenError> {
-    dbg!(s);
+/// Dest mnemonics with their registers in the order `A`, `D`, `M`, though any order is accepted
+pub const DEST_MNEMONICS: &[&str] = &["M", "D", "DM", "A", "AM", "AD", "ADM"];
 
-    let bin = match clean_str(s) {
-        "" => "000",
+pub const COMP_MNEMONICS: &[&str] = &[
+    "0", "1", "-1", "D", "A", "M", "!D", "!A", "!M", "-D", "-A", "-M", "D+1", "A+1", "M+1", "D-1",
+    "A-1", "M-1", "D+A", "D+M", "D-A", "D-M", "A-D", "M-D", "D&A", "D&M", "D|A", "D|M",
+];
+
+pub const SHIFT_MNEMONICS: &[&str] = &["D<<", "A<<", "M<<", "D>>", "A>>", "M>>"];
+
+pub const JUMP_MNEMONICS: &[&str] = &["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// `s` with its registers in the order `A`, `D`, `M`, if it names each at most once
+pub fn canonical_dest(s: &str) -> Option<String> {
+    let s = clean_str(s);
+    let counts = ['A', 'D', 'M'].map(|r| s.chars().filter(|&c| c == r).count());
+    if s.is_empty() || counts.iter().any(|&n| n > 1) || counts.iter().sum::<usize>() != s.len() {
+        return None;
+    }
+    Some(
+        "ADM"
+            .chars()
+            .zip(counts)
+            .filter(|(_, n)| *n == 1)
+            .map(|(c, _)| c)
+            .collect(),
+    )
+}
+
+/// The spelling of `s` in [`COMP_MNEMONICS`], swapping the operands of `+`, `&`
+/// and `|` if needed
+pub fn canonical_comp(s: &str) -> Option<&'static str> {
+    let s = clean_str(s);
+    let find = |m: &str| COMP_MNEMONICS.iter().copied().find(|c| *c == m);
+    find(s).or_else(|| {
+        let i = s.find(['+', '&', '|'])?;
+        find(&format!("{}{}{}", &s[i + 1..], &s[i..i + 1], &s[..i]))
+    })
+}
+
+pub fn dest(s: &str) -> Result<&str, CodeGenError> {
+    if clean_str(s).is_empty() {
+        return Ok("000");
+    }
+    let canonical = canonical_dest(s).ok_or(CodeGenError::InvalidInstruction)?;
+    let bin = match canonical.as_str() {
         "M" => "001",
         "D" => "010",
         "DM" => "011",
-        "MD" => "011",
         "A" => "100",
         "AM" => "101",
         "AD" => "110",
@@ -26,7 +99,8 @@ pub fn dest(s: &str) -> Result<&str, CodeGenError> {
 }
 
 pub fn comp(s: &str) -> Result<&str, CodeGenError> {
-    let bin = match clean_str(s) {
+    let canonical = canonical_comp(s).ok_or(CodeGenError::InvalidInstruction)?;
+    let bin = match canonical {
         "0" => "0101010",
         "1" => "0111111",
         "-1" => "0111010",
@@ -61,6 +135,21 @@ pub fn comp(s: &str) -> Result<&str, CodeGenError> {
     Ok(bin)
 }
 
+/// The 7 comp bits of a shift, which follow the prefix `101` instead of `111`
+pub fn shift(s: &str) -> Result<&str, CodeGenError> {
+    let bin = match clean_str(s) {
+        "D<<" => "0110000",
+        "A<<" => "0100000",
+        "M<<" => "1100000",
+        "D>>" => "0010000",
+        "A>>" => "0000000",
+        "M>>" => "1000000",
+        _ => return Err(CodeGenError::InvalidInstruction),
+    };
+
+    Ok(bin)
+}
+
 pub fn jump(s: &str) -> Result<&str, CodeGenError> {
     let bin = match clean_str(s) {
         "" => "000",
@@ -76,3 +165,87 @@ pub fn jump(s: &str) -> Result<&str, CodeGenError> {
 
     Ok(bin)
 }
+
+/// Mnemonic encoded by the 3 dest bits, `""` for no destination
+pub fn dest_mnemonic(bits: &str) -> Option<&'static str> {
+    std::iter::once("")
+        .chain(DEST_MNEMONICS.iter().copied())
+        .find(|m| dest(m) == Ok(bits))
+}
+
+/// Mnemonic encoded by the 7 `a c1..c6` comp bits
+pub fn comp_mnemonic(bits: &str) -> Option<&'static str> {
+    COMP_MNEMONICS.iter().copied().find(|m| comp(m) == Ok(bits))
+}
+
+/// Shift mnemonic encoded by the 7 comp bits after the prefix `101`
+pub fn shift_mnemonic(bits: &str) -> Option<&'static str> {
+    SHIFT_MNEMONICS
+        .iter()
+        .copied()
+        .find(|m| shift(m) == Ok(bits))
+}
+
+/// Mnemonic encoded by the 3 jump bits, `""` for no jump
+pub fn jump_mnemonic(bits: &str) -> Option<&'static str> {
+    std::iter::once("")
+        .chain(JUMP_MNEMONICS.iter().copied())
+        .find(|m| jump(m) == Ok(bits))
+}
+
+/// Why one field of a C-instruction can not be encoded
+pub(crate) struct FieldError {
+    pub message: String,
+    pub span: Span,
+    pub suggestion: Option<&'static str>,
+}
+
+/// Encode `inst` with the instructions of `isa`, reporting every invalid field
+pub(crate) fn encode_c(inst: &CInstruction, isa: Isa) -> Result<u16, Vec<FieldError>> {
+    let dest_text = inst.dest.as_ref().map_or("", |m| m.text.as_str());
+    let jump_text = inst.jump.as_ref().map_or("", |m| m.text.as_str());
+    let (prefix, comp) = match (comp(&inst.comp.text), shift(&inst.comp.text)) {
+        (Err(_), Ok(bits)) if isa == Isa::Extended => ("101", Ok(bits)),
+        (Err(_), Ok(_)) => {
+            return Err(vec![FieldError {
+                message: format!(
+                    "`{}` is only in the extended instruction set",
+                    inst.comp.text
+                ),
+                span: inst.comp.span,
+                suggestion: None,
+            }]);
+        }
+        (result, _) => ("111", result),
+    };
+    let comps = match isa {
+        Isa::Standard => COMP_MNEMONICS.to_vec(),
+        Isa::Extended => [COMP_MNEMONICS, SHIFT_MNEMONICS].concat(),
+    };
+    let fields = [
+        (Some(&inst.comp), "comp", comp, &comps[..]),
+        (inst.dest.as_ref(), "dest", dest(dest_text), DEST_MNEMONICS),
+        (inst.jump.as_ref(), "jump", jump(jump_text), JUMP_MNEMONICS),
+    ];
+
+    let mut bin = String::from(prefix);
+    let mut errors = Vec::new();
+    for (mnemonic, kind, result, candidates) in fields {
+        match (result, mnemonic) {
+            (Ok(b), _) => bin.push_str(b),
+            (Err(_), Some(m)) => errors.push(FieldError {
+                message: format!("invalid {} `{}`", kind, m.text),
+                span: m.span,
+                suggestion: error::closest(&m.text, candidates),
+            }),
+            (Err(_), None) => unreachable!("empty {} is always valid", kind),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(u16::from_str_radix(&bin, 2).unwrap())
+    } else {
+        errors.sort_by_key(|e| e.span.start);
+        Err(errors)
+    }
+}
@@ -0,0 +1,389 @@
+//! Turn `.hack` machine code back into Hack assembly
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ast::Span;
+use crate::code::{self, Isa};
+use crate::error::Diagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// @value
+    A(u16),
+    /// dest=comp;jump, with `""` for an omitted dest or jump
+    C {
+        dest: &'static str,
+        comp: &'static str,
+        jump: &'static str,
+    },
+    /// A C-instruction whose bits do not encode any mnemonic
+    Invalid(u16),
+}
+
+impl Instruction {
+    /// Decode `word`, which is invalid if it is a shift and `isa` has none
+    pub fn decode(word: u16, isa: Isa) -> Self {
+        if word & 0x8000 == 0 {
+            return Instruction::A(word);
+        }
+        let bits = format!("{:016b}", word);
+        let comp = match &bits[..3] {
+            "111" => code::comp_mnemonic(&bits[3..10]),
+            "101" if isa == Isa::Extended => code::shift_mnemonic(&bits[3..10]),
+            _ => return Instruction::Invalid(word),
+        };
+        match (
+            comp,
+            code::dest_mnemonic(&bits[10..13]),
+            code::jump_mnemonic(&bits[13..]),
+        ) {
+            (Some(comp), Some(dest), Some(jump)) => Instruction::C { dest, comp, jump },
+            _ => Instruction::Invalid(word),
+        }
+    }
+
+    fn uses_memory(&self) -> bool {
+        match self {
+            Instruction::C { dest, comp, .. } => dest.contains('M') || comp.contains('M'),
+            _ => false,
+        }
+    }
+
+    fn is_jump(&self) -> bool {
+        matches!(self, Instruction::C { jump, .. } if !jump.is_empty())
+    }
+}
+
+/// Names to use instead of raw addresses, read from a symbol file
+///
+/// Each line is `NAME ADDRESS [KIND]` where `KIND` is `label`, `variable`,
+/// `predefined` or `constant`, and defaults to `label`. Constants are not used
+/// for naming. Any further columns and `//` comments are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// ROM address -> labels, the first of which names it
+    labels: BTreeMap<u16, Vec<String>>,
+    /// RAM address -> variable or predefined symbol
+    variables: BTreeMap<u16, String>,
+}
+
+impl SymbolMap {
+    pub fn parse(file: &str, source: &str) -> Result<Self, Vec<Diagnostic>> {
+        let mut map = SymbolMap::default();
+        let mut diagnostics = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let content = line.split("//").next().unwrap_or("");
+            let mut columns = content.split_whitespace();
+            let Some(name) = columns.next() else {
+                continue;
+            };
+            let error = |message: String| {
+                let start = line.find(name).unwrap_or(0) + 1;
+                let span = Span::new(i + 1, start, start + content.trim().len());
+                Diagnostic::new(message, file, span, line)
+            };
+            let Some(address) = columns.next().and_then(|a| a.parse::<u16>().ok()) else {
+                diagnostics.push(error(format!("missing or invalid address for `{}`", name)));
+                continue;
+            };
+            let table = match columns.next().unwrap_or("label") {
+                "label" => {
+                    map.add_label(name, address);
+                    continue;
+                }
+                "variable" | "predefined" => &mut map.variables,
+                "constant" => continue,
+                kind => {
+                    diagnostics.push(error(format!("unknown symbol kind `{}`", kind)));
+                    continue;
+                }
+            };
+            // the first name given for an address wins
+            table.entry(address).or_insert_with(|| name.to_string());
+        }
+
+        if diagnostics.is_empty() {
+            Ok(map)
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    pub fn add_label(&mut self, name: &str, address: u16) {
+        let names = self.labels.entry(address).or_default();
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    /// Every label with its ROM address, in address order
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels
+            .iter()
+            .flat_map(|(addr, names)| names.iter().map(move |name| (*addr, name.as_str())))
+    }
+
+    pub fn add_variable(&mut self, name: &str, address: u16) {
+        self.variables
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+}
+
+/// Read the ASCII `0`/`1` lines of a `.hack` file, which must fit in the ROM
+pub fn read_hack(file: &str, source: &str) -> Result<Vec<u16>, Vec<Diagnostic>> {
+    let mut words = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let start = line.find(trimmed).unwrap_or(0) + 1;
+        let span = Span::new(i + 1, start, start + trimmed.len());
+        if trimmed.len() == 16 && trimmed.chars().all(|c| c == '0' || c == '1') {
+            if words.len() as i64 == crate::Assembler::MAX_VALUE + 1 {
+                let message = "program does not fit in the 32768 words of ROM".to_string();
+                diagnostics.push(Diagnostic::new(message, file, span, line));
+            }
+            words.push(u16::from_str_radix(trimmed, 2).unwrap());
+        } else {
+            diagnostics.push(Diagnostic::new(
+                "expected 16 binary digits".to_string(),
+                file,
+                span,
+                line,
+            ));
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(words)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    /// Hack assembly which assembles back to the same words, except that each
+    /// invalid word is left out as a comment and later instructions move down
+    /// to fill its place
+    pub source: String,
+    /// ROM address and word of every instruction that could not be decoded
+    pub invalid: Vec<(usize, u16)>,
+}
+
+/// Decode `words` of instruction set `isa` into assembly, naming jump targets
+/// with `symbols` or `LABEL_<address>`
+pub fn disassemble(words: &[u16], symbols: Option<&SymbolMap>, isa: Isa) -> Disassembly {
+    let instructions = words
+        .iter()
+        .map(|w| Instruction::decode(*w, isa))
+        .collect::<Vec<_>>();
+
+    // an A-instruction directly followed by a jump holds a ROM address
+    let targets = instructions
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Instruction::A(addr), next] if next.is_jump() => Some(*addr),
+            _ => None,
+        })
+        .filter(|addr| usize::from(*addr) <= instructions.len())
+        .collect::<BTreeSet<_>>();
+    let label = |addr: u16| {
+        symbols
+            .and_then(|s| s.labels.get(&addr).map(|names| names[0].clone()))
+            .unwrap_or_else(|| format!("LABEL_{}", addr))
+    };
+    let variable = |addr: u16| symbols.and_then(|s| s.variables.get(&addr).cloned());
+
+    let mut source = String::new();
+    let mut invalid = Vec::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if targets.contains(&(i as u16)) {
+            source.push_str(&format!("({})\n", label(i as u16)));
+        }
+        let next = instructions.get(i + 1);
+        let line = match *inst {
+            Instruction::A(addr)
+                if targets.contains(&addr) && next.is_some_and(|n| n.is_jump()) =>
+            {
+                format!("@{}", label(addr))
+            }
+            Instruction::A(addr) if next.is_some_and(|n| n.uses_memory()) => {
+                format!("@{}", variable(addr).unwrap_or_else(|| addr.to_string()))
+            }
+            Instruction::A(value) => format!("@{}", value),
+            Instruction::C { dest, comp, jump } => {
+                let mut line = String::new();
+                if !dest.is_empty() {
+                    line.push_str(dest);
+                    line.push('=');
+                }
+                line.push_str(comp);
+                if !jump.is_empty() {
+                    line.push(';');
+                    line.push_str(jump);
+                }
+                line
+            }
+            Instruction::Invalid(word) => {
+                invalid.push((i, word));
+                format!("// invalid instruction {:016b}", word)
+            }
+        };
+        source.push_str(&line);
+        source.push('\n');
+    }
+    // a jump may target the address just past the program
+    let end = instructions.len() as u16;
+    if targets.contains(&end) {
+        source.push_str(&format!("({})\n", label(end)));
+    }
+
+    Disassembly { source, invalid }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+
+    fn assemble(source: &str) -> Vec<u16> {
+        let mut buf = Vec::new();
+        Assembler::new(source).write(&mut buf).unwrap();
+        read_hack("test.hack", &String::from_utf8(buf).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Instruction::decode(0b0000000000000111, Isa::Standard),
+            Instruction::A(7)
+        );
+        assert_eq!(
+            Instruction::decode(0b1111110010011000, Isa::Standard),
+            Instruction::C {
+                dest: "DM",
+                comp: "M-1",
+                jump: ""
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0b1110101010000111, Isa::Standard),
+            Instruction::C {
+                dest: "",
+                comp: "0",
+                jump: "JMP"
+            }
+        );
+        // unused comp pattern
+        assert_eq!(
+            Instruction::decode(0b1111111111000000, Isa::Standard),
+            Instruction::Invalid(0b1111111111000000)
+        );
+        // shifts of the extended instruction set leave bit 14 clear
+        assert_eq!(
+            Instruction::decode(0b1011100000010000, Isa::Extended),
+            Instruction::C {
+                dest: "D",
+                comp: "M<<",
+                jump: ""
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0b1011100000010000, Isa::Standard),
+            Instruction::Invalid(0b1011100000010000)
+        );
+        // bit 13 must be set
+        assert_eq!(
+            Instruction::decode(0b1000101010000111, Isa::Standard),
+            Instruction::Invalid(0b1000101010000111)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for source in [
+            include_str!("../asm/Max.asm"),
+            include_str!("../asm/Rect.asm"),
+            include_str!("../asm/Pong.asm"),
+        ] {
+            let words = assemble(source);
+            let disassembly = disassemble(&words, None, Isa::Standard);
+            assert!(disassembly.invalid.is_empty());
+            assert_eq!(assemble(&disassembly.source), words);
+        }
+    }
+
+    #[test]
+    fn test_invalid_word() {
+        let jump = 0b1110101010000111;
+        let disassembly = disassemble(&[0x8000, 3, jump, 3, jump], None, Isa::Standard);
+        assert_eq!(disassembly.invalid, [(0, 0x8000)]);
+        // the label moves down with the instruction it names
+        assert_eq!(assemble(&disassembly.source), [2, jump, 2, jump]);
+    }
+
+    #[test]
+    fn test_synthesize_labels() {
+        let words = assemble(include_str!("../asm/MaxL.asm"));
+        let disassembly = disassemble(&words, None, Isa::Standard);
+        let expected = "\
+@0
+D=M
+@1
+D=D-M
+@LABEL_10
+D;JGT
+@1
+D=M
+@LABEL_12
+0;JMP
+(LABEL_10)
+@0
+D=M
+(LABEL_12)
+@2
+M=D
+(LABEL_14)
+@LABEL_14
+0;JMP
+";
+        assert_eq!(disassembly.source, expected);
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let map = SymbolMap::parse(
+            "Max.sym",
+            "// Max\nR0 0 predefined\nR1 1 predefined\nR2 2 predefined\n\
+             ITSR0 10\nOUTPUT_D 12 label\nEND 14 label\n",
+        )
+        .unwrap();
+        let words = assemble(include_str!("../asm/MaxL.asm"));
+        let disassembly = disassemble(&words, Some(&map), Isa::Standard);
+        assert_eq!(
+            assemble(&disassembly.source),
+            assemble(include_str!("../asm/Max.asm"))
+        );
+        assert!(disassembly.source.contains("(ITSR0)\n@R0\nD=M\n"));
+        assert!(disassembly.source.contains("(END)\n@END\n0;JMP\n"));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let diagnostics = read_hack("bad.hack", "0000000000000001\n00102\n").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 2);
+
+        let source = "0000000000000000\n".repeat(32768) + "1110101010000111\n";
+        let diagnostics = read_hack("big.hack", &source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 32769);
+
+        let diagnostics = SymbolMap::parse("bad.sym", "LOOP\nEND 3 something\n").unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+    }
+}
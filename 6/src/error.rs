@@ -0,0 +1,243 @@
+use std::fmt;
+use std::io;
+
+use crate::ast::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Severity {
+    #[default]
+    Error,
+    /// Suspicious but still assembled, unless in strict mode
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found in the source, pointing at the offending text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column range of the offending text (end is exclusive)
+    pub columns: std::ops::Range<usize>,
+    pub text: String,
+    pub suggestion: Option<String>,
+    /// The whole source line, used to render the snippet
+    pub source_line: String,
+    /// Related locations, such as the macro call a statement was expanded from
+    pub notes: Vec<Note>,
+    /// Name of the lint which found the problem
+    pub lint: Option<&'static str>,
+}
+
+/// A secondary location attached to a [`Diagnostic`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub columns: std::ops::Range<usize>,
+    pub source_line: String,
+}
+
+impl Note {
+    pub(crate) fn new(message: String, file: &str, span: Span, source_line: &str) -> Self {
+        Self {
+            message,
+            file: file.to_string(),
+            line: span.line,
+            columns: span.start..span.end,
+            source_line: source_line.to_string(),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub(crate) fn new(message: String, file: &str, span: Span, source_line: &str) -> Self {
+        let text = source_line
+            .get(span.start - 1..span.end - 1)
+            .unwrap_or("")
+            .to_string();
+        Self {
+            severity: Severity::Error,
+            message,
+            file: file.to_string(),
+            line: span.line,
+            columns: span.start..span.end,
+            text,
+            suggestion: None,
+            source_line: source_line.to_string(),
+            notes: Vec::new(),
+            lint: None,
+        }
+    }
+
+    pub(crate) fn warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    pub(crate) fn with_suggestion(mut self, suggestion: Option<&str>) -> Self {
+        self.suggestion = suggestion.map(|s| s.to_string());
+        self
+    }
+}
+
+/// Write the location, source line and `^^^` marker of one snippet
+fn write_snippet(
+    f: &mut fmt::Formatter<'_>,
+    file: &str,
+    line: usize,
+    columns: &std::ops::Range<usize>,
+    source_line: &str,
+) -> fmt::Result {
+    let width = line.to_string().len();
+    writeln!(f, "{:width$}--> {}:{}:{}", "", file, line, columns.start)?;
+    writeln!(f, "{:width$} |", "")?;
+    writeln!(f, "{} | {}", line, source_line)?;
+    let marker = "^".repeat(columns.len().max(1));
+    write!(
+        f,
+        "{:width$} | {:pad$}{}",
+        "",
+        "",
+        marker,
+        pad = columns.start - 1
+    )
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.severity.name(), self.message)?;
+        write_snippet(f, &self.file, self.line, &self.columns, &self.source_line)?;
+        if let Some(suggestion) = &self.suggestion {
+            let width = self.line.to_string().len();
+            write!(f, "\n{:width$} = help: did you mean `{}`?", "", suggestion)?;
+        }
+        if let Some(lint) = self.lint {
+            let width = self.line.to_string().len();
+            write!(
+                f,
+                "\n{:width$} = note: add `// lint:allow({})` to the line to allow this",
+                "", lint
+            )?;
+        }
+        for note in &self.notes {
+            writeln!(f, "\nnote: {}", note.message)?;
+            write_snippet(f, &note.file, note.line, &note.columns, &note.source_line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Every problem found in the source, in source order
+    Assemble(Vec<Diagnostic>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Assemble(diagnostics) => {
+                for (i, d) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", d)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Assemble(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Returns the candidate closest to `s`, if any is close enough to be a likely typo
+pub(crate) fn closest<'a>(s: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let threshold = (s.chars().count() / 2).max(1);
+    candidates
+        .iter()
+        .map(|c| (edit_distance(s, c), *c))
+        .filter(|(d, _)| *d <= threshold)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(closest("D+2", &["D+1", "D-1", "A"]), Some("D+1"));
+        assert_eq!(closest("JMPP", &["JMP", "JEQ"]), Some("JMP"));
+        assert_eq!(closest("XYZW", &["D+1", "A"]), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let d = Diagnostic {
+            severity: Severity::Error,
+            message: "invalid comp `D+2`".to_string(),
+            file: "Add.asm".to_string(),
+            line: 3,
+            columns: 3..6,
+            text: "D+2".to_string(),
+            suggestion: Some("D+1".to_string()),
+            source_line: "D=D+2".to_string(),
+            notes: Vec::new(),
+            lint: None,
+        };
+        let expected = "\
+error: invalid comp `D+2`
+ --> Add.asm:3:3
+  |
+3 | D=D+2
+  |   ^^^
+  = help: did you mean `D+1`?";
+        assert_eq!(d.to_string(), expected);
+    }
+}
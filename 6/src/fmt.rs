@@ -0,0 +1,225 @@
+//! Rewriting assembly source text
+//!
+//! [`format`] lays out a program in one style: labels and directives start the
+//! line, everything else is indented, trailing comments line up and runs of
+//! blank lines become one.
+
+use std::collections::BTreeMap;
+
+use crate::ast::{CInstruction, StatementKind};
+use crate::code;
+use crate::error::Diagnostic;
+use crate::parser::Parser;
+
+/// `inst` spelled as `dest=comp;jump` with dest registers in the order `A`, `D`,
+/// `M` and comp as in [`code::COMP_MNEMONICS`], keeping mnemonics it does not know
+pub fn canonical_c(inst: &CInstruction) -> String {
+    let mut text = String::new();
+    if let Some(dest) = &inst.dest {
+        text.push_str(&code::canonical_dest(&dest.text).unwrap_or_else(|| dest.text.clone()));
+        text.push('=');
+    }
+    text.push_str(code::canonical_comp(&inst.comp.text).unwrap_or(&inst.comp.text));
+    if let Some(jump) = &inst.jump {
+        text.push(';');
+        text.push_str(&jump.text);
+    }
+    text
+}
+
+/// Rewrite each C-instruction of `source` in its canonical spelling, leaving
+/// everything else on the line as it is
+pub fn canonicalize(file: &str, source: &str) -> Result<String, Vec<Diagnostic>> {
+    let statements = Parser::new(file, source).parse()?;
+    let mut lines = source.split('\n').map(String::from).collect::<Vec<_>>();
+    for statement in statements {
+        let StatementKind::C(inst) = &statement.kind else {
+            continue;
+        };
+        let span = statement.span;
+        let line = &mut lines[span.line - 1];
+        line.replace_range(span.start - 1..span.end - 1, &canonical_c(inst));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Spaces before an instruction
+const INDENT: usize = 4;
+/// Column where trailing comments start, unless the code is longer
+const COMMENT_COLUMN: usize = 24;
+
+/// `source` in the standard layout, with C-instructions spelled as by [`canonical_c`]
+///
+/// Comments on a line of their own keep starting the line if they did, and are
+/// otherwise indented like the next statement.
+pub fn format(file: &str, source: &str) -> Result<String, Vec<Diagnostic>> {
+    let statements = Parser::new(file, source)
+        .parse()?
+        .into_iter()
+        .map(|s| (s.span.line, s))
+        .collect::<BTreeMap<_, _>>();
+
+    // code and comment of each line, with `None` as the code of a line without a statement
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let (code, comment) = split_comment(line);
+            let code = statements.get(&(i + 1)).map(|statement| {
+                let indent = match statement.kind {
+                    StatementKind::Label(_)
+                    | StatementKind::Equ { .. }
+                    | StatementKind::Include(_)
+                    | StatementKind::MacroDef { .. }
+                    | StatementKind::EndMacro => 0,
+                    _ => INDENT,
+                };
+                let text = match &statement.kind {
+                    StatementKind::C(inst) => canonical_c(inst),
+                    _ => code.trim().to_string(),
+                };
+                (indent, text)
+            });
+            (line, code, comment)
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    let mut blank = false;
+    for (i, (line, code, comment)) in lines.iter().enumerate() {
+        let formatted = match (code, comment) {
+            (Some((indent, text)), None) => format!("{:indent$}{}", "", text),
+            (Some((indent, text)), Some(comment)) => {
+                let code = format!("{:indent$}{}", "", text);
+                let width = COMMENT_COLUMN.max(code.len() + 1);
+                format!("{:width$}{}", code, comment)
+            }
+            (None, Some(comment)) if line.starts_with("//") => comment.to_string(),
+            (None, Some(comment)) => {
+                let indent = lines[i..]
+                    .iter()
+                    .find_map(|(_, code, _)| code.as_ref().map(|(indent, _)| *indent))
+                    .unwrap_or(0);
+                format!("{:indent$}{}", "", comment)
+            }
+            (None, None) => {
+                blank = !out.is_empty();
+                continue;
+            }
+        };
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(&formatted);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// The code of `line` and its `//` comment, if any, without trailing whitespace
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '/') if line[i..].starts_with("//") => {
+                return (line[..i].trim_end(), Some(line[i..].trim_end()));
+            }
+            _ => {}
+        }
+    }
+    (line.trim_end(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+
+    #[test]
+    fn test_alternate_spellings() {
+        let source = "MD=A+D\nAMD = M & D ; JGT\nMA=1+M\nDA=D|M\nD = D + 1 ; JGT\n";
+        let canonical = "DM=D+A\nADM=D&M;JGT\nAM=M+1\nAD=D|M\nD=D+1;JGT\n";
+        assert_eq!(canonicalize("<input>", source).unwrap(), canonical);
+        assert_eq!(
+            Assembler::new(source).assemble().unwrap(),
+            Assembler::new(canonical).assemble().unwrap()
+        );
+
+        for bad in ["DD=0", "D=D-A+1", "D=1+1"] {
+            assert!(Assembler::new(bad).assemble().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_keeps_layout() {
+        let source = "// setup\n(LOOP)\n    MD=M+D   // add\n    @LOOP\n\tM-D;JMP\n";
+        assert_eq!(
+            canonicalize("<input>", source).unwrap(),
+            "// setup\n(LOOP)\n    DM=D+M   // add\n    @LOOP\n\tM-D;JMP\n"
+        );
+        assert!(canonicalize("<input>", "@(\n").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let source = "\
+// header
+
+
+  @R0 // first
+D = M
+ // loop start
+   (LOOP)   // here
+@LOOP
+MD=M+D
+
+
+\t0;JMP
+.equ N 2
+.macro TWO x
+@x
+.endm
+TWO N // call
+.include \"a//b.asm\"
+";
+        let formatted = "\
+// header
+
+    @R0                 // first
+    D=M
+// loop start
+(LOOP)                  // here
+    @LOOP
+    DM=D+M
+
+    0;JMP
+.equ N 2
+.macro TWO x
+    @x
+.endm
+    TWO N               // call
+.include \"a//b.asm\"
+";
+        assert_eq!(format("<input>", source).unwrap(), formatted);
+        assert_eq!(format("<input>", formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_programs() {
+        for source in [
+            include_str!("../asm/Max.asm"),
+            include_str!("../asm/Rect.asm"),
+            include_str!("../asm/Pong.asm"),
+        ] {
+            let formatted = format("<input>", source).unwrap();
+            assert_eq!(format("<input>", &formatted).unwrap(), formatted);
+            assert_eq!(
+                Assembler::new(&formatted).assemble().unwrap(),
+                Assembler::new(source).assemble().unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Reading a program from several files
+//!
+//! Each input file and each file it includes is parsed in turn, with the
+//! statements of an included file taking the place of its `.include`. A file is
+//! included at most once. Symbols starting with `.` are local to their file.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{AInstruction, Expr, FileId, Span, Statement, StatementKind};
+use crate::error::Diagnostic;
+use crate::parser::Parser;
+use crate::source::SourceMap;
+
+/// Parse `inputs` in order along with everything they include
+pub(crate) fn load(
+    sources: &mut SourceMap,
+    inputs: &[FileId],
+    include_paths: &[PathBuf],
+) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let mut loader = Loader {
+        sources,
+        include_paths,
+        stack: Vec::new(),
+        seen: BTreeSet::new(),
+        diagnostics: Vec::new(),
+    };
+    let mut program = Vec::new();
+    for &id in inputs {
+        let path = canonical(Path::new(&loader.sources.file(id).name));
+        loader.seen.insert(path.clone());
+        loader.stack.push(path);
+        loader.load_file(id, &mut program);
+        loader.stack.pop();
+    }
+
+    if loader.diagnostics.is_empty() {
+        Ok(program)
+    } else {
+        Err(loader.diagnostics)
+    }
+}
+
+struct Loader<'a> {
+    sources: &'a mut SourceMap,
+    include_paths: &'a [PathBuf],
+    /// Files currently being read, outermost first
+    stack: Vec<PathBuf>,
+    /// Every file read so far
+    seen: BTreeSet<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Loader<'_> {
+    fn load_file(&mut self, id: FileId, out: &mut Vec<Statement>) {
+        let file = self.sources.file(id);
+        let mut statements = match Parser::with_file_id(id, &file.name, &file.text).parse() {
+            Ok(statements) => statements,
+            Err(mut errors) => {
+                self.diagnostics.append(&mut errors);
+                return;
+            }
+        };
+        let prefix = local_prefix(&file.name, id);
+        for statement in &mut statements {
+            localize(&mut statement.kind, &prefix);
+        }
+
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Include(path) => self.include(path, statement.span, out),
+                _ => out.push(statement),
+            }
+        }
+    }
+
+    fn include(&mut self, path: &str, at: Span, out: &mut Vec<Statement>) {
+        let Some(found) = self.resolve(path, at.file) else {
+            let message = format!("can not find `{}` to include", path);
+            self.diagnostics.push(self.sources.diagnostic(message, at));
+            return;
+        };
+        let key = canonical(&found);
+        if let Some(start) = self.stack.iter().position(|p| *p == key) {
+            let cycle = self.stack[start..]
+                .iter()
+                .chain([&key])
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            let message = format!("include cycle: {}", cycle.join(" -> "));
+            self.diagnostics.push(self.sources.diagnostic(message, at));
+            return;
+        }
+        if !self.seen.insert(key.clone()) {
+            return;
+        }
+        let text = match std::fs::read_to_string(&found) {
+            Ok(text) => text,
+            Err(e) => {
+                let message = format!("can not read `{}`: {}", found.display(), e);
+                self.diagnostics.push(self.sources.diagnostic(message, at));
+                return;
+            }
+        };
+
+        let id = self
+            .sources
+            .add_included(&found.display().to_string(), &text, Some(at));
+        self.stack.push(key);
+        self.load_file(id, out);
+        self.stack.pop();
+    }
+
+    /// Look for `path` next to the including file, then in each include path
+    fn resolve(&self, path: &str, from: FileId) -> Option<PathBuf> {
+        let dir = Path::new(&self.sources.file(from).name)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        std::iter::once(dir)
+            .chain(self.include_paths.iter().cloned())
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Prefix making the local symbols of file `id` unique, e.g. `.mult$2` for `lib/mult.asm`
+///
+/// Renamed symbols still start with `.`, which marks them as local in object files.
+fn local_prefix(name: &str, id: FileId) -> String {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!(".{}${}", stem, id)
+}
+
+fn is_local(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Rename every symbol starting with `.` by adding `prefix`
+fn localize(kind: &mut StatementKind, prefix: &str) {
+    let rename = |name: &mut String| {
+        if is_local(name) {
+            *name = format!("{}{}", prefix, name);
+        }
+    };
+    match kind {
+        StatementKind::A(AInstruction::Symbol(name)) | StatementKind::Label(name) => rename(name),
+        StatementKind::A(AInstruction::Expr(expr)) => localize_expr(expr, prefix),
+        StatementKind::Equ { name, value } => {
+            rename(name);
+            localize_expr(value, prefix);
+        }
+        StatementKind::MacroCall { args, .. } => {
+            for arg in args {
+                localize_expr(arg, prefix);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn localize_expr(expr: &mut Expr, prefix: &str) {
+    match expr {
+        Expr::Number(_) => {}
+        Expr::Symbol(name, _) => {
+            if is_local(name) {
+                *name = format!("{}{}", prefix, name);
+            }
+        }
+        Expr::Neg(e) => localize_expr(e, prefix),
+        Expr::Binary(_, lhs, rhs) => {
+            localize_expr(lhs, prefix);
+            localize_expr(rhs, prefix);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `files`
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hack-include-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn load_main(dir: &Path, include_paths: &[PathBuf]) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+        let main = dir.join("main.asm");
+        let mut sources = SourceMap::default();
+        let id = sources.add(
+            &main.display().to_string(),
+            &std::fs::read_to_string(&main).unwrap(),
+        );
+        load(&mut sources, &[id], include_paths)
+    }
+
+    #[test]
+    fn test_include_and_local_labels() {
+        let dir = write_files(
+            "local",
+            &[
+                (
+                    "main.asm",
+                    ".include \"a.asm\"\n(.loop)\n@.loop\n.include \"b.asm\"\n.include \"a.asm\"\n",
+                ),
+                ("a.asm", "(.loop)\n@.loop\n"),
+                ("lib/b.asm", "@SHARED\n"),
+            ],
+        );
+        let statements = load_main(&dir, &[dir.join("lib")]).unwrap();
+        let kinds = statements.into_iter().map(|s| s.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                StatementKind::Label(".a$1.loop".to_string()),
+                StatementKind::A(AInstruction::Symbol(".a$1.loop".to_string())),
+                StatementKind::Label(".main$0.loop".to_string()),
+                StatementKind::A(AInstruction::Symbol(".main$0.loop".to_string())),
+                StatementKind::A(AInstruction::Symbol("SHARED".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = write_files(
+            "errors",
+            &[
+                ("main.asm", ".include \"a.asm\"\n.include \"missing.asm\"\n"),
+                ("a.asm", "@0\n.include \"main.asm\"\n"),
+            ],
+        );
+        let diagnostics = load_main(&dir, &[]).unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].message.starts_with("include cycle: "));
+        assert!(diagnostics[0].message.ends_with("main.asm"));
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].notes[0].line, 1);
+        assert_eq!(
+            diagnostics[1].message,
+            "can not find `missing.asm` to include"
+        );
+    }
+}
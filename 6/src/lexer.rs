@@ -0,0 +1,305 @@
+use crate::ast::{FileId, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    /// `@`
+    At,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// `=`
+    Equal,
+    /// `;`
+    Semicolon,
+    /// `+`
+    Plus,
+    /// `-`
+    Minus,
+    /// `!`
+    Not,
+    /// `&`
+    And,
+    /// `|`
+    Or,
+    /// `*`
+    Star,
+    /// `/`
+    Slash,
+    /// `,`
+    Comma,
+    /// `<<`
+    ShiftLeft,
+    /// `>>`
+    ShiftRight,
+    /// decimal digits, or hex and binary digits after `0x` and `0b`
+    Number(String),
+    /// a single character between `'`
+    Char(char),
+    /// text between `"` on one line
+    Str(String),
+    /// letters, digits, `_`, `.`, `$` and `:`, not starting with a digit
+    Ident(String),
+    /// end of a source line
+    Newline,
+    /// a character that can not start any token
+    Unknown(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    /// The source text of the token
+    pub fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::At => "@".to_string(),
+            TokenKind::LParen => "(".to_string(),
+            TokenKind::RParen => ")".to_string(),
+            TokenKind::Equal => "=".to_string(),
+            TokenKind::Semicolon => ";".to_string(),
+            TokenKind::Plus => "+".to_string(),
+            TokenKind::Minus => "-".to_string(),
+            TokenKind::Not => "!".to_string(),
+            TokenKind::And => "&".to_string(),
+            TokenKind::Or => "|".to_string(),
+            TokenKind::Star => "*".to_string(),
+            TokenKind::Slash => "/".to_string(),
+            TokenKind::Comma => ",".to_string(),
+            TokenKind::ShiftLeft => "<<".to_string(),
+            TokenKind::ShiftRight => ">>".to_string(),
+            TokenKind::Char(c) => format!("'{}'", c),
+            TokenKind::Str(s) => format!("\"{}\"", s),
+            TokenKind::Number(s) | TokenKind::Ident(s) => s.clone(),
+            TokenKind::Newline => "".to_string(),
+            TokenKind::Unknown(c) => c.to_string(),
+        }
+    }
+}
+
+/// Splits Hack assembly into tokens, skipping whitespace and `//` comments
+pub struct Lexer<'a> {
+    file: FileId,
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: FileId, source: &'a str) -> Self {
+        Self {
+            file,
+            source,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span::new(
+            self.line,
+            start - self.line_start + 1,
+            self.pos - self.line_start + 1,
+        )
+        .in_file(self.file)
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek_char() {
+            if !f(c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        &self.source[start..self.pos]
+    }
+}
+
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.take_while(|c| c != '\n' && c.is_whitespace());
+        if self.source[self.pos..].starts_with("//") {
+            self.take_while(|c| c != '\n');
+        }
+
+        let start = self.pos;
+        let c = self.peek_char()?;
+        self.pos += c.len_utf8();
+        let kind = match c {
+            '\n' => {
+                let token = Token {
+                    kind: TokenKind::Newline,
+                    span: self.span(start),
+                };
+                self.line += 1;
+                self.line_start = self.pos;
+                return Some(token);
+            }
+            '@' => TokenKind::At,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '=' => TokenKind::Equal,
+            ';' => TokenKind::Semicolon,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '!' => TokenKind::Not,
+            '&' => TokenKind::And,
+            '|' => TokenKind::Or,
+            '*' => TokenKind::Star,
+            '/' => TokenKind::Slash,
+            ',' => TokenKind::Comma,
+            '<' | '>' if self.peek_char() == Some(c) => {
+                self.pos += 1;
+                if c == '<' {
+                    TokenKind::ShiftLeft
+                } else {
+                    TokenKind::ShiftRight
+                }
+            }
+            '\'' => {
+                let rest = &self.source[self.pos..];
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), Some('\'')) if c != '\n' => {
+                        self.pos += c.len_utf8() + 1;
+                        TokenKind::Char(c)
+                    }
+                    _ => TokenKind::Unknown('\''),
+                }
+            }
+            '"' => {
+                let rest = &self.source[self.pos..];
+                match rest.find(['"', '\n']) {
+                    Some(end) if rest[end..].starts_with('"') => {
+                        self.pos += end + 1;
+                        TokenKind::Str(rest[..end].to_string())
+                    }
+                    _ => TokenKind::Unknown('"'),
+                }
+            }
+            c if c.is_ascii_digit() => {
+                // take letters too, so that `0x1F` is one token and `12ab` is reported as a bad number
+                self.take_while(|c| c.is_ascii_alphanumeric());
+                TokenKind::Number(self.source[start..self.pos].to_string())
+            }
+            c if is_symbol_char(c) => {
+                self.take_while(is_symbol_char);
+                TokenKind::Ident(self.source[start..self.pos].to_string())
+            }
+            c => TokenKind::Unknown(c),
+        };
+
+        Some(Token {
+            kind,
+            span: self.span(start),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        Lexer::new(0, source).map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_c_instruction() {
+        assert_eq!(
+            kinds("AM=M-1;JNE"),
+            vec![
+                TokenKind::Ident("AM".to_string()),
+                TokenKind::Equal,
+                TokenKind::Ident("M".to_string()),
+                TokenKind::Minus,
+                TokenKind::Number("1".to_string()),
+                TokenKind::Semicolon,
+                TokenKind::Ident("JNE".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_whitespace_and_comments() {
+        assert_eq!(
+            kinds("\t@ball.x$1   // comment\n// only comment\n(END)"),
+            vec![
+                TokenKind::At,
+                TokenKind::Ident("ball.x$1".to_string()),
+                TokenKind::Newline,
+                TokenKind::Newline,
+                TokenKind::LParen,
+                TokenKind::Ident("END".to_string()),
+                TokenKind::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_expression() {
+        assert_eq!(
+            kinds("@SCREEN+0x20*'A'"),
+            vec![
+                TokenKind::At,
+                TokenKind::Ident("SCREEN".to_string()),
+                TokenKind::Plus,
+                TokenKind::Number("0x20".to_string()),
+                TokenKind::Star,
+                TokenKind::Char('A'),
+            ]
+        );
+        assert_eq!(kinds("'AB'")[0], TokenKind::Unknown('\''));
+        assert_eq!(
+            kinds(".include \"lib/mult.asm\""),
+            vec![
+                TokenKind::Ident(".include".to_string()),
+                TokenKind::Str("lib/mult.asm".to_string()),
+            ]
+        );
+        assert_eq!(kinds("\"open\n")[0], TokenKind::Unknown('"'));
+    }
+
+    #[test]
+    fn test_tokenize_shift() {
+        assert_eq!(
+            kinds("M=D<<\nA>>;JMP <"),
+            vec![
+                TokenKind::Ident("M".to_string()),
+                TokenKind::Equal,
+                TokenKind::Ident("D".to_string()),
+                TokenKind::ShiftLeft,
+                TokenKind::Newline,
+                TokenKind::Ident("A".to_string()),
+                TokenKind::ShiftRight,
+                TokenKind::Semicolon,
+                TokenKind::Ident("JMP".to_string()),
+                TokenKind::Unknown('<'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_span() {
+        let tokens = Lexer::new(0, "@0\n  D=D+A").collect::<Vec<_>>();
+        assert_eq!(tokens[1].span, Span::new(1, 2, 3));
+        assert_eq!(tokens[3].span, Span::new(2, 3, 4));
+        assert_eq!(tokens[7].span, Span::new(2, 7, 8));
+    }
+}
@@ -1,150 +1,543 @@
 use std::io;
+use std::path::PathBuf;
 
+mod ast;
 mod code;
+pub mod disasm;
+mod error;
+pub mod fmt;
+mod include;
+mod lexer;
+pub mod lint;
+mod listing;
+mod macros;
+pub mod memory;
+pub mod object;
+pub mod optimize;
+mod output;
 mod parser;
+mod source;
+pub mod stream;
+mod symbol;
+
+pub use ast::{
+    AInstruction, CInstruction, Expansion, FileId, Mnemonic, Span, Statement, StatementKind,
+};
+pub use code::Isa;
+pub use error::{Diagnostic, Error, Note, Severity};
+pub use output::{OutputFormat, write_words};
+pub use parser::Parser;
+pub use source::SourceLine;
+use source::SourceMap;
+pub use symbol::SymbolKind;
+use symbol::SymbolTable;
+
+/// A ROM word which may depend on where its module is placed or on other modules
+enum Word {
+    Value(u16),
+    /// A ROM address in this module
+    Address(u16),
+    /// A symbol not defined in this module
+    Symbol {
+        name: String,
+        jump: bool,
+    },
+}
 
-use parser::{InstructionType, Parser};
-
-#[derive(Debug, Clone)]
-struct SymbolTable {
-    map: std::collections::BTreeMap<String, usize>,
+pub struct Assembler {
+    /// The input files and built-in macros, followed by included files once assembled
+    sources: SourceMap,
+    /// Input files in the order they are assembled
+    inputs: Vec<FileId>,
+    /// Directories searched by `.include` after the directory of the including file
+    include_paths: Vec<PathBuf>,
+    table: SymbolTable,
+    format: OutputFormat,
+    /// Location of each ROM word in an input file, set once the source is assembled
+    locations: Option<Vec<Span>>,
+    words: Vec<u16>,
+    /// Warnings of the last assembly
+    warnings: Vec<Diagnostic>,
+    /// Whether warnings fail the assembly
+    strict: bool,
+    /// Whether to run the [`lint`] pass
+    lint: bool,
+    isa: Isa,
+    memory_map: memory::MemoryMap,
+    /// Whether to run the [`optimize`] pass
+    optimize: bool,
+    /// What the optimizer saved in the last assembly
+    optimize_report: Option<optimize::Report>,
 }
 
-impl SymbolTable {
-    fn new() -> Self {
-        const DEFAULT_SYMBOL: &[(&str, usize)] = &[
-            ("R0", 0),
-            ("R1", 1),
-            ("R2", 2),
-            ("R3", 3),
-            ("R4", 4),
-            ("R5", 5),
-            ("R6", 6),
-            ("R7", 7),
-            ("R8", 8),
-            ("R9", 9),
-            ("R10", 10),
-            ("R11", 11),
-            ("R12", 12),
-            ("R13", 13),
-            ("R14", 14),
-            ("R15", 15),
-            ("SP", 0),
-            ("LCL", 1),
-            ("ARG", 2),
-            ("THIS", 3),
-            ("THAT", 4),
-            ("SCREEN", 16384),
-            ("KBD", 24576),
-        ];
-
-        let map = DEFAULT_SYMBOL
-            .iter()
-            .map(|(s, i)| (s.to_string(), *i))
-            .collect();
-        Self { map }
+impl Assembler {
+    pub(crate) const RAM_ADDR_START: usize = 16;
+    /// Largest value an A-instruction can load
+    pub(crate) const MAX_VALUE: i64 = 0x7fff;
+    const BUILTIN_FILE: FileId = 1;
+    pub fn new(source: &str) -> Self {
+        let mut sources = SourceMap::default();
+        sources.add("<input>", source);
+        sources.add("<builtin>", macros::BUILTIN);
+        Assembler {
+            sources,
+            inputs: vec![0],
+            include_paths: Vec::new(),
+            table: SymbolTable::new(),
+            format: OutputFormat::Text,
+            locations: None,
+            words: Vec::new(),
+            warnings: Vec::new(),
+            strict: false,
+            lint: false,
+            isa: Isa::Standard,
+            memory_map: memory::MemoryMap::default(),
+            optimize: false,
+            optimize_report: None,
+        }
     }
-    fn add_entry(&mut self, key: &str, addr: usize) {
-        let _ = self.map.insert(key.to_string(), addr);
+
+    /// Add another input file, assembled after the ones before it
+    ///
+    /// Labels are shared between files, except those starting with `.`.
+    pub fn add_file(&mut self, name: &str, source: &str) {
+        let id = self.sources.add(name, source);
+        self.inputs.push(id);
+        self.locations = None;
     }
 
-    fn contains(&self, key: &str) -> bool {
-        self.map.contains_key(key)
+    /// Add a directory to search for `.include`d files
+    pub fn add_include_path(&mut self, dir: impl Into<PathBuf>) {
+        self.include_paths.push(dir.into());
     }
 
-    fn get_address(&self, s: &str) -> Option<usize> {
-        self.map.get(s).map(|v| *v)
+    /// Set the file name shown in diagnostics
+    pub fn set_file_name(&mut self, name: &str) {
+        self.sources.file_mut(self.inputs[0]).name = name.to_string();
     }
-}
 
-pub struct Assembler {
-    parser: Parser,
-    table: SymbolTable,
-}
+    /// Treat warnings as errors
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
 
-impl Assembler {
-    const RAM_ADDR_START: usize = 16;
-    pub fn new(source: &str) -> Self {
-        Assembler {
-            parser: Parser::new(source),
-            table: SymbolTable::new(),
+    /// Set which instructions are accepted
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+    }
+
+    /// Use the predefined symbols and variable space of `map`
+    pub fn set_memory_map(&mut self, map: memory::MemoryMap) {
+        self.memory_map = map;
+    }
+
+    /// Also report the warnings of the [`lint`] pass
+    pub fn set_lint(&mut self, lint: bool) {
+        self.lint = lint;
+    }
+
+    /// Remove redundant instructions before placing labels
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// What the optimizer saved in the last assembly, if it ran
+    pub fn optimize_report(&self) -> Option<&optimize::Report> {
+        self.optimize_report.as_ref()
+    }
+
+    /// Warnings found by the last assembly, in source order
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Set the encoding used by [`Assembler::write`]
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    /// Assemble the source into machine words
+    pub fn assemble(&mut self) -> Result<Vec<u16>, Error> {
+        let words = self
+            .build(false)?
+            .into_iter()
+            .map(|word| match word {
+                Word::Value(v) | Word::Address(v) => v,
+                Word::Symbol { .. } => unreachable!("variables are allocated unless relocatable"),
+            })
+            .collect::<Vec<_>>();
+        self.words = words.clone();
+        Ok(words)
+    }
+
+    /// Assemble the source into an object for [`object::link`], leaving symbols
+    /// which are not defined in it to the linker
+    pub fn assemble_object(&mut self) -> Result<object::Object, Error> {
+        let mut object = object::Object {
+            name: self.sources.file(self.inputs[0]).name.clone(),
+            ..Default::default()
+        };
+        for (address, word) in self.build(true)?.into_iter().enumerate() {
+            let address = address as u16;
+            let value = match word {
+                Word::Value(v) => v,
+                Word::Address(v) => {
+                    object.relocations.push(address);
+                    v
+                }
+                Word::Symbol { name, jump } => {
+                    object.references.push(object::Reference {
+                        address,
+                        symbol: name,
+                        jump,
+                    });
+                    0
+                }
+            };
+            object.words.push(value);
         }
+        // names starting with `.` are local to their file or macro expansion, and
+        // constants which move neither as a label nor as a plain value can not be exported
+        let exported = self.table.iter().filter(|(name, _, kind)| {
+            matches!(kind, SymbolKind::Label | SymbolKind::Constant) && !name.starts_with('.')
+        });
+        for (name, value, _) in exported {
+            match self.table.moves(name) {
+                0 => object.constants.insert(name.to_string(), value as u16),
+                1 => object.exports.insert(name.to_string(), value as u16),
+                _ => None,
+            };
+        }
+        self.words = object.words.clone();
+        Ok(object)
+    }
+
+    /// Parse, expand and encode the source, leaving unknown symbols as
+    /// [`Word::Symbol`] if `relocatable` and allocating them as variables otherwise
+    fn build(&mut self, relocatable: bool) -> Result<Vec<Word>, Error> {
+        // forget the files included by an earlier run
+        let given = self.inputs.iter().max().unwrap().max(&Self::BUILTIN_FILE) + 1;
+        self.sources.truncate(given);
+        self.table = SymbolTable::with_predefined(&self.memory_map.symbols);
+        self.warnings.clear();
+        self.optimize_report = None;
+
+        let builtin = self.sources.file(Self::BUILTIN_FILE);
+        let builtins = Parser::with_file_id(Self::BUILTIN_FILE, &builtin.name, &builtin.text)
+            .parse()
+            .map_err(Error::Assemble)?;
+        let program = include::load(&mut self.sources, &self.inputs, &self.include_paths)
+            .map_err(Error::Assemble)?;
+        let mut program =
+            macros::expand(program, builtins, &self.sources).map_err(Error::Assemble)?;
+        let mut diagnostics = Vec::new();
+        if self.optimize {
+            match optimize::optimize(&mut program, &self.table, &self.sources) {
+                Ok(report) => self.optimize_report = Some(report),
+                Err(warnings) => diagnostics = warnings,
+            }
+        }
+        let words = self.second_path(&program, relocatable, diagnostics)?;
+        // words from a macro are listed at the call
+        self.locations = Some(
+            program
+                .iter()
+                .filter(|s| s.kind.is_instruction())
+                .map(|s| self.sources.written_at(s))
+                .collect(),
+        );
+        Ok(words)
+    }
+
+    pub fn write(&mut self, writer: &mut impl io::Write) -> Result<(), Error> {
+        let words = self.assemble()?;
+        output::write_words(writer, &words, self.format)?;
+        Ok(())
     }
 
-    pub fn write(&mut self, writer: &mut impl io::Write) -> Result<(), io::Error> {
-        self.first_path();
-        self.second_path(writer)
+    /// Write the source assembled as a `.hobj` object file
+    pub fn write_object(&mut self, writer: &mut impl io::Write) -> Result<(), Error> {
+        self.assemble_object()?.write(writer)?;
+        Ok(())
     }
 
-    fn first_path(&mut self) {
-        while self.parser.has_more_lines() {
-            self.parser.advance();
-            match self.parser.instruction_type() {
-                InstructionType::InstA => {}
-                InstructionType::InstL => {
-                    let sym = self.parser.symbol();
-                    let row = self.parser.row();
-                    if !self.table.contains(sym) {
-                        self.table.add_entry(sym, row);
+    /// Write every symbol with its kind and address, in the format read by
+    /// [`disasm::SymbolMap::parse`]
+    pub fn write_symbols(&mut self, writer: &mut impl io::Write) -> Result<(), Error> {
+        self.ensure_assembled()?;
+        self.table.write(writer)?;
+        Ok(())
+    }
+
+    /// Write each source line next to the ROM address and encoding of its instruction,
+    /// with each included file after the input files under a header naming it
+    pub fn write_listing(&mut self, writer: &mut impl io::Write) -> Result<(), Error> {
+        self.ensure_assembled()?;
+        let locations = self.locations.as_deref().unwrap_or_default();
+        // inputs come first, then the files they include in the order they were read
+        for id in (0..self.sources.len()).filter(|&id| id != Self::BUILTIN_FILE) {
+            let file = self.sources.file(id);
+            if let Some(at) = file.included_at {
+                let at = format!("{}:{}", self.sources.file(at.file).name, at.line);
+                writeln!(writer, "\n{:31}// {}, included from {}", "", file.name, at)?;
+            } else if !self.inputs.contains(&id) {
+                continue;
+            }
+            let words = locations
+                .iter()
+                .zip(&self.words)
+                .enumerate()
+                .filter(|(_, (l, _))| l.file == id)
+                .map(|(addr, (l, word))| (addr, l.line, *word))
+                .collect::<Vec<_>>();
+            listing::write_listing(writer, &file.text, &words)?;
+        }
+        Ok(())
+    }
+
+    /// Every symbol with its address and kind, sorted by name
+    pub fn symbols(&mut self) -> Result<Vec<(String, usize, SymbolKind)>, Error> {
+        self.ensure_assembled()?;
+        Ok(self
+            .table
+            .iter()
+            .map(|(name, addr, kind)| (name.to_string(), addr, kind))
+            .collect())
+    }
+
+    /// The line each ROM word was assembled from, in the file it was written in,
+    /// with the words of a macro at its call
+    pub fn source_lines(&mut self) -> Result<Vec<SourceLine>, Error> {
+        self.ensure_assembled()?;
+        let locations = self.locations.as_deref().unwrap_or_default();
+        Ok(locations
+            .iter()
+            .map(|&span| SourceLine {
+                file: self.sources.file(span.file).name.clone(),
+                line: span.line,
+                text: self.sources.line(span).trim().to_string(),
+            })
+            .collect())
+    }
+
+    fn ensure_assembled(&mut self) -> Result<(), Error> {
+        if self.locations.is_none() {
+            self.assemble()?;
+        }
+        Ok(())
+    }
+
+    fn first_path(&mut self, program: &[Statement]) -> Vec<(Span, Diagnostic)> {
+        let mut diagnostics = Vec::new();
+        let mut constants = Vec::new();
+        let mut row = 0;
+        for statement in program {
+            match &statement.kind {
+                StatementKind::A(_) | StatementKind::C(_) => row += 1,
+                StatementKind::Label(sym) => {
+                    if let Err(message) = self.table.add_label(sym, row) {
+                        diagnostics.push(self.diagnostic(statement, message, None));
                     }
                 }
-                InstructionType::InstC => {}
+                StatementKind::Equ { name, value } => constants.push((name, value, statement)),
+                StatementKind::Include(_)
+                | StatementKind::MacroDef { .. }
+                | StatementKind::EndMacro
+                | StatementKind::MacroCall { .. } => {
+                    unreachable!("includes and macros are already expanded")
+                }
             }
         }
 
-        self.parser.reset();
+        let constants = constants
+            .into_iter()
+            .map(|(name, value, statement)| (name.as_str(), value, statement))
+            .collect();
+        for (statement, message, span) in self.table.resolve_constants(constants) {
+            diagnostics.push(self.diagnostic(statement, message, span));
+        }
+
+        diagnostics
     }
 
-    fn second_path(&mut self, writer: &mut impl io::Write) -> Result<(), io::Error> {
-        let mut need_ln = false;
-        let mut a_count = 0;
-        while self.parser.has_more_lines() {
-            self.parser.advance();
-            match self.parser.instruction_type() {
-                InstructionType::InstA => {
-                    if need_ln {
-                        writeln!(writer)?;
+    fn second_path(
+        &mut self,
+        program: &[Statement],
+        relocatable: bool,
+        mut diagnostics: Vec<(Span, Diagnostic)>,
+    ) -> Result<Vec<Word>, Error> {
+        let mut words = Vec::new();
+        diagnostics.extend(self.first_path(program));
+        let mut variables = memory::Variables::default();
+        for (i, statement) in program.iter().enumerate() {
+            let word = match &statement.kind {
+                StatementKind::A(AInstruction::Literal(value)) => Word::Value(*value),
+                StatementKind::A(AInstruction::Symbol(sym)) => match self.table.get(sym) {
+                    Some((addr, _)) => match self.table.moves(sym) {
+                        0 => Word::Value(u16::try_from(addr).unwrap()),
+                        1 => Word::Address(u16::try_from(addr).unwrap()),
+                        _ if !relocatable => Word::Value(u16::try_from(addr).unwrap()),
+                        _ => {
+                            diagnostics.push(self.diagnostic(
+                                statement,
+                                format!("`{}` can not be relocated", sym),
+                                None,
+                            ));
+                            continue;
+                        }
+                    },
+                    None if relocatable => {
+                        let next = program[i + 1..].iter().find(|s| s.kind.is_instruction());
+                        let jump = matches!(next, Some(Statement { kind: StatementKind::C(c), .. }) if c.jump.is_some());
+                        Word::Symbol {
+                            name: sym.clone(),
+                            jump,
+                        }
                     }
-                    let sym = self.parser.symbol();
-                    if let Ok(addr) = u16::from_str_radix(sym, 10) {
-                        write!(writer, "{:016b}", addr)?;
-                    } else {
-                        // use label
-                        if let Some(addr) = self.table.get_address(sym) {
-                            let addr = u16::try_from(addr).unwrap();
-                            write!(writer, "{:016b}", addr)?;
-                        } else {
-                            let addr = Self::RAM_ADDR_START + a_count;
-                            self.table.add_entry(sym, addr);
-                            a_count += 1;
-                            write!(writer, "{:016b}", addr)?;
+                    None => match variables.allocate(&self.memory_map, &mut self.table, sym) {
+                        Ok((addr, warning)) => {
+                            if let Some(message) = warning {
+                                let (key, d) = self.diagnostic(statement, message, None);
+                                diagnostics.push((key, d.warning()));
+                            }
+                            Word::Value(u16::try_from(addr).unwrap())
+                        }
+                        Err(message) => {
+                            diagnostics.push(self.diagnostic(statement, message, None));
+                            continue;
                         }
+                    },
+                },
+                StatementKind::A(AInstruction::Expr(expr)) => {
+                    // labels move with the module, so evaluate with the module at 0 and at 1
+                    // to tell ROM addresses from plain values
+                    let eval = |base: i64| {
+                        expr.eval(&|s: &str| {
+                            self.table
+                                .get_address(s)
+                                .map(|a| a as i64 + base * self.table.moves(s))
+                        })
                     };
-                }
-                InstructionType::InstC => {
-                    if need_ln {
-                        writeln!(writer)?;
+                    let value = match eval(0) {
+                        Ok(v) if !relocatable => Ok((v, false)),
+                        Ok(v) => match eval(1) {
+                            Ok(moved) if moved == v => Ok((v, false)),
+                            Ok(moved) if moved == v + 1 => Ok((v, true)),
+                            _ => Err(self.diagnostic(
+                                statement,
+                                "expression can not be relocated".to_string(),
+                                None,
+                            )),
+                        },
+                        Err(e) => Err(self.diagnostic(statement, e.message, e.span)),
+                    };
+                    match value {
+                        Ok((v, true)) if (0..=Self::MAX_VALUE).contains(&v) => {
+                            Word::Address(v as u16)
+                        }
+                        Ok((v, false)) if (0..=Self::MAX_VALUE).contains(&v) => {
+                            Word::Value(v as u16)
+                        }
+                        Ok((v, _)) => {
+                            diagnostics.push(self.diagnostic(
+                                statement,
+                                format!("value {} does not fit in 15 bits", v),
+                                None,
+                            ));
+                            continue;
+                        }
+                        Err(d) => {
+                            diagnostics.push(d);
+                            continue;
+                        }
                     }
-                    // dbg!(self.parser.peek_line());
-                    let comp = self.parser.comp();
-                    let dest = self.parser.dest();
-                    let jump = self.parser.jump();
-                    write!(
-                        writer,
-                        "111{}{}{}",
-                        code::comp(comp).unwrap(),
-                        code::dest(dest).unwrap(),
-                        code::jump(jump).unwrap()
-                    )?;
                 }
-                InstructionType::InstL => {
+                StatementKind::C(inst) => match self.encode_c(statement, inst) {
+                    Ok(word) => Word::Value(word),
+                    Err(mut errors) => {
+                        diagnostics.append(&mut errors);
+                        continue;
+                    }
+                },
+                StatementKind::Label(_) | StatementKind::Equ { .. } => {
                     // do nothing
+                    continue;
                 }
+                StatementKind::Include(_)
+                | StatementKind::MacroDef { .. }
+                | StatementKind::EndMacro
+                | StatementKind::MacroCall { .. } => {
+                    unreachable!("includes and macros are already expanded")
+                }
+            };
+            if words.len() as i64 == Self::MAX_VALUE + 1 {
+                diagnostics.push(self.diagnostic(
+                    statement,
+                    "program does not fit in the 32768 words of ROM".to_string(),
+                    None,
+                ));
             }
-            need_ln = true;
+            words.push(word);
         }
 
-        Ok(())
+        if self.lint {
+            diagnostics.extend(lint::lint(
+                program,
+                &self.table,
+                &self.sources,
+                &self.memory_map,
+            ));
+        }
+        diagnostics.sort_by_key(|(key, d)| (*key, d.columns.start));
+        let (warnings, errors): (Vec<_>, Vec<_>) = diagnostics
+            .into_iter()
+            .map(|(_, d)| d)
+            .partition(|d| d.severity == Severity::Warning);
+        let errors = if self.strict {
+            let promoted = warnings.into_iter().map(|d| Diagnostic {
+                severity: Severity::Error,
+                ..d
+            });
+            errors.into_iter().chain(promoted).collect()
+        } else {
+            self.warnings = warnings;
+            errors
+        };
+        if errors.is_empty() {
+            Ok(words)
+        } else {
+            Err(Error::Assemble(errors))
+        }
+    }
+
+    fn encode_c(
+        &self,
+        statement: &Statement,
+        inst: &CInstruction,
+    ) -> Result<u16, Vec<(Span, Diagnostic)>> {
+        code::encode_c(inst, self.isa).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|e| {
+                    let (key, d) = self.diagnostic(statement, e.message, Some(e.span));
+                    (key, d.with_suggestion(e.suggestion))
+                })
+                .collect()
+        })
+    }
+
+    /// A diagnostic at `span`, or the whole statement, keyed by where the statement
+    /// appears in the program so that errors inside macros sort by their call
+    fn diagnostic(
+        &self,
+        statement: &Statement,
+        message: String,
+        span: Option<Span>,
+    ) -> (Span, Diagnostic) {
+        let span = span.unwrap_or(statement.span);
+        let key = self.sources.location(statement, span);
+        let d = self.sources.statement_diagnostic(statement, message, span);
+        (key, d)
     }
 }
 
@@ -242,4 +635,344 @@ mod tests {
 
         assert_eq!(expected, String::from_utf8(buf).unwrap())
     }
+
+    #[test]
+    fn test_collect_all_errors() {
+        let source = "@1\nD=D+2\nD;JMPP\nX=A\n";
+
+        let mut asm = Assembler::new(source);
+        asm.set_file_name("Bad.asm");
+
+        let mut buf = Vec::new();
+        let Err(Error::Assemble(diagnostics)) = asm.write(&mut buf) else {
+            panic!("expected assemble error")
+        };
+
+        assert!(buf.is_empty());
+        assert_eq!(diagnostics.len(), 3);
+
+        assert_eq!(diagnostics[0].file, "Bad.asm");
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].columns, 3..6);
+        assert_eq!(diagnostics[0].text, "D+2");
+        assert_eq!(diagnostics[0].suggestion.as_deref(), Some("D+1"));
+
+        assert_eq!(diagnostics[1].line, 3);
+        assert_eq!(diagnostics[1].columns, 3..7);
+        assert_eq!(diagnostics[1].suggestion.as_deref(), Some("JMP"));
+
+        assert_eq!(diagnostics[2].line, 4);
+        assert_eq!(diagnostics[2].columns, 1..2);
+        assert_eq!(diagnostics[2].text, "X");
+    }
+
+    #[test]
+    fn test_symbols_round_trip() {
+        let mut asm = Assembler::new(include_str!("../asm/Max.asm"));
+        let mut symbols = Vec::new();
+        asm.write_symbols(&mut symbols).unwrap();
+        let symbols = String::from_utf8(symbols).unwrap();
+        assert!(symbols.contains("ITSR0       10 label\n"));
+
+        let map = disasm::SymbolMap::parse("Max.sym", &symbols).unwrap();
+        assert_eq!(
+            map.labels().collect::<Vec<_>>(),
+            vec![(10, "ITSR0"), (12, "OUTPUT_D"), (14, "END")]
+        );
+        let disassembly = disasm::disassemble(&asm.assemble().unwrap(), Some(&map), Isa::Standard);
+        assert!(disassembly.source.contains("@ITSR0\nD;JGT\n"));
+        assert!(disassembly.source.contains("@R2\nM=D\n"));
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut asm = Assembler::new(include_str!("../asm/Max.asm"));
+        let mut listing = Vec::new();
+        asm.write_listing(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert_eq!(
+            listing.lines().count(),
+            include_str!("../asm/Max.asm").lines().count()
+        );
+        assert!(listing.contains("   10  0000000000000000  0000    @R0\n"));
+        assert!(listing.contains("\n                               (ITSR0)\n"));
+    }
+
+    #[test]
+    fn test_listing_macro_call() {
+        let mut asm = Assembler::new("@1\nD=A\nPUSH_D\n@2\n");
+        let mut listing = Vec::new();
+        asm.write_listing(&mut listing).unwrap();
+        let expected = concat!(
+            "    0  0000000000000001  0001  @1\n",
+            "    1  1110110000010000  EC10  D=A\n",
+            "    2  0000000000000000  0000  PUSH_D\n",
+            "    3  1111110111001000  FDC8\n",
+            "    4  1111110010100000  FCA0\n",
+            "    5  1110001100001000  E308\n",
+            "    6  0000000000000010  0002  @2\n",
+        );
+        assert_eq!(String::from_utf8(listing).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_listing_include() {
+        let dir = std::env::temp_dir().join(format!("hack-listing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.asm"), "// lib\n(LIB)\n@LIB\n0;JMP\n").unwrap();
+        let mut asm = Assembler::new("@1\n.include \"lib.asm\"\n@2\n");
+        asm.add_include_path(&dir);
+        let mut listing = Vec::new();
+        asm.write_listing(&mut listing).unwrap();
+        let expected = format!(
+            concat!(
+                "    0  0000000000000001  0001  @1\n",
+                "                               .include \"lib.asm\"\n",
+                "    3  0000000000000010  0002  @2\n",
+                "\n",
+                "                               // {}, included from <input>:2\n",
+                "                               // lib\n",
+                "                               (LIB)\n",
+                "    1  0000000000000001  0001  @LIB\n",
+                "    2  1110101010000111  EA87  0;JMP\n",
+            ),
+            dir.join("lib.asm").display()
+        );
+        assert_eq!(String::from_utf8(listing).unwrap(), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_source_lines() {
+        let mut asm =
+            Assembler::new("(START)\n  @x  // first\nD=M\n.macro TWO\nD=0\nD=1\n.endm\nTWO\n");
+        let lines = asm.source_lines().unwrap();
+        assert_eq!(
+            lines
+                .iter()
+                .map(|l| (l.line, l.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(2, "@x  // first"), (3, "D=M"), (8, "TWO"), (8, "TWO")]
+        );
+        let symbols = asm.symbols().unwrap();
+        assert!(symbols.contains(&("START".to_string(), 0, SymbolKind::Label)));
+        assert!(symbols.contains(&("x".to_string(), 16, SymbolKind::Variable)));
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let source = "\
+.equ ROW_END ROW+32
+.equ ROW SCREEN+0x20*4
+@ROW_END
+@TABLE_END-TABLE
+(TABLE)
+@'A'
+@0b11
+(TABLE_END)
+@i
+";
+        let words = Assembler::new(source).assemble().unwrap();
+        assert_eq!(words, vec![16384 + 128 + 32, 2, 65, 3, 16]);
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let source = "\
+.equ BIG 0x8000
+.equ LOOP1 LOOP2
+.equ LOOP2 LOOP1
+@-1
+@SCREEN*2
+@i+1
+";
+        let Err(Error::Assemble(diagnostics)) = Assembler::new(source).assemble() else {
+            panic!("expected assemble error")
+        };
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (1, "value 32768 of `BIG` does not fit in 15 bits"),
+                (
+                    2,
+                    "can not resolve `LOOP1`: unknown symbol `LOOP2` in expression"
+                ),
+                (
+                    3,
+                    "can not resolve `LOOP2`: unknown symbol `LOOP1` in expression"
+                ),
+                (4, "value -1 does not fit in 15 bits"),
+                (5, "value 32768 does not fit in 15 bits"),
+                (6, "unknown symbol `i` in expression"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_checks() {
+        assert!(Assembler::new("@32767\n").assemble().is_ok());
+        // the word would read as a C-instruction
+        for (source, message) in [
+            ("@40000\n", "value 40000 does not fit in 15 bits"),
+            ("@0x7fff+1\n", "value 32768 does not fit in 15 bits"),
+        ] {
+            let Err(Error::Assemble(diagnostics)) = Assembler::new(source).assemble() else {
+                panic!("expected assemble error")
+            };
+            assert_eq!(diagnostics[0].message, message);
+        }
+    }
+
+    #[test]
+    fn test_variables_past_screen() {
+        // variables 16..=16383 fit below the screen
+        let source = (0..=16368)
+            .map(|i| format!("@v{}\n", i))
+            .collect::<String>();
+        let mut asm = Assembler::new(&source);
+        let words = asm.assemble().unwrap();
+        assert_eq!(words[16368], 16384);
+        assert_eq!(asm.warnings().len(), 1);
+        assert_eq!(asm.warnings()[0].severity, Severity::Warning);
+        assert_eq!(asm.warnings()[0].line, 16369);
+        assert!(
+            asm.warnings()[0]
+                .to_string()
+                .starts_with("warning: variable `v16368`")
+        );
+
+        asm.set_strict(true);
+        let Err(Error::Assemble(diagnostics)) = asm.assemble() else {
+            panic!("expected assemble error")
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(asm.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_variables_on_devices() {
+        let warnings = |map: &str| {
+            let mut asm = Assembler::new("@a\n@b\n@c\n@d\n@e\n@f\n");
+            asm.set_memory_map(memory::MemoryMap::parse("board.map", map).unwrap());
+            asm.assemble().unwrap();
+            asm.warnings()
+                .iter()
+                .map(|w| (w.line, w.message.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            warnings("SCREEN = 20\nKBD = 22\nvariables = 18..30\n"),
+            [
+                (
+                    3,
+                    "variable `c` is allocated at 20, where the screen memory map starts".into()
+                ),
+                (
+                    5,
+                    "variable `e` is allocated at 22, where the keyboard register starts".into()
+                ),
+            ]
+        );
+        assert_eq!(
+            warnings("SCREEN = 20\nKBD = 40\nvariables = 21..30\n"),
+            [(
+                1,
+                "variable `a` is allocated at 21, past the start of the screen memory map".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "\
+MOV R0, R1
+POP_D
+JUMP_IF_ZERO END
+(END)
+";
+        let expected = "@R0\nD=M\n@R1\nM=D\n@SP\nAM=M-1\nD=M\n@END\nD;JEQ\n(END)\n";
+        let mut asm = Assembler::new(source);
+        assert_eq!(
+            asm.assemble().unwrap(),
+            Assembler::new(expected).assemble().unwrap()
+        );
+
+        let mut listing = Vec::new();
+        asm.write_listing(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.starts_with("    0  0000000000000000  0000  MOV R0, R1\n"));
+        assert_eq!(listing.matches("MOV R0, R1").count(), 1);
+    }
+
+    #[test]
+    fn test_macro_error_notes() {
+        let source = "\
+.macro BAD
+D=D+2
+.endm
+@0
+BAD
+";
+        let mut asm = Assembler::new(source);
+        asm.set_file_name("Bad.asm");
+        let Err(Error::Assemble(diagnostics)) = asm.assemble() else {
+            panic!("expected assemble error")
+        };
+        let expected = "\
+error: invalid comp `D+2`
+ --> Bad.asm:2:3
+  |
+2 | D=D+2
+  |   ^^^
+  = help: did you mean `D+1`?
+note: in this expansion of macro `BAD`
+ --> Bad.asm:5:1
+  |
+5 | BAD
+  | ^^^";
+        assert_eq!(diagnostics[0].to_string(), expected);
+    }
+
+    #[test]
+    fn test_extended_isa() {
+        let source = "D=D<<\nAM=M>>;JNE\nD=A>>\n";
+        let mut asm = Assembler::new(source);
+        let Err(Error::Assemble(errors)) = asm.assemble() else {
+            panic!("shifts are not standard");
+        };
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].message,
+            "`D<<` is only in the extended instruction set"
+        );
+
+        asm.set_isa(Isa::Extended);
+        assert_eq!(
+            asm.assemble().unwrap(),
+            vec![0b1010110000010000, 0b1011000000101101, 0b1010000000010000]
+        );
+        let Err(Error::Assemble(errors)) = Assembler::new("D=D<<1\n").assemble() else {
+            panic!("not a shift");
+        };
+        assert_eq!(errors[0].message, "invalid comp `D<<1`");
+    }
+
+    #[test]
+    fn test_multiple_files() {
+        let mut asm = Assembler::new("(.end)\n@MULT\n0;JMP\n@.end\n");
+        asm.set_file_name("main.asm");
+        asm.add_file("mult.asm", "(MULT)\n(.end)\n@.end\n0;JMP\n");
+        assert_eq!(asm.assemble().unwrap(), vec![3, 0xea87, 0, 3, 0xea87]);
+
+        let mut listing = Vec::new();
+        asm.write_listing(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.contains("    3  0000000000000011  0003  @.end\n"));
+        assert_eq!(listing.lines().count(), 8);
+    }
 }
@@ -0,0 +1,372 @@
+//! Checks for code which assembles but is probably wrong
+//!
+//! Each lint has a name, and a warning is not reported for a line whose
+//! comment contains `lint:allow(NAME)`, or `lint:allow(NAME, OTHER)` for
+//! several lints.
+
+use std::collections::BTreeMap;
+
+use crate::ast::{AInstruction, Expr, Span, Statement, StatementKind};
+use crate::error::{self, Diagnostic};
+use crate::memory::MemoryMap;
+use crate::source::SourceMap;
+use crate::symbol::{SymbolKind, SymbolTable};
+
+/// Name and description of every lint
+pub const LINTS: &[(&str, &str)] = &[
+    (
+        "single-use-variable",
+        "a variable used only once, often a misspelled label",
+    ),
+    ("unused-label", "a label which is never referred to"),
+    (
+        "mmio-write",
+        "writing `M` right after loading an address of the screen or keyboard",
+    ),
+    (
+        "a-dest-jump",
+        "a jump which also assigns `A`, so the target is not what `A` is set to",
+    ),
+    (
+        "unreachable-code",
+        "instructions after an unconditional jump with no label to reach them",
+    ),
+    (
+        "duplicate-label",
+        "a label defined again, where the first definition wins",
+    ),
+];
+
+/// Run every lint over the expanded `program` for a computer with memory map
+/// `map`, returning warnings keyed by where they appear in the program
+pub(crate) fn lint(
+    program: &[Statement],
+    table: &SymbolTable,
+    sources: &SourceMap,
+    map: &MemoryMap,
+) -> Vec<(Span, Diagnostic)> {
+    let mut linter = Linter {
+        sources,
+        screen: map.symbols.get("SCREEN").copied(),
+        kbd: map.symbols.get("KBD").copied(),
+        warnings: Vec::new(),
+    };
+    linter.symbols(program, table);
+    linter.duplicate_labels(program);
+    linter.instructions(program, table);
+    linter
+        .warnings
+        .sort_by_key(|(key, d)| (*key, d.columns.start));
+    linter.warnings
+}
+
+struct Linter<'a> {
+    sources: &'a SourceMap,
+    screen: Option<usize>,
+    kbd: Option<usize>,
+    warnings: Vec<(Span, Diagnostic)>,
+}
+
+impl Linter<'_> {
+    fn warn(
+        &mut self,
+        lint: &'static str,
+        statement: &Statement,
+        message: String,
+        suggestion: Option<&str>,
+    ) {
+        if self.allowed(lint, statement) {
+            return;
+        }
+        let mut d = self
+            .sources
+            .statement_diagnostic(statement, message, statement.span)
+            .warning()
+            .with_suggestion(suggestion);
+        d.lint = Some(lint);
+        let key = self.sources.location(statement, statement.span);
+        self.warnings.push((key, d));
+    }
+
+    /// Whether the line of `statement`, or of a macro call it was expanded from,
+    /// allows `lint`
+    fn allowed(&self, lint: &str, statement: &Statement) -> bool {
+        std::iter::once(statement.span)
+            .chain(statement.expansion.iter().map(|e| e.call))
+            .any(|span| allows(self.sources.line(span), lint))
+    }
+
+    fn symbols(&mut self, program: &[Statement], table: &SymbolTable) {
+        // every use of each symbol, in program order
+        let mut uses = BTreeMap::<&str, Vec<&Statement>>::new();
+        for statement in program {
+            match &statement.kind {
+                StatementKind::A(AInstruction::Symbol(name)) => {
+                    uses.entry(name).or_default().push(statement)
+                }
+                StatementKind::A(AInstruction::Expr(expr))
+                | StatementKind::Equ { value: expr, .. } => {
+                    for name in symbols(expr) {
+                        uses.entry(name).or_default().push(statement);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let labels = table
+            .iter()
+            .filter(|(_, _, kind)| *kind == SymbolKind::Label)
+            .map(|(name, _, _)| name)
+            .collect::<Vec<_>>();
+        for (name, statements) in &uses {
+            let variable = matches!(table.get(name), None | Some((_, SymbolKind::Variable)));
+            if variable && statements.len() == 1 {
+                self.warn(
+                    "single-use-variable",
+                    statements[0],
+                    format!("variable `{}` is used only once", name),
+                    error::closest(name, &labels),
+                );
+            }
+        }
+
+        for statement in program {
+            if let StatementKind::Label(name) = &statement.kind
+                && !uses.contains_key(name.as_str())
+            {
+                self.warn(
+                    "unused-label",
+                    statement,
+                    format!("label `{}` is never used", name),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn duplicate_labels(&mut self, program: &[Statement]) {
+        let mut defined = BTreeMap::new();
+        for statement in program {
+            let StatementKind::Label(name) = &statement.kind else {
+                continue;
+            };
+            if let Some(first) = defined.get(name.as_str()) {
+                let before = self.warnings.len();
+                self.warn(
+                    "duplicate-label",
+                    statement,
+                    format!(
+                        "label `{}` is already defined, so this one is ignored",
+                        name
+                    ),
+                    None,
+                );
+                if let Some((_, d)) = self.warnings.get_mut(before) {
+                    d.notes
+                        .push(self.sources.note("first defined here".to_string(), *first));
+                }
+            } else {
+                defined.insert(name.as_str(), statement.span);
+            }
+        }
+    }
+
+    /// Lints looking at each instruction and the one before it
+    fn instructions(&mut self, program: &[Statement], table: &SymbolTable) {
+        let mut previous: Option<&Statement> = None;
+        let mut after_jump = false;
+        for statement in program {
+            match &statement.kind {
+                StatementKind::Label(_) => {
+                    after_jump = false;
+                    previous = None;
+                    continue;
+                }
+                kind if !kind.is_instruction() => continue,
+                _ => {}
+            }
+            if after_jump {
+                self.warn(
+                    "unreachable-code",
+                    statement,
+                    "unreachable instruction after an unconditional jump".to_string(),
+                    None,
+                );
+                after_jump = false;
+            }
+
+            if let StatementKind::C(c) = &statement.kind {
+                let dest = c.dest.as_ref().map_or("", |m| m.text.as_str());
+                let jump = c.jump.as_ref().map_or("", |m| m.text.as_str());
+                if dest.contains('M')
+                    && let Some(address) = previous.and_then(|p| a_value(p, table))
+                {
+                    if Some(address) == self.kbd {
+                        self.warn(
+                            "mmio-write",
+                            statement,
+                            "write to `KBD`, which the keyboard overwrites".to_string(),
+                            None,
+                        );
+                    } else if let (Some(screen), Some(kbd)) = (self.screen, self.kbd)
+                        && (screen..kbd).contains(&address)
+                    {
+                        self.warn(
+                            "mmio-write",
+                            statement,
+                            format!("direct write to screen memory at {}", address),
+                            None,
+                        );
+                    }
+                }
+                if dest.contains('A') && !jump.is_empty() {
+                    self.warn(
+                        "a-dest-jump",
+                        statement,
+                        format!(
+                            "`{}` jumps to the old value of `A`, not the one it assigns",
+                            c.comp.text
+                        ),
+                        None,
+                    );
+                }
+                after_jump = always_jumps(&c.comp.text, jump);
+            }
+            previous = Some(statement);
+        }
+    }
+}
+
+/// Whether a `lint:allow(...)` comment on `line` names `lint`
+fn allows(line: &str, lint: &str) -> bool {
+    let Some((_, comment)) = line.split_once("//") else {
+        return false;
+    };
+    comment.match_indices("lint:allow(").any(|(i, m)| {
+        let names = &comment[i + m.len()..];
+        let names = names.split(')').next().unwrap_or("");
+        names.split(',').any(|n| n.trim() == lint)
+    })
+}
+
+fn symbols(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::Number(_) => Vec::new(),
+        Expr::Symbol(name, _) => vec![name],
+        Expr::Neg(e) => symbols(e),
+        Expr::Binary(_, lhs, rhs) => {
+            let mut names = symbols(lhs);
+            names.extend(symbols(rhs));
+            names
+        }
+    }
+}
+
+/// The value an A-instruction loads, if known without allocating variables
+fn a_value(statement: &Statement, table: &SymbolTable) -> Option<usize> {
+    match &statement.kind {
+        StatementKind::A(AInstruction::Literal(v)) => Some(usize::from(*v)),
+        StatementKind::A(AInstruction::Symbol(name)) => match table.get(name)? {
+            (_, SymbolKind::Variable | SymbolKind::Label) => None,
+            (addr, _) => Some(addr),
+        },
+        StatementKind::A(AInstruction::Expr(expr)) => {
+            let value = expr.eval(&|s: &str| table.get_address(s).map(|a| a as i64));
+            value.ok().and_then(|v| usize::try_from(v).ok())
+        }
+        _ => None,
+    }
+}
+
+/// Whether `comp;jump` jumps whatever the registers hold
+pub(crate) fn always_jumps(comp: &str, jump: &str) -> bool {
+    matches!(
+        (comp, jump),
+        (_, "JMP")
+            | ("0", "JEQ" | "JGE" | "JLE")
+            | ("1", "JGT" | "JGE" | "JNE")
+            | ("-1", "JLT" | "JLE" | "JNE")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Assembler;
+    use crate::memory::MemoryMap;
+
+    fn lints(source: &str) -> Vec<(usize, &'static str)> {
+        let mut asm = Assembler::new(source);
+        asm.set_lint(true);
+        asm.assemble().unwrap();
+        asm.warnings()
+            .iter()
+            .map(|d| (d.line, d.lint.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lints() {
+        let source = "\
+(LOOP)
+@LOPP
+0;JMP
+@SCREEN
+M=-1
+@KBD
+M=0
+(LOOP)
+@LOOP
+AM=M-1;JNE
+(UNUSED)
+";
+        assert_eq!(
+            lints(source),
+            vec![
+                (2, "single-use-variable"),
+                (4, "unreachable-code"),
+                (5, "mmio-write"),
+                (7, "mmio-write"),
+                (8, "duplicate-label"),
+                (10, "a-dest-jump"),
+                (11, "unused-label"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut asm = Assembler::new("@16384\nM=1\n@20480\nM=1\n@28672\nM=0\n@KBD\nM=0\n");
+        asm.set_memory_map(
+            MemoryMap::parse("board.map", "SCREEN = 0x5000\nKBD = 0x7000\n").unwrap(),
+        );
+        asm.set_lint(true);
+        asm.assemble().unwrap();
+        let warnings = asm.warnings().iter().map(|d| d.line).collect::<Vec<_>>();
+        assert_eq!(warnings, [4, 6, 8]);
+        assert!(asm.warnings()[1].message.starts_with("write to `KBD`"));
+    }
+
+    #[test]
+    fn test_allow() {
+        let source = "\
+@once // lint:allow(single-use-variable)
+(END) // lint:allow(unused-label, mmio-write)
+@END
+0;JMP
+@x // lint:allow(unused-label)
+M=0
+@x
+";
+        assert_eq!(lints(source), vec![(5, "unreachable-code")]);
+    }
+
+    #[test]
+    fn test_clean_program() {
+        assert!(lints(include_str!("../asm/Max.asm")).is_empty());
+        let mut asm = Assembler::new("@LOPP\n(LOOP)\n@LOOP\n0;JMP\n");
+        asm.set_lint(true);
+        asm.assemble().unwrap();
+        assert_eq!(asm.warnings()[0].suggestion.as_deref(), Some("LOOP"));
+    }
+}
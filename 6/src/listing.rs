@@ -0,0 +1,59 @@
+use std::io;
+
+/// Write every source line, prefixed by the ROM address, binary and hex encoding
+/// of the instruction on that line
+///
+/// `words` holds the ROM address, 1-based source line and encoding of each word
+/// from `source`, in order. A line with several words, such as a macro call,
+/// gets one row per word with the source shown on the first.
+pub(crate) fn write_listing(
+    writer: &mut impl io::Write,
+    source: &str,
+    words: &[(usize, usize, u16)],
+) -> io::Result<()> {
+    let mut rom = words.iter().peekable();
+    for (i, line) in source.lines().enumerate() {
+        let mut text = line;
+        let mut listed = false;
+        while let Some((addr, _, word)) = rom.next_if(|(_, l, _)| *l == i + 1) {
+            let row = format!("{:5}  {:016b}  {:04X}  {}", addr, word, word, text);
+            writeln!(writer, "{}", row.trim_end())?;
+            text = "";
+            listed = true;
+        }
+        if !listed {
+            let row = format!("{:5}  {:16}  {:4}  {}", "", "", "", line);
+            writeln!(writer, "{}", row.trim_end())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_listing() {
+        let source = "// add\n@2\nD=A\n\n(END)\n@END\n0;JMP";
+        let words = [
+            (0, 2, 0x0002),
+            (1, 3, 0xec10),
+            (2, 6, 0x0002),
+            (3, 7, 0xea87),
+        ];
+
+        let mut buf = Vec::new();
+        write_listing(&mut buf, source, &words).unwrap();
+        let expected = concat!(
+            "                               // add\n",
+            "    0  0000000000000010  0002  @2\n",
+            "    1  1110110000010000  EC10  D=A\n",
+            "\n",
+            "                               (END)\n",
+            "    2  0000000000000010  0002  @END\n",
+            "    3  1110101010000111  EA87  0;JMP\n",
+        );
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+}
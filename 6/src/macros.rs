@@ -0,0 +1,403 @@
+//! Expansion of `.macro` definitions
+//!
+//! Labels defined inside a macro body are local to each expansion, so a macro
+//! with a loop can be used more than once. Like file-local labels, their new
+//! names start with `.`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ast::{AInstruction, Expansion, Expr, Span, Statement, StatementKind};
+use crate::error::{self, Diagnostic};
+use crate::source::SourceMap;
+
+/// Macros available to every program
+pub(crate) const BUILTIN: &str = include_str!("builtin.asm");
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Statement>,
+    /// Labels defined in the body
+    labels: BTreeSet<String>,
+    span: Span,
+}
+
+/// Replace every macro call in `program` by the body of its definition
+///
+/// Definitions in `program` take precedence over those in `builtins`.
+pub(crate) fn expand(
+    program: Vec<Statement>,
+    builtins: Vec<Statement>,
+    sources: &SourceMap,
+) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let mut expander = Expander {
+        macros: BTreeMap::new(),
+        sources,
+        count: 0,
+        diagnostics: Vec::new(),
+    };
+    expander.collect(builtins, false);
+    let program = expander.collect(program, true);
+
+    let mut out = Vec::new();
+    for statement in program {
+        expander.expand_statement(statement, &mut Vec::new(), &mut out);
+    }
+
+    if expander.diagnostics.is_empty() {
+        Ok(out)
+    } else {
+        expander.diagnostics.sort_by_key(|(key, _)| *key);
+        Err(expander.diagnostics.into_iter().map(|(_, d)| d).collect())
+    }
+}
+
+struct Expander<'a> {
+    macros: BTreeMap<String, Macro>,
+    sources: &'a SourceMap,
+    /// Number of expansions so far, used to make local labels unique
+    count: usize,
+    /// Each diagnostic with the location in the program it is sorted by
+    diagnostics: Vec<(Span, Diagnostic)>,
+}
+
+impl Expander<'_> {
+    /// Move the definitions out of `statements`, returning the remaining statements
+    fn collect(&mut self, statements: Vec<Statement>, user: bool) -> Vec<Statement> {
+        let mut defined_here = BTreeMap::new();
+        let mut rest = Vec::new();
+        let mut statements = statements.into_iter();
+        while let Some(statement) = statements.next() {
+            let (name, params) = match &statement.kind {
+                StatementKind::MacroDef { name, params } => (name.clone(), params.clone()),
+                StatementKind::EndMacro => {
+                    self.error(&statement, "`.endm` without `.macro`".to_string());
+                    continue;
+                }
+                _ => {
+                    rest.push(statement);
+                    continue;
+                }
+            };
+
+            let mut body = Vec::new();
+            let mut terminated = false;
+            for inner in statements.by_ref() {
+                match inner.kind {
+                    StatementKind::EndMacro => {
+                        terminated = true;
+                        break;
+                    }
+                    StatementKind::MacroDef { .. } => {
+                        self.error(&inner, "macro definitions can not be nested".to_string())
+                    }
+                    _ => body.push(inner),
+                }
+            }
+            if !terminated {
+                self.error(&statement, format!("macro `{}` is missing `.endm`", name));
+            }
+
+            if let Some(previous) = defined_here.insert(name.clone(), statement.span) {
+                let mut d = self.sources.diagnostic(
+                    format!("macro `{}` is already defined", name),
+                    statement.span,
+                );
+                d.notes.push(
+                    self.sources
+                        .note("previous definition here".to_string(), previous),
+                );
+                self.diagnostics.push((self.location(&statement), d));
+                continue;
+            }
+            if user && self.macros.contains_key(&name) {
+                // replaces a built-in macro
+                self.macros.remove(&name);
+            }
+            let labels = body
+                .iter()
+                .filter_map(|s| match &s.kind {
+                    StatementKind::Label(label) => Some(label.clone()),
+                    _ => None,
+                })
+                .collect();
+            self.macros.insert(
+                name,
+                Macro {
+                    params,
+                    body,
+                    labels,
+                    span: statement.span,
+                },
+            );
+        }
+        rest
+    }
+
+    /// Push `statement` to `out`, expanding it first if it is a macro call
+    ///
+    /// `active` holds the macros currently being expanded, to catch recursion.
+    fn expand_statement(
+        &mut self,
+        statement: Statement,
+        active: &mut Vec<String>,
+        out: &mut Vec<Statement>,
+    ) {
+        let StatementKind::MacroCall { name, args } = &statement.kind else {
+            out.push(statement);
+            return;
+        };
+        let Some(mac) = self.macros.get(name) else {
+            let names = self.macros.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+            let suggestion = error::closest(name, &names);
+            let d = self
+                .sources
+                .statement_diagnostic(
+                    &statement,
+                    format!("unknown macro `{}`", name),
+                    statement.span,
+                )
+                .with_suggestion(suggestion);
+            self.diagnostics.push((self.location(&statement), d));
+            return;
+        };
+        let (n_params, definition) = (mac.params.len(), mac.span);
+        if args.len() != n_params {
+            let message = format!(
+                "macro `{}` takes {} argument{} but {} {} given",
+                name,
+                n_params,
+                if n_params == 1 { "" } else { "s" },
+                args.len(),
+                if args.len() == 1 { "was" } else { "were" },
+            );
+            self.error_at_definition(&statement, message, definition);
+            return;
+        }
+        if active.contains(name) {
+            let message = format!("macro `{}` expands itself recursively", name);
+            self.error_at_definition(&statement, message, definition);
+            return;
+        }
+
+        self.count += 1;
+        let mac = &self.macros[name];
+        let params = mac.params.iter().cloned().zip(args.iter().cloned());
+        let substitution = Substitution {
+            args: params.collect(),
+            labels: &mac.labels,
+            prefix: format!(".{}${}$", name, self.count),
+        };
+        let mut expansion = vec![Expansion {
+            name: name.clone(),
+            call: statement.span,
+        }];
+        expansion.extend(statement.expansion.iter().cloned());
+        let body = mac
+            .body
+            .iter()
+            .map(|s| Statement {
+                kind: substitution.statement(&s.kind),
+                span: s.span,
+                expansion: expansion.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        active.push(name.clone());
+        for s in body {
+            self.expand_statement(s, active, out);
+        }
+        active.pop();
+    }
+
+    /// Where a statement appears in the program as written, used to sort diagnostics
+    fn location(&self, statement: &Statement) -> Span {
+        self.sources.location(statement, statement.span)
+    }
+
+    fn error(&mut self, statement: &Statement, message: String) {
+        let d = self
+            .sources
+            .statement_diagnostic(statement, message, statement.span);
+        self.diagnostics.push((self.location(statement), d));
+    }
+
+    /// Report an error at a call, pointing to the definition of the macro too
+    fn error_at_definition(&mut self, call: &Statement, message: String, definition: Span) {
+        let mut d = self.sources.statement_diagnostic(call, message, call.span);
+        let name = match &call.kind {
+            StatementKind::MacroCall { name, .. } => name,
+            _ => unreachable!("only macro calls refer to a definition"),
+        };
+        d.notes.insert(
+            0,
+            self.sources
+                .note(format!("macro `{}` defined here", name), definition),
+        );
+        self.diagnostics.push((self.location(call), d));
+    }
+}
+
+/// Rewrites a macro body for one expansion
+struct Substitution<'a> {
+    /// Parameter name -> argument
+    args: BTreeMap<String, Expr>,
+    labels: &'a BTreeSet<String>,
+    /// Prepended to local labels
+    prefix: String,
+}
+
+impl Substitution<'_> {
+    fn statement(&self, kind: &StatementKind) -> StatementKind {
+        match kind {
+            StatementKind::A(AInstruction::Symbol(name)) => match self.args.get(name) {
+                Some(arg) => StatementKind::A(AInstruction::from_expr(arg.clone())),
+                None => StatementKind::A(AInstruction::Symbol(self.label(name))),
+            },
+            StatementKind::A(AInstruction::Expr(expr)) => {
+                StatementKind::A(AInstruction::from_expr(self.expr(expr)))
+            }
+            StatementKind::Label(name) => StatementKind::Label(self.label(name)),
+            StatementKind::Equ { name, value } => StatementKind::Equ {
+                name: name.clone(),
+                value: self.expr(value),
+            },
+            StatementKind::MacroCall { name, args } => StatementKind::MacroCall {
+                name: name.clone(),
+                args: args.iter().map(|a| self.expr(a)).collect(),
+            },
+            kind => kind.clone(),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Expr {
+        match expr {
+            Expr::Number(n) => Expr::Number(*n),
+            Expr::Symbol(name, span) => match self.args.get(name) {
+                Some(arg) => arg.clone(),
+                None => Expr::Symbol(self.label(name), *span),
+            },
+            Expr::Neg(e) => Expr::Neg(Box::new(self.expr(e))),
+            Expr::Binary(op, lhs, rhs) => {
+                Expr::Binary(*op, Box::new(self.expr(lhs)), Box::new(self.expr(rhs)))
+            }
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        if self.labels.contains(name) {
+            format!("{}{}", self.prefix, name)
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn expand_source(source: &str) -> Result<Vec<StatementKind>, Vec<Diagnostic>> {
+        let mut sources = SourceMap::default();
+        sources.add("test.asm", source);
+        let program = Parser::new("test.asm", source).parse().unwrap();
+        let statements = expand(program, Vec::new(), &sources)?;
+        Ok(statements.into_iter().map(|s| s.kind).collect())
+    }
+
+    fn symbol(name: &str) -> StatementKind {
+        StatementKind::A(AInstruction::Symbol(name.to_string()))
+    }
+
+    #[test]
+    fn test_expand_with_local_labels() {
+        let source = "\
+.macro WAIT n
+(LOOP)
+@n
+D=D-A
+@LOOP
+D;JGT
+.endm
+WAIT 3
+WAIT count
+";
+        let found = expand_source(source).unwrap();
+        let labels = found
+            .iter()
+            .filter_map(|s| match s {
+                StatementKind::Label(l) => Some(l.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec![".WAIT$1$LOOP", ".WAIT$2$LOOP"]);
+        assert_eq!(found[1], StatementKind::A(AInstruction::Literal(3)));
+        assert_eq!(found[3], symbol(".WAIT$1$LOOP"));
+        assert_eq!(found[6], symbol("count"));
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let source = "\
+.macro INNER x
+@x
+.endm
+.macro OUTER y
+INNER y+1
+INNER y
+.endm
+OUTER z
+";
+        let mut sources = SourceMap::default();
+        sources.add("test.asm", source);
+        let program = Parser::new("test.asm", source).parse().unwrap();
+        let statements = expand(program, Vec::new(), &sources).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert!(matches!(
+            statements[0].kind,
+            StatementKind::A(AInstruction::Expr(_))
+        ));
+        assert_eq!(statements[1].kind, symbol("z"));
+        let chain = statements[1]
+            .expansion
+            .iter()
+            .map(|e| (e.name.as_str(), e.call.line))
+            .collect::<Vec<_>>();
+        assert_eq!(chain, vec![("INNER", 6), ("OUTER", 8)]);
+    }
+
+    #[test]
+    fn test_expansion_errors() {
+        let source = "\
+.macro TWO a, b
+@a
+.endm
+.macro SELF
+SELF
+.endm
+TWO 1
+TWOO 1, 2
+SELF
+.endm
+.macro OPEN
+";
+        let diagnostics = expand_source(source).unwrap_err();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str(), d.notes.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (7, "macro `TWO` takes 2 arguments but 1 was given", 1),
+                (8, "unknown macro `TWOO`", 0),
+                (5, "macro `SELF` expands itself recursively", 2),
+                (10, "`.endm` without `.macro`", 0),
+                (11, "macro `OPEN` is missing `.endm`", 0),
+            ]
+        );
+        assert_eq!(diagnostics[0].notes[0].line, 1);
+        assert_eq!(diagnostics[1].suggestion.as_deref(), Some("TWO"));
+        assert_eq!(diagnostics[2].notes[1].line, 9);
+    }
+}
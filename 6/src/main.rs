@@ -1,32 +1,219 @@
-use std::io::Read;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
 
-use assembler::Assembler;
+use assembler::memory::MemoryMap;
+use assembler::{Assembler, Diagnostic, Error, Isa, OutputFormat, fmt, stream};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: assembler [-c | --format {}] [--isa {}] [-O] [--memory-map FILE] [--strict] [--lint] [--symbols FILE] [--listing FILE] [-I DIR]... \
+         [-o OUTPUT] [INPUT.asm]...\n       assembler INPUT.asm OUTPUT.hack\n       \
+         assembler --canonical [-o OUTPUT] [INPUT.asm]\n       \
+         assembler --stream [--format F] [--isa I] [--memory-map FILE] [-o OUTPUT] [INPUT.asm]",
+        OutputFormat::NAMES.join("|"),
+        Isa::NAMES.join("|")
+    );
+    std::process::exit(2)
+}
+
+/// Write `bytes` to `path`, or to stdout
+///
+/// A file is written beside `path` and renamed over it, so an output which is
+/// also an input is replaced only once everything has been read.
+fn report(diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprintln!("{}\n", d);
+    }
+    let s = if diagnostics.len() == 1 { "" } else { "s" };
+    eprintln!(
+        "error: could not assemble due to {} error{}",
+        diagnostics.len(),
+        s
+    );
+    std::process::exit(1)
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> std::io::Result<()> {
+    let Some(path) = path else {
+        return std::io::stdout().write_all(bytes);
+    };
+    let mut temp = Path::new(path).as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, bytes)?;
+    std::fs::rename(&temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = std::env::args().collect::<Vec<_>>();
-    let mut asm = if args.len() == 1 {
+    let mut format = None;
+    let mut isa = None;
+    let mut object = false;
+    let mut strict = false;
+    let mut lint = false;
+    let mut optimize = false;
+    let mut canonical = false;
+    let mut streaming = false;
+    let mut symbols_path = None;
+    let mut memory_map_path = None;
+    let mut listing_path = None;
+    let mut include_paths = Vec::new();
+    let mut out_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                format = Some(name.parse()?);
+            }
+            "--isa" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                isa = Some(name.parse()?);
+            }
+            "--memory-map" => memory_map_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => symbols_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--listing" => listing_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-c" => object = true,
+            "--strict" => strict = true,
+            "--lint" => lint = true,
+            "-O" | "--optimize" => optimize = true,
+            "--canonical" => canonical = true,
+            "--stream" => streaming = true,
+            "-I" => include_paths.push(args.next().unwrap_or_else(|| usage())),
+            "-o" => out_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    // --canonical and --stream would otherwise ignore these
+    let whole_program = object
+        || strict
+        || lint
+        || optimize
+        || !include_paths.is_empty()
+        || symbols_path.is_some()
+        || listing_path.is_some();
+    let encoding = format.is_some() || isa.is_some() || memory_map_path.is_some();
+    if (canonical || streaming) && whole_program
+        || canonical && (streaming || encoding)
+        || object && format.is_some()
+    {
+        usage();
+    }
+    let format = format.unwrap_or(OutputFormat::Text);
+    let isa = isa.unwrap_or(Isa::Standard);
+
+    // `assembler Prog.asm Prog.hack` names its output; any other run of several files needs `-o`
+    if out_path.is_none() && paths.len() > 1 {
+        match paths.as_slice() {
+            [_, output] if output.ends_with(".hack") => out_path = paths.pop(),
+            _ => usage(),
+        }
+    }
+
+    let memory_map = match &memory_map_path {
+        Some(path) => {
+            let source = std::fs::read_to_string(path)?;
+            MemoryMap::parse(path, &source).unwrap_or_else(|d| report(&d))
+        }
+        None => MemoryMap::default(),
+    };
+
+    if canonical {
+        let (name, source) = match paths.as_slice() {
+            [] => {
+                let mut source = String::new();
+                std::io::stdin().read_to_string(&mut source)?;
+                ("<input>", source)
+            }
+            [input] => (input.as_str(), std::fs::read_to_string(input)?),
+            _ => usage(),
+        };
+        match fmt::canonicalize(name, &source) {
+            Ok(text) => write_output(out_path.as_deref(), text.as_bytes())?,
+            Err(diagnostics) => {
+                for d in &diagnostics {
+                    eprintln!("{}\n", d);
+                }
+                std::process::exit(1)
+            }
+        }
+        return Ok(());
+    }
+
+    // assemble line by line without keeping the source
+    if streaming {
+        let result = match paths.as_slice() {
+            [] => stream::assemble("<input>", std::io::stdin().lock(), isa, &memory_map),
+            [input] => {
+                let file = std::fs::File::open(input)?;
+                stream::assemble(input, BufReader::new(file), isa, &memory_map)
+            }
+            _ => usage(),
+        };
+        return match result {
+            Ok((words, warnings)) => {
+                for w in &warnings {
+                    eprintln!("{}\n", w);
+                }
+                let mut out = Vec::new();
+                assembler::write_words(&mut out, &words, format)?;
+                Ok(write_output(out_path.as_deref(), &out)?)
+            }
+            Err(Error::Assemble(diagnostics)) => report(&diagnostics),
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    let mut asm = if let Some((input, rest)) = paths.split_first() {
+        let mut asm = Assembler::new(&std::fs::read_to_string(input)?);
+        asm.set_file_name(input);
+        for path in rest {
+            asm.add_file(path, &std::fs::read_to_string(path)?);
+        }
+        asm
+    } else {
         let mut source = String::new();
         std::io::stdin().read_to_string(&mut source)?;
         Assembler::new(&source)
-    } else if args.len() >= 2 {
-        let input = &args[1];
-        let mut file = std::fs::File::open(input)?;
-        let mut source = String::new();
-        file.read_to_string(&mut source)?;
-        Assembler::new(&source)
-    } else {
-        panic!("need input path")
     };
+    asm.set_format(format);
+    asm.set_isa(isa);
+    asm.set_memory_map(memory_map);
+    asm.set_strict(strict);
+    asm.set_lint(lint);
+    asm.set_optimize(optimize);
+    for dir in include_paths {
+        asm.add_include_path(dir);
+    }
 
-    if args.len() == 2 {
-        let mut out = std::io::stdout();
-        asm.write(&mut out)?
+    let mut out = Vec::new();
+    let result = if object {
+        asm.write_object(&mut out)
     } else {
-        let out_path = &args[2];
-        let mut file = std::fs::File::create(out_path)?;
+        asm.write(&mut out)
+    };
 
-        asm.write(&mut file)?
-    }
+    let result = result.and_then(|()| {
+        if let Some(path) = &symbols_path {
+            asm.write_symbols(&mut std::fs::File::create(path)?)?;
+        }
+        if let Some(path) = &listing_path {
+            asm.write_listing(&mut std::fs::File::create(path)?)?;
+        }
+        Ok(())
+    });
 
-    Ok(())
+    for w in asm.warnings() {
+        eprintln!("{}\n", w);
+    }
+    if let Some(report) = asm.optimize_report() {
+        eprintln!("{}", report);
+    }
+    match result {
+        Ok(()) => Ok(write_output(out_path.as_deref(), &out)?),
+        Err(Error::Assemble(diagnostics)) => report(&diagnostics),
+        Err(e) => Err(e.into()),
+    }
 }
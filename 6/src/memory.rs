@@ -0,0 +1,214 @@
+//! Predefined symbols and variable space for boards with other memory maps
+//!
+//! A memory map file has one `NAME = ADDRESS` per line, which defines a
+//! predefined symbol or moves one of the standard ones, and may give the RAM
+//! used for variables as `variables = START..END`. Addresses are decimal, `0x`
+//! hex or `0b` binary, and `#` or `//` starts a comment:
+//!
+//! ```text
+//! LED = 0x6001      # status lights
+//! variables = 32..0x4000
+//! ```
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::Assembler;
+use crate::ast::Span;
+use crate::error::Diagnostic;
+use crate::lexer::is_symbol_char;
+use crate::parser::parse_number;
+use crate::symbol::{DEFAULT_SYMBOL, SymbolKind, SymbolTable};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// Predefined symbols and their RAM addresses
+    pub symbols: BTreeMap<String, usize>,
+    /// RAM addresses given to variables in order of first use
+    pub variables: Range<usize>,
+}
+
+impl Default for MemoryMap {
+    /// The memory map of the course computer
+    fn default() -> Self {
+        Self {
+            symbols: DEFAULT_SYMBOL
+                .iter()
+                .map(|(name, addr)| (name.to_string(), *addr))
+                .collect(),
+            variables: Assembler::RAM_ADDR_START..Assembler::MAX_VALUE as usize + 1,
+        }
+    }
+}
+
+/// Gives variables the RAM of a memory map in order of first use
+#[derive(Debug, Default)]
+pub(crate) struct Variables {
+    count: usize,
+    /// Where the last variable landed, to warn once on reaching a device
+    device: Option<&'static str>,
+}
+
+impl Variables {
+    /// Add `name` to `table` at the next free address of `map`, returning the
+    /// address and a warning if it is on the screen or keyboard
+    pub(crate) fn allocate(
+        &mut self,
+        map: &MemoryMap,
+        table: &mut SymbolTable,
+        name: &str,
+    ) -> Result<(usize, Option<String>), String> {
+        let address = map.variables.start + self.count;
+        if address >= map.variables.end {
+            return Err(format!("no RAM left for variable `{}`", name));
+        }
+        let warning = map.device_warning(name, address, &mut self.device);
+        table.add_entry(name, address, SymbolKind::Variable);
+        self.count += 1;
+        Ok((address, warning))
+    }
+}
+
+impl MemoryMap {
+    /// A warning if the variable `name` at `address` is the first to land in
+    /// the screen memory map, or at or past the keyboard register; `device`
+    /// keeps where the variable before it landed
+    fn device_warning(
+        &self,
+        name: &str,
+        address: usize,
+        device: &mut Option<&'static str>,
+    ) -> Option<String> {
+        let start = |symbol| self.symbols.get(symbol).copied().filter(|&a| address >= a);
+        let now = match (start("KBD"), start("SCREEN")) {
+            (Some(kbd), _) => Some(("keyboard register", kbd)),
+            (None, Some(screen)) => Some(("screen memory map", screen)),
+            (None, None) => None,
+        };
+        if now.map(|(d, _)| d) == *device {
+            return None;
+        }
+        *device = now.map(|(d, _)| d);
+        let (device, start) = now?;
+        Some(if address == start {
+            format!(
+                "variable `{}` is allocated at {}, where the {} starts",
+                name, address, device
+            )
+        } else {
+            format!(
+                "variable `{}` is allocated at {}, past the start of the {}",
+                name, address, device
+            )
+        })
+    }
+
+    /// The standard memory map with the changes given by `source`
+    pub fn parse(file: &str, source: &str) -> Result<Self, Vec<Diagnostic>> {
+        let mut map = Self::default();
+        let limit = map.variables.end;
+        let mut diagnostics = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            let code = code.split("//").next().unwrap_or("");
+            if code.trim().is_empty() {
+                continue;
+            }
+            let error = |message: String| {
+                let start = line.len() - line.trim_start().len() + 1;
+                let span = Span::new(i + 1, start, code.trim_end().len() + 1);
+                Diagnostic::new(message, file, span, line)
+            };
+
+            let Some((name, value)) = code.split_once('=') else {
+                diagnostics.push(error("expected `NAME = ADDRESS`".to_string()));
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name == "variables" {
+                let range = value
+                    .split_once("..")
+                    .and_then(|(start, end)| Some(address(start.trim())?..address(end.trim())?));
+                match range {
+                    Some(range) if range.start < range.end && range.end <= limit => {
+                        map.variables = range
+                    }
+                    _ => diagnostics.push(error(format!(
+                        "expected `variables = START..END` with START below END and END at most {}",
+                        limit
+                    ))),
+                }
+            } else if name.is_empty()
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(is_symbol_char)
+            {
+                diagnostics.push(error(format!("invalid symbol name `{}`", name)));
+            } else {
+                match address(value) {
+                    Some(addr) if addr < limit => {
+                        map.symbols.insert(name.to_string(), addr);
+                    }
+                    _ => diagnostics.push(error(format!(
+                        "`{}` is not an address from 0 to {}",
+                        value,
+                        Assembler::MAX_VALUE
+                    ))),
+                }
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(map)
+        } else {
+            Err(diagnostics)
+        }
+    }
+}
+
+fn address(s: &str) -> Option<usize> {
+    parse_number(s).and_then(|n| usize::try_from(n).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_parse() {
+        let map = MemoryMap::parse(
+            "board.map",
+            "# extra devices\nLED = 0x6001\nSCREEN = 0x5000 // moved\n\nvariables = 32..64\n",
+        )
+        .unwrap();
+        assert_eq!(map.symbols["LED"], 0x6001);
+        assert_eq!(map.symbols["SCREEN"], 0x5000);
+        assert_eq!(map.symbols["KBD"], 0x6000);
+        assert_eq!(map.variables, 32..64);
+
+        let diagnostics =
+            MemoryMap::parse("board.map", "LED\n1X = 2\nBIG = 0x8000\nvariables = 9..3\n")
+                .unwrap_err();
+        assert_eq!(
+            diagnostics.iter().map(|d| d.line).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(diagnostics[0].message, "expected `NAME = ADDRESS`");
+        assert_eq!(diagnostics[1].message, "invalid symbol name `1X`");
+    }
+
+    #[test]
+    fn test_assemble_with_map() {
+        let map = MemoryMap::parse("board.map", "LED = 0x6001\nvariables = 32..34\n").unwrap();
+        let mut asm = Assembler::new("@LED\n@a\n@b\n@a\n");
+        asm.set_memory_map(map);
+        assert_eq!(asm.assemble().unwrap(), vec![0x6001, 32, 33, 32]);
+
+        let mut asm = Assembler::new("@a\n@b\n@c\n");
+        asm.set_memory_map(MemoryMap::parse("board.map", "variables = 32..34").unwrap());
+        let Err(Error::Assemble(errors)) = asm.assemble() else {
+            panic!("expected running out of RAM");
+        };
+        assert_eq!(errors[0].message, "no RAM left for variable `c`");
+    }
+}
@@ -0,0 +1,531 @@
+//! Relocatable object files and the linker which combines them
+//!
+//! An object file (`.hobj`) is text:
+//!
+//! ```text
+//! HOBJ 1
+//! NAME Mult.asm
+//! EXPORT MULT 0
+//! CONST WIDTH 32
+//! RELOC 5
+//! REF 2 i
+//! REF 7 END jump
+//! CODE
+//! 0000000000000000
+//! ...
+//! ```
+//!
+//! `EXPORT` lines give the labels other modules may use, and the `.equ`
+//! constants naming a ROM address, relative to the start of the module, and
+//! `CONST` lines the other `.equ` constants they may use. `RELOC` lines give
+//! the words holding such a relative address. `REF` lines give the
+//! words holding a symbol not defined in the module, which is a label or
+//! constant of another module or, unless the word is used as a jump target, a
+//! variable. `CODE` is followed by one word per line.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::ast::Span;
+use crate::disasm;
+use crate::error::Diagnostic;
+use crate::memory::MemoryMap;
+
+/// The assembled code of one module
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Module name used in link errors
+    pub name: String,
+    /// Code, with references left as 0 and labels relative to the start of the module
+    pub words: Vec<u16>,
+    /// Labels and constants naming a ROM address other modules can refer to,
+    /// relative to the start of the module
+    pub exports: BTreeMap<String, u16>,
+    /// Other constants other modules can refer to
+    pub constants: BTreeMap<String, u16>,
+    /// Addresses of the words holding a ROM address of this module
+    pub relocations: Vec<u16>,
+    pub references: Vec<Reference>,
+}
+
+/// A word holding a symbol which is not defined in its module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub address: u16,
+    pub symbol: String,
+    /// Whether the next instruction jumps to the symbol, so it must be a label
+    pub jump: bool,
+}
+
+impl Object {
+    pub fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        writeln!(writer, "HOBJ 1")?;
+        writeln!(writer, "NAME {}", self.name)?;
+        for (name, address) in &self.exports {
+            writeln!(writer, "EXPORT {} {}", name, address)?;
+        }
+        for (name, value) in &self.constants {
+            writeln!(writer, "CONST {} {}", name, value)?;
+        }
+        for address in &self.relocations {
+            writeln!(writer, "RELOC {}", address)?;
+        }
+        for r in &self.references {
+            let jump = if r.jump { " jump" } else { "" };
+            writeln!(writer, "REF {} {}{}", r.address, r.symbol, jump)?;
+        }
+        writeln!(writer, "CODE")?;
+        for word in &self.words {
+            writeln!(writer, "{:016b}", word)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(file: &str, source: &str) -> Result<Self, Vec<Diagnostic>> {
+        let mut object = Object::default();
+        let mut diagnostics = Vec::new();
+        let mut lines = source.lines().enumerate();
+        let error = |i: usize, line: &str, message: String| {
+            let span = Span::new(i + 1, 1, line.trim_end().len() + 1);
+            Diagnostic::new(message, file, span, line)
+        };
+
+        match lines.next() {
+            Some((_, "HOBJ 1")) => {}
+            Some((i, line)) => {
+                diagnostics.push(error(i, line, "expected `HOBJ 1`".to_string()));
+                return Err(diagnostics);
+            }
+            None => {
+                let span = Span::new(1, 1, 1);
+                let message = "empty object file".to_string();
+                return Err(vec![Diagnostic::new(message, file, span, "")]);
+            }
+        }
+
+        let mut code_start = None;
+        for (i, line) in lines.by_ref() {
+            let mut columns = line.split_whitespace();
+            let address = |s: Option<&str>| s.and_then(|a| a.parse::<u16>().ok());
+            match columns.next() {
+                None => {}
+                Some("NAME") => object.name = line.trim_start()["NAME".len()..].trim().to_string(),
+                Some("EXPORT") => match (columns.next(), address(columns.next())) {
+                    (Some(name), Some(a)) => {
+                        object.exports.insert(name.to_string(), a);
+                    }
+                    _ => diagnostics.push(error(i, line, "expected `EXPORT NAME ADDRESS`".into())),
+                },
+                Some("CONST") => match (columns.next(), address(columns.next())) {
+                    (Some(name), Some(v)) => {
+                        object.constants.insert(name.to_string(), v);
+                    }
+                    _ => diagnostics.push(error(i, line, "expected `CONST NAME VALUE`".into())),
+                },
+                Some("RELOC") => match address(columns.next()) {
+                    Some(a) => object.relocations.push(a),
+                    None => diagnostics.push(error(i, line, "expected `RELOC ADDRESS`".into())),
+                },
+                Some("REF") => match (address(columns.next()), columns.next(), columns.next()) {
+                    (Some(a), Some(symbol), jump @ (None | Some("jump"))) => {
+                        object.references.push(Reference {
+                            address: a,
+                            symbol: symbol.to_string(),
+                            jump: jump.is_some(),
+                        })
+                    }
+                    _ => diagnostics.push(error(
+                        i,
+                        line,
+                        "expected `REF ADDRESS SYMBOL [jump]`".into(),
+                    )),
+                },
+                Some("CODE") => {
+                    code_start = Some(i + 1);
+                    break;
+                }
+                Some(other) => {
+                    diagnostics.push(error(i, line, format!("unknown record `{}`", other)))
+                }
+            }
+        }
+
+        match code_start {
+            Some(start) => {
+                // `read_hack` numbers lines from 1, so pad to keep them matching the file
+                let code =
+                    "\n".repeat(start) + &lines.map(|(_, l)| l).collect::<Vec<_>>().join("\n");
+                match disasm::read_hack(file, &code) {
+                    Ok(words) => object.words = words,
+                    Err(mut errors) => diagnostics.append(&mut errors),
+                }
+            }
+            None => {
+                let line = source.lines().count();
+                let span = Span::new(line, 1, 1);
+                diagnostics.push(Diagnostic::new("missing `CODE`".into(), file, span, ""));
+            }
+        }
+
+        let len = object.words.len();
+        let outside = |a: &u16| usize::from(*a) >= len;
+        if object.relocations.iter().any(outside)
+            || object.references.iter().any(|r| outside(&r.address))
+        {
+            let span = Span::new(1, 1, 1);
+            let message = "relocation or reference past the end of the code".to_string();
+            diagnostics.push(Diagnostic::new(message, file, span, ""));
+        }
+
+        if diagnostics.is_empty() {
+            Ok(object)
+        } else {
+            Err(diagnostics)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// Two modules export the same label
+    Duplicate {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    /// A module jumps to a label which no module exports
+    Undefined { symbol: String, module: String },
+    /// More variables than there is RAM for
+    NoRam { symbol: String, module: String },
+    /// The modules together have more words than the ROM
+    TooLarge { words: usize },
+    /// A relocated address or a reference does not fit in an A-instruction
+    Overflow {
+        value: usize,
+        address: u16,
+        module: String,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Duplicate {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "`{}` is defined in both `{}` and `{}`",
+                symbol, first, second
+            ),
+            LinkError::Undefined { symbol, module } => {
+                write!(f, "undefined label `{}` in `{}`", symbol, module)
+            }
+            LinkError::NoRam { symbol, module } => {
+                write!(f, "no RAM left for variable `{}` in `{}`", symbol, module)
+            }
+            LinkError::TooLarge { words } => write!(
+                f,
+                "{} words do not fit in the {} words of ROM",
+                words, ROM_SIZE
+            ),
+            LinkError::Overflow {
+                value,
+                address,
+                module,
+            } => write!(
+                f,
+                "value {} of word {} in `{}` does not fit in 15 bits",
+                value, address, module
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+const ROM_SIZE: usize = 0x8000;
+/// The largest value of an A-instruction
+const MAX_VALUE: usize = 0x7fff;
+
+/// Place `objects` one after another and resolve the references between them
+///
+/// References to symbols no module exports become variables, shared by every
+/// module using the same name, in the variable space of `map`.
+pub fn link(objects: &[Object], map: &MemoryMap) -> Result<Vec<u16>, Vec<LinkError>> {
+    let mut errors = Vec::new();
+    let mut bases = Vec::new();
+    let mut len = 0;
+    for object in objects {
+        bases.push(len);
+        len += object.words.len();
+    }
+    if len > ROM_SIZE {
+        return Err(vec![LinkError::TooLarge { words: len }]);
+    }
+
+    let mut globals: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (i, (object, base)) in objects.iter().zip(&bases).enumerate() {
+        let labels = object
+            .exports
+            .iter()
+            .map(|(name, address)| (name, base + usize::from(*address)));
+        let constants = object
+            .constants
+            .iter()
+            .map(|(name, value)| (name, usize::from(*value)));
+        for (name, value) in labels.chain(constants) {
+            match globals.get(name) {
+                Some((_, first)) => errors.push(LinkError::Duplicate {
+                    symbol: name.clone(),
+                    first: objects[*first].name.clone(),
+                    second: object.name.clone(),
+                }),
+                None => {
+                    globals.insert(name.clone(), (value, i));
+                }
+            }
+        }
+    }
+
+    let space = &map.variables;
+    let mut variables = BTreeMap::new();
+    let mut words = Vec::with_capacity(len);
+    for (object, base) in objects.iter().zip(&bases) {
+        let mut values = object
+            .relocations
+            .iter()
+            .map(|&address| {
+                (
+                    address,
+                    usize::from(object.words[usize::from(address)]) + base,
+                )
+            })
+            .collect::<Vec<_>>();
+        for r in &object.references {
+            let value = match globals.get(&r.symbol) {
+                Some((address, _)) => *address,
+                None if r.jump => {
+                    errors.push(LinkError::Undefined {
+                        symbol: r.symbol.clone(),
+                        module: object.name.clone(),
+                    });
+                    continue;
+                }
+                None => match variables.get(&r.symbol) {
+                    Some(&address) => address,
+                    None if space.start + variables.len() < space.end => {
+                        let next = space.start + variables.len();
+                        variables.insert(r.symbol.clone(), next);
+                        next
+                    }
+                    None => {
+                        errors.push(LinkError::NoRam {
+                            symbol: r.symbol.clone(),
+                            module: object.name.clone(),
+                        });
+                        continue;
+                    }
+                },
+            };
+            values.push((r.address, value));
+        }
+        let mut code = object.words.clone();
+        for (address, value) in values {
+            if value > MAX_VALUE {
+                errors.push(LinkError::Overflow {
+                    value,
+                    address,
+                    module: object.name.clone(),
+                });
+            } else {
+                code[usize::from(address)] = value as u16;
+            }
+        }
+        words.extend(code);
+    }
+
+    if errors.is_empty() {
+        Ok(words)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+
+    fn object(source: &str) -> Object {
+        Assembler::new(source).assemble_object().unwrap()
+    }
+
+    #[test]
+    fn test_assemble_object() {
+        let object = object("(START)\n@i\nM=0\n@START\n0;JMP\n@MULT\n0;JMP\n@R1\n@START+2\n");
+        assert_eq!(object.exports, BTreeMap::from([("START".to_string(), 0)]));
+        assert_eq!(object.relocations, vec![2, 7]);
+        assert_eq!(
+            object.references,
+            vec![
+                Reference {
+                    address: 0,
+                    symbol: "i".to_string(),
+                    jump: false
+                },
+                Reference {
+                    address: 4,
+                    symbol: "MULT".to_string(),
+                    jump: true
+                },
+            ]
+        );
+        assert_eq!(object.words[6], 1);
+        assert_eq!(object.words[7], 2);
+    }
+
+    #[test]
+    fn test_write_and_parse() {
+        let mut object = object("(.loop)\n@x\n@.loop\n(END)\n@END\n0;JMP\n");
+        object.name = "Loop.asm".to_string();
+        let mut buf = Vec::new();
+        object.write(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.starts_with("HOBJ 1\nNAME Loop.asm\nEXPORT END 2\nRELOC 1\nRELOC 2\n"));
+        assert_eq!(Object::parse("Loop.hobj", &text), Ok(object));
+
+        let diagnostics = Object::parse("bad.hobj", "HOBJ 1\nREF x\nCODE\n0101\n").unwrap_err();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (2, "expected `REF ADDRESS SYMBOL [jump]`"),
+                (4, "expected 16 binary digits"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_constants() {
+        let main = object(".equ ROW 32\n@ROW\nD=A\n@WIDTH\nD=D+A\n");
+        let screen = object(".equ WIDTH 512\n(DRAW)\n@ROW\n");
+        assert_eq!(
+            screen.constants,
+            BTreeMap::from([("WIDTH".to_string(), 512)])
+        );
+        let linked = link(&[main, screen], &MemoryMap::default()).unwrap();
+        assert_eq!((linked[2], linked[4]), (512, 32));
+    }
+
+    #[test]
+    fn test_link_label_constants() {
+        let a = object("@1\nD=A\n@2\n");
+        let b = object(".equ X END\n.equ Y X+1\n.equ N END-X\n(END)\n@X\n@Y\n@X+2\n@N\n0;JMP\n");
+        assert_eq!(
+            b.exports,
+            BTreeMap::from([
+                ("END".to_string(), 0),
+                ("X".to_string(), 0),
+                ("Y".to_string(), 1)
+            ])
+        );
+        assert_eq!(b.constants, BTreeMap::from([("N".to_string(), 0)]));
+        assert_eq!(b.relocations, vec![0, 1, 2]);
+        let c = object("@Y\n");
+        let linked = link(&[a, b, c], &MemoryMap::default()).unwrap();
+        assert_eq!(linked[3..7], [3, 4, 5, 0]);
+        assert_eq!(linked[8], 4);
+
+        let errors = Assembler::new(".equ X END*2\n(END)\n@X\n")
+            .assemble_object()
+            .unwrap_err();
+        assert!(errors.to_string().contains("`X` can not be relocated"));
+    }
+
+    #[test]
+    fn test_link_overflow() {
+        let start = Object {
+            name: "Start.asm".to_string(),
+            words: vec![0; 2],
+            ..Object::default()
+        };
+        let end = Object {
+            name: "End.asm".to_string(),
+            words: vec![0x7ffe],
+            relocations: vec![0],
+            ..Object::default()
+        };
+        let errors = link(&[start, end], &MemoryMap::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["value 32768 of word 0 in `End.asm` does not fit in 15 bits"]
+        );
+    }
+
+    #[test]
+    fn test_link_memory_map() {
+        let map = MemoryMap::parse("board.map", "variables = 100..101").unwrap();
+        let linked = link(&[object("@x\n@x\n")], &map).unwrap();
+        assert_eq!(linked, [100, 100]);
+        let errors = link(&[object("@x\n@y\n")], &map).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["no RAM left for variable `y` in `<input>`"]
+        );
+    }
+
+    #[test]
+    fn test_link_out_of_ram() {
+        let space = MemoryMap::default().variables;
+        let module = Object {
+            name: "Big.asm".to_string(),
+            words: vec![0; space.len() + 1],
+            references: (0..=space.len() as u16)
+                .map(|address| Reference {
+                    address,
+                    symbol: format!("v{}", address),
+                    jump: false,
+                })
+                .collect(),
+            ..Object::default()
+        };
+        let errors = link(&[module], &MemoryMap::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![format!(
+                "no RAM left for variable `v{}` in `Big.asm`",
+                space.len()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_link() {
+        let main = object("@count\nM=0\n@MULT\n0;JMP\n(BACK)\n@BACK\n0;JMP\n");
+        let mult = object("(MULT)\n@count\nM=M+1\n@total\n@BACK\n0;JMP\n");
+        let linked = link(&[main.clone(), mult], &MemoryMap::default()).unwrap();
+        let single = Assembler::new(
+            "@count\nM=0\n@MULT\n0;JMP\n(BACK)\n@BACK\n0;JMP\n\
+             (MULT)\n@count\nM=M+1\n@total\n@BACK\n0;JMP\n",
+        )
+        .assemble()
+        .unwrap();
+        assert_eq!(linked, single);
+
+        let errors = link(&[main.clone(), main], &MemoryMap::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "`BACK` is defined in both `<input>` and `<input>`",
+                "undefined label `MULT` in `<input>`",
+                "undefined label `MULT` in `<input>`",
+            ]
+        );
+    }
+}
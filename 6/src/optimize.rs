@@ -0,0 +1,431 @@
+//! Peephole optimization of the expanded program
+//!
+//! The rewrites only remove or retarget instructions, and labels are placed
+//! after optimizing, so every label still points at its instruction. A program
+//! jumping to a fixed ROM address, as in `@10` then `0;JMP`, is left alone since
+//! removing instructions would move its target. Computed jumps, such as `A=M`
+//! then `0;JMP`, are assumed to go to addresses taken from labels.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::ast::{AInstruction, CInstruction, Span, Statement, StatementKind};
+use crate::error::Diagnostic;
+use crate::lint::always_jumps;
+use crate::source::SourceMap;
+use crate::symbol::SymbolTable;
+
+/// Words removed or changed by each rewrite
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Instructions before optimizing
+    pub before: usize,
+    /// Instructions after optimizing
+    pub after: usize,
+    /// A-instructions loading what `A` already holds
+    pub redundant_loads: usize,
+    /// Writes to `A` or `D` which are overwritten before being read
+    pub dead_writes: usize,
+    /// Jumps retargeted past a jump they would have landed on
+    pub threaded_jumps: usize,
+    /// Instructions after an unconditional jump with no label to reach them
+    pub unreachable: usize,
+}
+
+impl Report {
+    pub fn saved(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "optimized {} words to {}, saving {}: {} redundant loads, {} dead writes, \
+             {} unreachable, {} jumps threaded",
+            self.before,
+            self.after,
+            self.saved(),
+            self.redundant_loads,
+            self.dead_writes,
+            self.unreachable,
+            self.threaded_jumps
+        )
+    }
+}
+
+/// Optimize `program` until no rewrite applies
+///
+/// Leaves `program` alone and returns a warning if it jumps to a fixed address.
+pub(crate) fn optimize(
+    program: &mut Vec<Statement>,
+    table: &SymbolTable,
+    sources: &SourceMap,
+) -> Result<Report, Vec<(Span, Diagnostic)>> {
+    let labels = program
+        .iter()
+        .filter_map(|s| match &s.kind {
+            StatementKind::Label(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if let Some(statement) = fixed_jump(program, &labels, table) {
+        let message =
+            "not optimizing, since this jumps to a fixed ROM address which would move".to_string();
+        let d = sources
+            .statement_diagnostic(statement, message, statement.span)
+            .warning();
+        return Err(vec![(sources.location(statement, statement.span), d)]);
+    }
+    let labels = labels.into_iter().map(String::from).collect();
+
+    let mut report = Report {
+        before: count(program),
+        ..Default::default()
+    };
+    loop {
+        let size = (count(program), report.threaded_jumps);
+        thread_jumps(program, &labels, &mut report);
+        *program = remove_unreachable(std::mem::take(program), &mut report);
+        *program = remove_redundant_loads(std::mem::take(program), &mut report);
+        *program = remove_dead_writes(std::mem::take(program), &mut report);
+        if (count(program), report.threaded_jumps) == size {
+            break;
+        }
+    }
+    report.after = count(program);
+    Ok(report)
+}
+
+fn count(program: &[Statement]) -> usize {
+    program.iter().filter(|s| s.kind.is_instruction()).count()
+}
+
+fn dest(c: &CInstruction) -> &str {
+    c.dest.as_ref().map_or("", |m| m.text.as_str())
+}
+
+fn jump(c: &CInstruction) -> &str {
+    c.jump.as_ref().map_or("", |m| m.text.as_str())
+}
+
+/// The first jump whose target is loaded by something other than a label
+fn fixed_jump<'a>(
+    program: &'a [Statement],
+    labels: &BTreeSet<&str>,
+    table: &SymbolTable,
+) -> Option<&'a Statement> {
+    let constants = program
+        .iter()
+        .filter_map(|s| match &s.kind {
+            StatementKind::Equ { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    for pair in program.windows(2) {
+        let (StatementKind::A(a), StatementKind::C(c)) = (&pair[0].kind, &pair[1].kind) else {
+            continue;
+        };
+        let fixed = match a {
+            AInstruction::Symbol(name) => {
+                !labels.contains(name.as_str())
+                    && (table.contains(name) || constants.contains(name.as_str()))
+            }
+            AInstruction::Literal(_) | AInstruction::Expr(_) => true,
+        };
+        if fixed && c.jump.is_some() {
+            return Some(&pair[1]);
+        }
+    }
+    None
+}
+
+/// Index of the first instruction at or after `i`, passing over labels
+fn next_instruction(program: &[Statement], i: usize) -> Option<usize> {
+    (i..program.len()).find(|&j| program[j].kind.is_instruction())
+}
+
+/// Point jumps to a label at an unconditional jump straight to that jump's target
+fn thread_jumps(program: &mut [Statement], labels: &BTreeSet<String>, report: &mut Report) {
+    let mut targets = BTreeMap::new();
+    for (i, statement) in program.iter().enumerate() {
+        if let StatementKind::Label(name) = &statement.kind
+            && let Some(j) = next_instruction(program, i)
+        {
+            targets.insert(name.clone(), j);
+        }
+    }
+    // the label an unconditional `@L` / `0;JMP` pair at an index goes on to
+    let forward = |i: usize| match (&program[i].kind, program.get(i + 1).map(|s| &s.kind)) {
+        (StatementKind::A(AInstruction::Symbol(name)), Some(StatementKind::C(c)))
+            if labels.contains(name)
+                && dest(c).is_empty()
+                && always_jumps(&c.comp.text, jump(c)) =>
+        {
+            Some(name.clone())
+        }
+        _ => None,
+    };
+
+    let mut rewrites = Vec::new();
+    for i in 0..program.len().saturating_sub(1) {
+        let (StatementKind::A(AInstruction::Symbol(name)), StatementKind::C(c)) =
+            (&program[i].kind, &program[i + 1].kind)
+        else {
+            continue;
+        };
+        if !labels.contains(name) || c.jump.is_none() {
+            continue;
+        }
+        // the instruction itself uses the address as `A` or `M`
+        if dest(c).contains('M') || c.comp.text.contains(['A', 'M']) {
+            continue;
+        }
+        // when the jump is not taken the next instruction may still use `A`
+        let unconditional = always_jumps(&c.comp.text, jump(c));
+        let next_loads_a = matches!(
+            program.get(i + 2).map(|s| &s.kind),
+            None | Some(StatementKind::A(_))
+        );
+        if !unconditional && !next_loads_a {
+            continue;
+        }
+
+        let mut target = name.clone();
+        let mut seen = BTreeSet::from([target.clone()]);
+        while let Some(next) = targets.get(&target).and_then(|&j| forward(j)) {
+            if !seen.insert(next.clone()) {
+                break;
+            }
+            target = next;
+        }
+        if target != *name {
+            rewrites.push((i, target));
+        }
+    }
+
+    for (i, target) in rewrites {
+        program[i].kind = StatementKind::A(AInstruction::Symbol(target));
+        report.threaded_jumps += 1;
+    }
+}
+
+/// Drop instructions between an unconditional jump and the next label
+fn remove_unreachable(program: Vec<Statement>, report: &mut Report) -> Vec<Statement> {
+    let mut after_jump = false;
+    let mut out = Vec::with_capacity(program.len());
+    for statement in program {
+        match &statement.kind {
+            StatementKind::Label(_) => after_jump = false,
+            StatementKind::A(_) | StatementKind::C(_) if after_jump => {
+                report.unreachable += 1;
+                continue;
+            }
+            StatementKind::C(c) => after_jump = always_jumps(&c.comp.text, jump(c)),
+            _ => {}
+        }
+        out.push(statement);
+    }
+    out
+}
+
+/// What `A` is known to hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Known {
+    /// The value of an A-instruction
+    Value(String),
+    /// The word in RAM at the value of an A-instruction, as loaded by `A=M`
+    Load(String),
+}
+
+/// Text identifying the value loaded by `a`, if it is simple enough to compare
+fn load_key(a: &AInstruction) -> Option<String> {
+    match a {
+        AInstruction::Literal(v) => Some(v.to_string()),
+        AInstruction::Symbol(name) => Some(name.clone()),
+        AInstruction::Expr(_) => None,
+    }
+}
+
+fn is_load_through_a(statement: Option<&Statement>) -> bool {
+    matches!(
+        statement.map(|s| &s.kind),
+        Some(StatementKind::C(c)) if dest(c) == "A" && c.comp.text == "M" && c.jump.is_none()
+    )
+}
+
+/// Drop `@X` when `A` already holds `X`, and `@X` / `A=M` when `A` already holds
+/// the word at `X`
+fn remove_redundant_loads(program: Vec<Statement>, report: &mut Report) -> Vec<Statement> {
+    let mut known: Option<Known> = None;
+    let mut out = Vec::with_capacity(program.len());
+    let mut program = program.into_iter().peekable();
+    while let Some(statement) = program.next() {
+        match &statement.kind {
+            StatementKind::Label(_) => known = None,
+            StatementKind::A(a) => {
+                let key = load_key(a);
+                match (&known, &key) {
+                    (Some(Known::Value(k)), Some(key)) if k == key => {
+                        report.redundant_loads += 1;
+                        continue;
+                    }
+                    (Some(Known::Load(k)), Some(key))
+                        if k == key && is_load_through_a(program.peek()) =>
+                    {
+                        program.next();
+                        report.redundant_loads += 2;
+                        continue;
+                    }
+                    _ => known = key.map(Known::Value),
+                }
+            }
+            StatementKind::C(c) => {
+                let dest = dest(c);
+                if dest.contains('A') {
+                    known = match known {
+                        Some(Known::Value(k)) if dest == "A" && c.comp.text == "M" => {
+                            Some(Known::Load(k))
+                        }
+                        _ => None,
+                    };
+                } else if dest.contains('M') && matches!(known, Some(Known::Load(_))) {
+                    // the write may have changed the word `A` was loaded from
+                    known = None;
+                }
+            }
+            _ => {}
+        }
+        out.push(statement);
+    }
+    out
+}
+
+/// Drop `D=...` and `A=...` when the register is written again before being
+/// read in the same block
+fn remove_dead_writes(program: Vec<Statement>, report: &mut Report) -> Vec<Statement> {
+    let dead = (0..program.len())
+        .map(|i| match &program[i].kind {
+            StatementKind::C(c) if c.jump.is_none() => match dest(c) {
+                "D" => overwritten('D', &program[i + 1..]),
+                "A" => overwritten('A', &program[i + 1..]),
+                _ => false,
+            },
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    report.dead_writes += dead.iter().filter(|&&d| d).count();
+    program
+        .into_iter()
+        .zip(dead)
+        .filter_map(|(s, dead)| (!dead).then_some(s))
+        .collect()
+}
+
+/// Whether `register` is written by `rest` before anything may read it
+fn overwritten(register: char, rest: &[Statement]) -> bool {
+    for statement in rest {
+        match &statement.kind {
+            StatementKind::Label(_) => return false,
+            StatementKind::A(_) if register == 'A' => return true,
+            StatementKind::C(c) => {
+                // `M` and jumps read the address in `A`
+                let reads = c.comp.text.contains(register)
+                    || c.jump.is_some()
+                    || register == 'A' && (c.comp.text.contains('M') || dest(c).contains('M'));
+                if reads {
+                    return false;
+                }
+                if dest(c).contains(register) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Assembler;
+
+    fn optimized(source: &str) -> (Vec<u16>, super::Report) {
+        let mut asm = Assembler::new(source);
+        asm.set_optimize(true);
+        let words = asm.assemble().unwrap();
+        (words, asm.optimize_report().unwrap().clone())
+    }
+
+    fn assemble(source: &str) -> Vec<u16> {
+        Assembler::new(source).assemble().unwrap()
+    }
+
+    #[test]
+    fn test_redundant_loads() {
+        let (words, report) = optimized(
+            "@SP\nA=M\nM=D\n@SP\nM=M+1\n@SP\nA=M\nD=M\n@SP\nA=M\nM=D\n@SP\nA=M\n(L)\n@SP\n",
+        );
+        assert_eq!(
+            words,
+            assemble("@SP\nA=M\nM=D\n@SP\nM=M+1\nA=M\nD=M\nM=D\n@SP\nA=M\n(L)\n@SP\n")
+        );
+        assert_eq!(report.redundant_loads, 3);
+    }
+
+    #[test]
+    fn test_dead_writes() {
+        let (words, report) = optimized("D=M\n@x\nD=A\nM=D\nD=1\n(L)\nD=0\nD=-1\n@L\nD;JEQ\n");
+        assert_eq!(words, assemble("@x\nD=A\nM=D\nD=1\n(L)\nD=-1\n@L\nD;JEQ\n"));
+        assert_eq!(report.dead_writes, 2);
+    }
+
+    #[test]
+    fn test_threading_and_unreachable() {
+        let source = "\
+@A
+D;JGT
+@B
+0;JMP
+M=1
+(A)
+@B
+0;JMP
+(B)
+@B
+0;JMP
+";
+        let (words, report) = optimized(source);
+        assert_eq!(
+            words,
+            assemble("@B\nD;JGT\n0;JMP\n(A)\n@B\n0;JMP\n(B)\n@B\n0;JMP\n")
+        );
+        assert_eq!(report.threaded_jumps, 1);
+        assert_eq!(report.unreachable, 1);
+        // once both jumps go to `B` the second `@B` is redundant
+        assert_eq!(report.redundant_loads, 1);
+        assert_eq!((report.before, report.after, report.saved()), (9, 7, 2));
+    }
+
+    #[test]
+    fn test_threading_keeps_memory_access() {
+        for jump in ["M=D;JMP", "D=M;JEQ", "D=D+A;JMP"] {
+            let source = format!("@B\n{}\n(B)\n@END\n0;JMP\n(END)\n@END\n0;JMP\n", jump);
+            let (words, report) = optimized(&source);
+            assert_eq!(words, assemble(&source), "{}", jump);
+            assert_eq!(report.threaded_jumps, 0);
+        }
+    }
+
+    #[test]
+    fn test_fixed_jumps() {
+        let source = include_str!("../asm/Pong.asm");
+        let mut asm = Assembler::new(source);
+        asm.set_optimize(true);
+        assert_eq!(asm.assemble().unwrap(), assemble(source));
+        assert!(asm.optimize_report().is_none());
+        assert_eq!(asm.warnings()[0].line, 16);
+        assert!(asm.warnings()[0].message.starts_with("not optimizing"));
+    }
+}
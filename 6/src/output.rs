@@ -0,0 +1,177 @@
+use std::io;
+
+/// Encoding of the assembled machine words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One line of 16 ASCII `0`/`1` per word, as read by the course tools
+    #[default]
+    Text,
+    /// Raw 16-bit words, most significant byte first
+    BinaryBigEndian,
+    /// Raw 16-bit words, least significant byte first
+    BinaryLittleEndian,
+    /// One 4-digit hex word per line, as read by Verilog `$readmemh`
+    Hex,
+    /// Intel HEX records addressed by word, each word stored high byte first
+    IntelHex,
+    /// Logisim "v2.0 raw" ROM image
+    Logisim,
+}
+
+impl OutputFormat {
+    pub const NAMES: &[&str] = &["text", "bin-be", "bin-le", "hex", "ihex", "logisim"];
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "bin-be" => Ok(OutputFormat::BinaryBigEndian),
+            "bin-le" => Ok(OutputFormat::BinaryLittleEndian),
+            "hex" => Ok(OutputFormat::Hex),
+            "ihex" => Ok(OutputFormat::IntelHex),
+            "logisim" => Ok(OutputFormat::Logisim),
+            _ => Err(format!(
+                "unknown output format `{}` (expected one of {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Write `words` encoded as `format`
+pub fn write_words(
+    writer: &mut impl io::Write,
+    words: &[u16],
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            let lines = words
+                .iter()
+                .map(|w| format!("{:016b}", w))
+                .collect::<Vec<_>>();
+            write!(writer, "{}", lines.join("\n"))
+        }
+        OutputFormat::BinaryBigEndian => {
+            let bytes = words
+                .iter()
+                .flat_map(|w| w.to_be_bytes())
+                .collect::<Vec<_>>();
+            writer.write_all(&bytes)
+        }
+        OutputFormat::BinaryLittleEndian => {
+            let bytes = words
+                .iter()
+                .flat_map(|w| w.to_le_bytes())
+                .collect::<Vec<_>>();
+            writer.write_all(&bytes)
+        }
+        OutputFormat::Hex => {
+            for w in words {
+                writeln!(writer, "{:04x}", w)?;
+            }
+            Ok(())
+        }
+        OutputFormat::IntelHex => {
+            const WORDS_PER_RECORD: usize = 8;
+            for (i, chunk) in words.chunks(WORDS_PER_RECORD).enumerate() {
+                let address = (i * WORDS_PER_RECORD) as u16;
+                let data = chunk
+                    .iter()
+                    .flat_map(|w| w.to_be_bytes())
+                    .collect::<Vec<_>>();
+                write_ihex_record(writer, address, 0x00, &data)?;
+            }
+            // end of file
+            write_ihex_record(writer, 0, 0x01, &[])
+        }
+        OutputFormat::Logisim => {
+            const WORDS_PER_LINE: usize = 8;
+            writeln!(writer, "v2.0 raw")?;
+            for chunk in words.chunks(WORDS_PER_LINE) {
+                let line = chunk.iter().map(|w| format!("{:x}", w)).collect::<Vec<_>>();
+                writeln!(writer, "{}", line.join(" "))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_ihex_record(
+    writer: &mut impl io::Write,
+    address: u16,
+    record_type: u8,
+    data: &[u8],
+) -> io::Result<()> {
+    let [hi, lo] = address.to_be_bytes();
+    let mut bytes = vec![data.len() as u8, hi, lo, record_type];
+    bytes.extend_from_slice(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<String>();
+    writeln!(writer, ":{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(words: &[u16], format: OutputFormat) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_words(&mut buf, words, format).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_binary() {
+        let words = [0x0002, 0xec10];
+        assert_eq!(
+            render(&words, OutputFormat::BinaryBigEndian),
+            vec![0x00, 0x02, 0xec, 0x10]
+        );
+        assert_eq!(
+            render(&words, OutputFormat::BinaryLittleEndian),
+            vec![0x02, 0x00, 0x10, 0xec]
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        let words = [0x0002, 0xec10];
+        assert_eq!(render(&words, OutputFormat::Hex), b"0002\nec10\n");
+        assert_eq!(render(&words, OutputFormat::Logisim), b"v2.0 raw\n2 ec10\n");
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let words = (0..10).collect::<Vec<u16>>();
+        let expected = "\
+:1000000000000001000200030004000500060007D4
+:0400080000080009E3
+:00000001FF
+";
+        assert_eq!(
+            String::from_utf8(render(&words, OutputFormat::IntelHex)).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        for name in OutputFormat::NAMES {
+            assert!(name.parse::<OutputFormat>().is_ok());
+        }
+        assert!("elf".parse::<OutputFormat>().is_err());
+    }
+}
@@ -1,287 +1,584 @@
-use std::cell::RefCell;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum InstructionType {
-    /// @xxx
-    InstA,
-    /// dest=xxx;jmp
-    InstC,
-    /// (xxx)
-    InstL,
+use std::iter::Peekable;
+
+use crate::ast::{
+    AInstruction, BinOp, CInstruction, Expr, FileId, Mnemonic, Span, Statement, StatementKind,
+};
+use crate::error::Diagnostic;
+use crate::lexer::{Lexer, Token, TokenKind};
+
+struct SyntaxError {
+    message: String,
+    span: Span,
 }
 
-#[derive(Debug, Clone)]
-pub struct Parser {
-    source: String,
-    pos: RefCell<usize>,
-    instruction_count: usize,
+/// An expression and the span it covers
+type ExprResult = Result<(Expr, Span), SyntaxError>;
+
+/// Builds [`Statement`]s from the tokens of a whole source file
+pub struct Parser<'a> {
+    file: &'a str,
+    source: &'a str,
+    tokens: Peekable<Lexer<'a>>,
 }
 
-impl Parser {
-    pub fn new(source: &str) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(file: &'a str, source: &'a str) -> Self {
+        Self::with_file_id(0, file, source)
+    }
+
+    /// A parser whose spans point into file `id` of a [`crate::source::SourceMap`]
+    pub(crate) fn with_file_id(id: FileId, file: &'a str, source: &'a str) -> Self {
         Self {
-            source: source.to_owned(),
-            pos: RefCell::new(0),
-            instruction_count: 0,
+            file,
+            source,
+            tokens: Lexer::new(id, source).peekable(),
         }
     }
 
-    pub fn has_more_lines(&self) -> bool {
-        let s = self.get_rest();
-        let skip = if self.has_advance() { 1 } else { 0 };
-        for line in s.split("\n").skip(skip).map(remove_head_space) {
-            if !is_comment(line) && !line.is_empty() {
-                return true;
+    /// Parse every line, collecting all syntax errors instead of stopping at the first
+    pub fn parse(mut self) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+        let mut statements = Vec::new();
+        let mut diagnostics = Vec::new();
+        while self.tokens.peek().is_some() {
+            match self.parse_line() {
+                Ok(Some(statement)) => statements.push(statement),
+                Ok(None) => {}
+                Err(e) => {
+                    diagnostics.push(self.diagnostic(e));
+                    self.skip_line();
+                }
             }
         }
-        false
-    }
-
-    pub fn advance(&mut self) {
-        let mut has_before = false;
-        loop {
-            let line = self.peek_line();
-            let trimmed = remove_head_space(line);
-            if is_comment(trimmed) || trimmed.is_empty() {
-                *self.pos.borrow_mut() += line.len() + 1;
-                has_before = true;
-                continue;
-            }
-            if self.has_advance() && !has_before {
-                *self.pos.borrow_mut() += line.len() + 1;
-            }
 
-            let next_line = remove_head_space(self.peek_line());
-            if !is_comment(next_line) && !next_line.is_empty() {
-                break;
-            }
+        if diagnostics.is_empty() {
+            Ok(statements)
+        } else {
+            Err(diagnostics)
         }
+    }
 
-        match self.instruction_type() {
-            InstructionType::InstA | InstructionType::InstC => {
-                self.instruction_count += 1;
+    fn parse_line(&mut self) -> Result<Option<Statement>, SyntaxError> {
+        let Some(first) = self.tokens.next() else {
+            return Ok(None);
+        };
+        let statement = match first.kind {
+            TokenKind::Newline => return Ok(None),
+            TokenKind::At => self.parse_a_instruction(first.span)?,
+            TokenKind::LParen => self.parse_label(first.span)?,
+            TokenKind::Ident(ref name) if name.starts_with('.') => self.parse_directive(first)?,
+            TokenKind::Ident(ref name) if self.is_macro_call(name) => {
+                self.parse_macro_call(first)?
             }
-            _ => {}
+            _ => self.parse_c_instruction(first)?,
         };
+        self.expect_end_of_line()?;
+        Ok(Some(statement))
     }
 
-    pub fn instruction_type(&self) -> InstructionType {
-        let l = remove_head_space(self.peek_line());
-
-        if l.starts_with("@") {
-            InstructionType::InstA
-        } else if l.starts_with("(") && l.ends_with(")") {
-            InstructionType::InstL
-        } else {
-            InstructionType::InstC
+    fn parse_a_instruction(&mut self, at: Span) -> Result<Statement, SyntaxError> {
+        let (expr, span) = self.parse_expr(at)?;
+        // the top bit of a word marks a C-instruction, so an A-instruction holds 15 bits
+        if let Expr::Number(n) = expr
+            && n > crate::Assembler::MAX_VALUE
+        {
+            return Err(self.error(format!("value {} does not fit in 15 bits", n), span));
         }
+        let inst = AInstruction::from_expr(expr);
+        Ok(Statement::new(StatementKind::A(inst), at.to(span)))
     }
 
-    pub fn symbol(&self) -> &str {
-        let line = remove_head_space(self.peek_line());
-        let inst = self.instruction_type();
-        match inst {
-            InstructionType::InstA => {
-                // skip first `@`
-                &line[1..]
+    fn parse_directive(&mut self, directive: Token) -> Result<Statement, SyntaxError> {
+        match directive.text().as_str() {
+            ".equ" => {
+                let token = self.next_in_line("a constant name", directive.span)?;
+                let TokenKind::Ident(name) = token.kind else {
+                    return Err(self.error(
+                        format!("expected a constant name, found `{}`", token.text()),
+                        token.span,
+                    ));
+                };
+                let (value, span) = self.parse_expr(token.span)?;
+                Ok(Statement::new(
+                    StatementKind::Equ { name, value },
+                    directive.span.to(span),
+                ))
+            }
+            ".macro" => {
+                let token = self.next_in_line("a macro name", directive.span)?;
+                let TokenKind::Ident(name) = token.kind else {
+                    return Err(self.error(
+                        format!("expected a macro name, found `{}`", token.text()),
+                        token.span,
+                    ));
+                };
+                let mut params = Vec::new();
+                let mut span = token.span;
+                if self.peek_in_line() {
+                    loop {
+                        let param = self.next_in_line("a parameter name", span)?;
+                        let TokenKind::Ident(p) = param.kind else {
+                            return Err(self.error(
+                                format!("expected a parameter name, found `{}`", param.text()),
+                                param.span,
+                            ));
+                        };
+                        params.push(p);
+                        span = param.span;
+                        if self
+                            .tokens
+                            .next_if(|t| t.kind == TokenKind::Comma)
+                            .is_none()
+                        {
+                            break;
+                        }
+                    }
+                }
+                Ok(Statement::new(
+                    StatementKind::MacroDef { name, params },
+                    directive.span.to(span),
+                ))
             }
-            InstructionType::InstC => panic!("expected a instruction or label"),
-            InstructionType::InstL => {
-                let size = line.len();
-                &line[1..(size - 1)]
+            ".endm" => Ok(Statement::new(StatementKind::EndMacro, directive.span)),
+            ".include" => {
+                let token = self.next_in_line("a file name", directive.span)?;
+                let TokenKind::Str(path) = token.kind else {
+                    return Err(self.error(
+                        format!("expected a quoted file name, found `{}`", token.text()),
+                        token.span,
+                    ));
+                };
+                Ok(Statement::new(
+                    StatementKind::Include(path),
+                    directive.span.to(token.span),
+                ))
             }
+            other => Err(self.error(format!("unknown directive `{}`", other), directive.span)),
         }
     }
 
-    pub fn dest(&self) -> &str {
-        let line = remove_head_space(self.peek_line());
-        let inst = self.instruction_type();
-        match inst {
-            InstructionType::InstC => {
-                if let Some(p) = line.find("=") {
-                    &line[..p]
-                } else {
-                    ""
+    /// Parse a constant expression following `after`, returning it with its span
+    fn parse_expr(&mut self, after: Span) -> ExprResult {
+        let (expr, span) = self.parse_term(after)?;
+        self.parse_binary_rest(
+            expr,
+            span,
+            &[TokenKind::Plus, TokenKind::Minus],
+            Self::parse_term,
+        )
+    }
+
+    fn parse_term(&mut self, after: Span) -> ExprResult {
+        let (expr, span) = self.parse_unary(after)?;
+        self.parse_binary_rest(
+            expr,
+            span,
+            &[TokenKind::Star, TokenKind::Slash],
+            Self::parse_unary,
+        )
+    }
+
+    fn parse_binary_rest(
+        &mut self,
+        mut lhs: Expr,
+        mut span: Span,
+        operators: &[TokenKind],
+        operand: fn(&mut Self, Span) -> ExprResult,
+    ) -> ExprResult {
+        while let Some(op) = self.tokens.next_if(|t| operators.contains(&t.kind)) {
+            let (rhs, rhs_span) = operand(self, op.span)?;
+            let op = match op.kind {
+                TokenKind::Plus => BinOp::Add,
+                TokenKind::Minus => BinOp::Sub,
+                TokenKind::Star => BinOp::Mul,
+                _ => BinOp::Div,
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            span = span.to(rhs_span);
+        }
+        Ok((lhs, span))
+    }
+
+    fn parse_unary(&mut self, after: Span) -> ExprResult {
+        let token = self.next_in_line("a number or symbol", after)?;
+        match token.kind {
+            TokenKind::Minus => {
+                let (expr, span) = self.parse_unary(token.span)?;
+                Ok((Expr::Neg(Box::new(expr)), token.span.to(span)))
+            }
+            TokenKind::LParen => {
+                let (expr, _) = self.parse_expr(token.span)?;
+                let rparen = self.next_in_line("`)`", token.span)?;
+                if rparen.kind != TokenKind::RParen {
+                    return Err(self.error(
+                        format!("expected `)`, found `{}`", rparen.text()),
+                        rparen.span,
+                    ));
                 }
+                Ok((expr, token.span.to(rparen.span)))
             }
-            _ => panic!("expected c instruction"),
+            TokenKind::Number(ref n) => match parse_number(n) {
+                Some(value) => Ok((Expr::Number(value), token.span)),
+                None => Err(self.error(format!("invalid number `{}`", n), token.span)),
+            },
+            TokenKind::Char(c) => Ok((Expr::Number(c as i64), token.span)),
+            TokenKind::Ident(name) => Ok((Expr::Symbol(name, token.span), token.span)),
+            _ => Err(self.error(
+                format!("expected a number or symbol, found `{}`", token.text()),
+                token.span,
+            )),
         }
     }
 
-    pub fn comp(&self) -> &str {
-        let line = remove_head_space(self.peek_line());
-        let inst = self.instruction_type();
-        match inst {
-            InstructionType::InstC => {
-                let mut start = 0;
-                let mut end = line.len();
-                if let Some(sp) = line.find("=") {
-                    start = sp + 1;
-                };
-                if let Some(ep) = line.find(";") {
-                    end = ep;
-                };
-                &line[start..end]
-            }
-            _ => panic!("expected c instruction"),
+    fn parse_label(&mut self, lparen: Span) -> Result<Statement, SyntaxError> {
+        let token = self.next_in_line("a label name", lparen)?;
+        let TokenKind::Ident(name) = token.kind else {
+            return Err(self.error(
+                format!("expected a label name, found `{}`", token.text()),
+                token.span,
+            ));
+        };
+        let rparen = self.next_in_line("`)`", token.span)?;
+        if rparen.kind != TokenKind::RParen {
+            return Err(self.error(
+                format!("expected `)`, found `{}`", rparen.text()),
+                rparen.span,
+            ));
         }
+        Ok(Statement::new(
+            StatementKind::Label(name),
+            lparen.to(rparen.span),
+        ))
+    }
+
+    /// A line starting with a name other than a C-instruction dest or comp calls a macro
+    fn is_macro_call(&mut self, name: &str) -> bool {
+        let register = name.chars().all(|c| matches!(c, 'A' | 'D' | 'M'));
+        let field_end = self
+            .tokens
+            .peek()
+            .is_some_and(|t| matches!(t.kind, TokenKind::Equal | TokenKind::Semicolon));
+        !register && !field_end
     }
 
-    pub fn jump(&self) -> &str {
-        let line = remove_head_space(self.peek_line());
-        let inst = self.instruction_type();
-        match inst {
-            InstructionType::InstC => {
-                if let Some(p) = line.find(";") {
-                    &line[(p + 1)..]
-                } else {
-                    ""
+    fn parse_macro_call(&mut self, first: Token) -> Result<Statement, SyntaxError> {
+        let TokenKind::Ident(name) = first.kind else {
+            unreachable!("macro calls start with a name");
+        };
+        let mut args = Vec::new();
+        let mut span = first.span;
+        if self.peek_in_line() {
+            loop {
+                let (arg, arg_span) = self.parse_expr(span)?;
+                args.push(arg);
+                span = arg_span;
+                match self.tokens.next_if(|t| t.kind == TokenKind::Comma) {
+                    Some(comma) => span = comma.span,
+                    None => break,
                 }
             }
-            _ => panic!("expected c instruction"),
         }
+        Ok(Statement::new(
+            StatementKind::MacroCall { name, args },
+            first.span.to(span),
+        ))
     }
 
-    pub(crate) fn reset(&mut self) {
-        self.instruction_count = 0;
-        *self.pos.borrow_mut() = 0;
+    fn parse_c_instruction(&mut self, first: Token) -> Result<Statement, SyntaxError> {
+        let mut tokens = vec![first];
+        while let Some(t) = self.tokens.next_if(|t| t.kind != TokenKind::Newline) {
+            tokens.push(t);
+        }
+        let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+
+        for t in &tokens {
+            if matches!(
+                t.kind,
+                TokenKind::At
+                    | TokenKind::LParen
+                    | TokenKind::RParen
+                    | TokenKind::Star
+                    | TokenKind::Slash
+                    | TokenKind::Comma
+                    | TokenKind::Char(_)
+                    | TokenKind::Str(_)
+                    | TokenKind::Unknown(_)
+            ) {
+                return Err(self.error(format!("unexpected `{}`", t.text()), t.span));
+            }
+        }
+
+        let (dest, rest) = match tokens.iter().position(|t| t.kind == TokenKind::Equal) {
+            Some(p) => {
+                let dest = self.mnemonic(&tokens[..p], "dest", tokens[p].span)?;
+                (Some(dest), &tokens[p + 1..])
+            }
+            None => (None, &tokens[..]),
+        };
+        let (comp, jump) = match rest.iter().position(|t| t.kind == TokenKind::Semicolon) {
+            Some(p) => {
+                let comp = self.mnemonic(&rest[..p], "comp", rest[p].span)?;
+                let jump = self.mnemonic(&rest[p + 1..], "jump", rest[p].span)?;
+                (comp, Some(jump))
+            }
+            None => (self.mnemonic(rest, "comp", span)?, None),
+        };
+
+        Ok(Statement::new(
+            StatementKind::C(CInstruction { dest, comp, jump }),
+            span,
+        ))
     }
 
-    pub(crate) fn row(&self) -> usize {
-        self.instruction_count
+    /// Join the tokens of one C-instruction field, reporting `near` when it is empty
+    fn mnemonic(&self, tokens: &[Token], field: &str, near: Span) -> Result<Mnemonic, SyntaxError> {
+        let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+            return Err(self.error(format!("missing {}", field), near));
+        };
+        if let Some(t) = tokens
+            .iter()
+            .find(|t| matches!(t.kind, TokenKind::Equal | TokenKind::Semicolon))
+        {
+            return Err(self.error(format!("unexpected `{}`", t.text()), t.span));
+        }
+        let text = tokens.iter().map(|t| t.text()).collect::<String>();
+        Ok(Mnemonic::new(&text, first.span.to(last.span)))
     }
 
-    fn has_advance(&self) -> bool {
-        self.instruction_count > 0
+    fn next_in_line(&mut self, expected: &str, after: Span) -> Result<Token, SyntaxError> {
+        match self.tokens.next_if(|t| t.kind != TokenKind::Newline) {
+            Some(t) => Ok(t),
+            None => Err(self.error(
+                format!("expected {}", expected),
+                Span::new(after.line, after.end, after.end + 1).in_file(after.file),
+            )),
+        }
     }
 
-    fn get_rest(&self) -> &str {
-        let p = *self.pos.borrow();
-        if p < self.source.len() {
-            &self.source[p..]
-        } else {
-            ""
+    /// Whether another token follows on the current line
+    fn peek_in_line(&mut self) -> bool {
+        self.tokens
+            .peek()
+            .is_some_and(|t| t.kind != TokenKind::Newline)
+    }
+
+    fn expect_end_of_line(&mut self) -> Result<(), SyntaxError> {
+        match self.tokens.next() {
+            None => Ok(()),
+            Some(t) if t.kind == TokenKind::Newline => Ok(()),
+            Some(t) => Err(self.error(
+                format!("expected end of line, found `{}`", t.text()),
+                t.span,
+            )),
         }
     }
 
-    pub(crate) fn peek_line(&self) -> &str {
-        let s = self.get_rest();
-        let l = if let Some(end) = s.find("\n") {
-            &s[..end]
-        } else {
-            s
-        };
-        l
+    fn skip_line(&mut self) {
+        for t in self.tokens.by_ref() {
+            if t.kind == TokenKind::Newline {
+                break;
+            }
+        }
+    }
+
+    fn error(&self, message: String, span: Span) -> SyntaxError {
+        SyntaxError { message, span }
     }
-}
 
-fn remove_head_space(s: &str) -> &str {
-    s.trim_start_matches(" ")
+    fn diagnostic(&self, e: SyntaxError) -> Diagnostic {
+        let line = self.source.lines().nth(e.span.line - 1).unwrap_or("");
+        Diagnostic::new(e.message, self.file, e.span, line)
+    }
 }
 
-fn is_comment(s: &str) -> bool {
-    s.starts_with("//")
+/// Parse a decimal, `0x` hex or `0b` binary number
+pub(crate) fn parse_number(s: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b").or(s.strip_prefix("0B")) {
+        (bin, 2)
+    } else {
+        (s, 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_parse_dst_comp_c_instruction() {
-        {
-            let parser = Parser::new("D=A");
+    fn parse(source: &str) -> Vec<StatementKind> {
+        Parser::new("test.asm", source)
+            .parse()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.kind)
+            .collect()
+    }
 
-            assert_eq!(parser.dest(), "D");
-            assert_eq!(parser.comp(), "A");
-            assert_eq!(parser.jump(), "");
+    fn c_fields(source: &str) -> (Option<String>, String, Option<String>) {
+        match parse(source).remove(0) {
+            StatementKind::C(c) => (c.dest.map(|m| m.text), c.comp.text, c.jump.map(|m| m.text)),
+            s => panic!("expected c instruction, got {:?}", s),
         }
+    }
 
-        {
-            let parser = Parser::new("D=D+A");
-
-            assert_eq!(parser.dest(), "D");
-            assert_eq!(parser.comp(), "D+A");
-            assert_eq!(parser.jump(), "");
-        }
+    #[test]
+    fn test_parse_dst_comp_c_instruction() {
+        assert_eq!(
+            c_fields("D=A"),
+            (Some("D".to_string()), "A".to_string(), None)
+        );
+        assert_eq!(
+            c_fields("D=D+A"),
+            (Some("D".to_string()), "D+A".to_string(), None)
+        );
     }
 
     #[test]
     fn test_parse_comp_jump_c_instruction() {
-        {
-            let parser = Parser::new("0;JMP");
-
-            assert_eq!(parser.dest(), "");
-            assert_eq!(parser.comp(), "0");
-            assert_eq!(parser.jump(), "JMP");
-        }
-
-        {
-            let parser = Parser::new("D;JMP");
-
-            assert_eq!(parser.dest(), "");
-            assert_eq!(parser.comp(), "D");
-            assert_eq!(parser.jump(), "JMP");
-        }
+        assert_eq!(
+            c_fields("0;JMP"),
+            (None, "0".to_string(), Some("JMP".to_string()))
+        );
+        assert_eq!(
+            c_fields("D;JMP"),
+            (None, "D".to_string(), Some("JMP".to_string()))
+        );
     }
 
     #[test]
     fn test_parse_dest_comp_jump_c_instruction() {
-        {
-            let parser = Parser::new("D=1;JMP");
-
-            assert_eq!(parser.dest(), "D");
-            assert_eq!(parser.comp(), "1");
-            assert_eq!(parser.jump(), "JMP");
-        }
-
-        {
-            let parser = Parser::new("M=D;JNE");
-
-            assert_eq!(parser.dest(), "M");
-            assert_eq!(parser.comp(), "D");
-            assert_eq!(parser.jump(), "JNE");
-        }
+        assert_eq!(
+            c_fields("D=1;JMP"),
+            (
+                Some("D".to_string()),
+                "1".to_string(),
+                Some("JMP".to_string())
+            )
+        );
+        assert_eq!(
+            c_fields("M=D;JNE   // trailing comment"),
+            (
+                Some("M".to_string()),
+                "D".to_string(),
+                Some("JNE".to_string())
+            )
+        );
     }
 
     #[test]
     fn test_parse_a_instruction() {
-        {
-            let parser = Parser::new("@123");
+        assert_eq!(
+            parse("@123"),
+            vec![StatementKind::A(AInstruction::Literal(123))]
+        );
+        assert_eq!(
+            parse("@xxx"),
+            vec![StatementKind::A(AInstruction::Symbol("xxx".to_string()))]
+        );
+        assert_eq!(
+            parse("\t@xxx   // comment"),
+            vec![StatementKind::A(AInstruction::Symbol("xxx".to_string()))]
+        );
+    }
 
-            assert_eq!(parser.symbol(), "123")
+    #[test]
+    fn test_parse_literals() {
+        for (source, value) in [
+            ("@0x4000", 0x4000),
+            ("@0b101", 5),
+            ("@'A'", 65),
+            ("@(7)", 7),
+        ] {
+            match parse(source).remove(0) {
+                StatementKind::A(AInstruction::Literal(v)) => assert_eq!(v, value),
+                StatementKind::A(AInstruction::Expr(e)) => {
+                    assert_eq!(e.eval(&|_| None), Ok(value as i64))
+                }
+                s => panic!("expected a instruction, got {:?}", s),
+            }
         }
+    }
 
-        {
-            let parser = Parser::new("@xxx");
+    #[test]
+    fn test_parse_expression() {
+        let StatementKind::A(AInstruction::Expr(expr)) = parse("@SCREEN+32*4").remove(0) else {
+            panic!("expected expression");
+        };
+        let lookup = |s: &str| (s == "SCREEN").then_some(16384);
+        assert_eq!(expr.eval(&lookup), Ok(16384 + 128));
 
-            assert_eq!(parser.symbol(), "xxx")
-        }
+        let StatementKind::A(AInstruction::Expr(expr)) = parse("@-(END-START)/2").remove(0) else {
+            panic!("expected expression");
+        };
+        let lookup = |s: &str| match s {
+            "START" => Some(10),
+            "END" => Some(20),
+            _ => None,
+        };
+        assert_eq!(expr.eval(&lookup), Ok(-5));
+    }
 
-        {
-            let parser = Parser::new("@xxx   ");
+    #[test]
+    fn test_parse_equ() {
+        assert_eq!(
+            parse(".equ ROWS 0x10 // comment"),
+            vec![StatementKind::Equ {
+                name: "ROWS".to_string(),
+                value: Expr::Number(16)
+            }]
+        );
+        let diagnostics = Parser::new("test.asm", ".equ 1 2\n.org 3\n@0x\n@(1+2\n")
+            .parse()
+            .unwrap_err();
+        let found = diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                "expected a constant name, found `1`",
+                "unknown directive `.org`",
+                "invalid number `0x`",
+                "expected `)`",
+            ]
+        );
+    }
 
-            assert_eq!(parser.symbol(), "xxx   ")
-        }
+    #[test]
+    fn test_parse_macro() {
+        let found = parse(".macro MOV src, dst\n.endm\nMOV R0, 1+2\nPUSH_D\nD;JGT");
+        assert_eq!(
+            found[0],
+            StatementKind::MacroDef {
+                name: "MOV".to_string(),
+                params: vec!["src".to_string(), "dst".to_string()]
+            }
+        );
+        assert_eq!(found[1], StatementKind::EndMacro);
+        let StatementKind::MacroCall { name, args } = &found[2] else {
+            panic!("expected macro call, got {:?}", found[2]);
+        };
+        assert_eq!((name.as_str(), args.len()), ("MOV", 2));
+        assert!(matches!(&found[3], StatementKind::MacroCall { args, .. } if args.is_empty()));
+        assert!(matches!(found[4], StatementKind::C(_)));
     }
 
     #[test]
     fn test_parse_label() {
-        {
-            let parser = Parser::new("(abc)");
-
-            assert_eq!(parser.symbol(), "abc")
-        }
-
-        {
-            let parser = Parser::new("(LOOP  )");
-
-            assert_eq!(parser.symbol(), "LOOP  ")
-        }
+        assert_eq!(
+            parse("(abc)"),
+            vec![StatementKind::Label("abc".to_string())]
+        );
+        assert_eq!(
+            parse("(LOOP  )"),
+            vec![StatementKind::Label("LOOP".to_string())]
+        );
     }
 
     #[test]
-    fn test_advance() {
+    fn test_parse_program() {
         let source = r#"
 // some comment
 
@@ -293,29 +590,32 @@ D=M
   @R0
         "#;
 
-        let mut parser = Parser::new(source);
-
-        // move to first instruction
-        assert!(parser.has_more_lines());
-        parser.advance();
-        assert_eq!(parser.instruction_type(), InstructionType::InstA);
-
-        // next instruction
-        assert!(parser.has_more_lines());
-        parser.advance();
-        assert_eq!(parser.instruction_type(), InstructionType::InstC);
-
-        // next instruction
-        assert!(parser.has_more_lines());
-        parser.advance();
-        assert_eq!(parser.instruction_type(), InstructionType::InstL);
-
-        // next instruction
-        assert!(parser.has_more_lines());
-        parser.advance();
-        assert_eq!(parser.instruction_type(), InstructionType::InstA);
+        let statements = Parser::new("test.asm", source).parse().unwrap();
+        let lines = statements.iter().map(|s| s.span.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![5, 6, 7, 9]);
+        assert!(matches!(statements[0].kind, StatementKind::A(_)));
+        assert!(matches!(statements[1].kind, StatementKind::C(_)));
+        assert!(matches!(statements[2].kind, StatementKind::Label(_)));
+        assert!(matches!(statements[3].kind, StatementKind::A(_)));
+        assert_eq!(statements[3].span, Span::new(9, 3, 6));
+    }
 
-        // no instruction
-        assert_eq!(parser.has_more_lines(), false);
+    #[test]
+    fn test_parse_errors() {
+        let source = "@\n(LOOP\nD=;JMP\n@1 2\nD=A\n";
+        let diagnostics = Parser::new("test.asm", source).parse().unwrap_err();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (1, "expected a number or symbol"),
+                (2, "expected `)`"),
+                (3, "missing comp"),
+                (4, "expected end of line, found `2`"),
+            ]
+        );
     }
 }
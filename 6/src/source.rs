@@ -0,0 +1,133 @@
+use crate::ast::{FileId, Span, Statement};
+use crate::error::{Diagnostic, Note};
+
+#[derive(Debug, Clone)]
+pub(crate) struct SourceFile {
+    pub name: String,
+    pub text: String,
+    /// The `.include` which brought this file in
+    pub included_at: Option<Span>,
+}
+
+/// Where a ROM word comes from, as given by [`crate::Assembler::source_lines`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    /// The line without surrounding whitespace
+    pub text: String,
+}
+
+/// Every source file taking part in one assembly, indexed by [`FileId`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: &str, text: &str) -> FileId {
+        self.add_included(name, text, None)
+    }
+
+    pub fn add_included(&mut self, name: &str, text: &str, at: Option<Span>) -> FileId {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            text: text.to_string(),
+            included_at: at,
+        });
+        self.files.len() - 1
+    }
+
+    /// Forget every file added after the first `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.files.truncate(len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id]
+    }
+
+    pub fn file_mut(&mut self, id: FileId) -> &mut SourceFile {
+        &mut self.files[id]
+    }
+
+    pub fn line(&self, span: Span) -> &str {
+        self.files[span.file]
+            .text
+            .lines()
+            .nth(span.line - 1)
+            .unwrap_or("")
+    }
+
+    /// Where `span` appears in a file given on the command line, following `.include`s
+    pub fn root(&self, mut span: Span) -> Span {
+        while let Some(at) = self.files[span.file].included_at {
+            span = at;
+        }
+        span
+    }
+
+    /// Where `statement` appears outside of any macro, in the file it was
+    /// written in, used to place words in the listing
+    pub fn written_at(&self, statement: &Statement) -> Span {
+        statement
+            .expansion
+            .last()
+            .map_or(statement.span, |e| e.call)
+    }
+
+    /// Where part of `statement` appears outside of any macro or included file,
+    /// used to sort diagnostics
+    pub fn location(&self, statement: &Statement, span: Span) -> Span {
+        self.root(statement.expansion.last().map_or(span, |e| e.call))
+    }
+
+    pub fn diagnostic(&self, message: String, span: Span) -> Diagnostic {
+        let mut diagnostic = self.plain_diagnostic(message, span);
+        self.push_include_notes(&mut diagnostic, span);
+        diagnostic
+    }
+
+    fn plain_diagnostic(&self, message: String, span: Span) -> Diagnostic {
+        Diagnostic::new(message, &self.files[span.file].name, span, self.line(span))
+    }
+
+    fn push_include_notes(&self, diagnostic: &mut Diagnostic, mut span: Span) {
+        while let Some(at) = self.files[span.file].included_at {
+            diagnostic
+                .notes
+                .push(self.note("included from here".to_string(), at));
+            span = at;
+        }
+    }
+
+    pub fn note(&self, message: String, span: Span) -> Note {
+        Note::new(message, &self.files[span.file].name, span, self.line(span))
+    }
+
+    /// A diagnostic for part of `statement`, noting each macro call it was expanded from
+    pub fn statement_diagnostic(
+        &self,
+        statement: &Statement,
+        message: String,
+        span: Span,
+    ) -> Diagnostic {
+        let Some(outermost) = statement.expansion.last() else {
+            return self.diagnostic(message, span);
+        };
+        let mut diagnostic = self.plain_diagnostic(message, span);
+        for expansion in &statement.expansion {
+            diagnostic.notes.push(self.note(
+                format!("in this expansion of macro `{}`", expansion.name),
+                expansion.call,
+            ));
+        }
+        self.push_include_notes(&mut diagnostic, outermost.call);
+        diagnostic
+    }
+}
@@ -0,0 +1,252 @@
+//! Assembling a source as it is read
+//!
+//! Each line is parsed and encoded as soon as it is read, so only the encoded
+//! words and the symbols they use are kept until every label is known. Macros
+//! and `.include` need the whole program and are not supported.
+
+use std::io::BufRead;
+
+use crate::Assembler;
+use crate::ast::{AInstruction, Expr, Span, StatementKind};
+use crate::code::{self, Isa};
+use crate::error::{Diagnostic, Error};
+use crate::memory::{MemoryMap, Variables};
+use crate::parser::Parser;
+use crate::symbol::SymbolTable;
+
+/// A ROM word as kept between the two passes
+enum Pending {
+    Value(u16),
+    /// `@symbol`, with its span and line for reporting errors
+    Symbol(String, Span, String),
+    /// `@expression`, with its span and line for reporting errors
+    Expr(Expr, Span, String),
+}
+
+/// A `.equ` waiting for the symbols it refers to
+struct Constant {
+    name: String,
+    value: Expr,
+    span: Span,
+    line: String,
+}
+
+/// Assemble the lines of `reader` for a computer with memory map `map`, naming
+/// `file` in diagnostics, into the words and any warnings
+pub fn assemble(
+    file: &str,
+    reader: impl BufRead,
+    isa: Isa,
+    map: &MemoryMap,
+) -> Result<(Vec<u16>, Vec<Diagnostic>), Error> {
+    let mut table = SymbolTable::with_predefined(&map.symbols);
+    let mut pending = Vec::new();
+    let mut constants = Vec::new();
+    let mut diagnostics = Vec::new();
+    // spans from parsing a single line are on line 1
+    let on_line = |span: Span, n: usize| Span { line: n, ..span };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let n = i + 1;
+        let statements = match Parser::new(file, &line).parse() {
+            Ok(statements) => statements,
+            Err(errors) => {
+                diagnostics.extend(errors.into_iter().map(|d| Diagnostic { line: n, ..d }));
+                continue;
+            }
+        };
+        for statement in statements {
+            let span = on_line(statement.span, n);
+            let row = pending.len();
+            match statement.kind {
+                StatementKind::A(AInstruction::Literal(v)) => pending.push(Pending::Value(v)),
+                StatementKind::A(AInstruction::Symbol(name)) => {
+                    pending.push(Pending::Symbol(name, span, line.clone()))
+                }
+                StatementKind::A(AInstruction::Expr(expr)) => {
+                    pending.push(Pending::Expr(expr, span, line.clone()))
+                }
+                StatementKind::C(inst) => match code::encode_c(&inst, isa) {
+                    Ok(word) => pending.push(Pending::Value(word)),
+                    Err(errors) => {
+                        diagnostics.extend(errors.into_iter().map(|e| {
+                            Diagnostic::new(e.message, file, on_line(e.span, n), &line)
+                                .with_suggestion(e.suggestion)
+                        }));
+                        // keep the addresses of later labels right
+                        pending.push(Pending::Value(0));
+                    }
+                },
+                StatementKind::Label(name) => {
+                    if let Err(message) = table.add_label(&name, row) {
+                        diagnostics.push(Diagnostic::new(message, file, span, &line));
+                    }
+                }
+                StatementKind::Equ { name, value } => constants.push(Constant {
+                    name,
+                    value,
+                    span,
+                    line: line.clone(),
+                }),
+                StatementKind::Include(_)
+                | StatementKind::MacroDef { .. }
+                | StatementKind::EndMacro
+                | StatementKind::MacroCall { .. } => {
+                    let message =
+                        "macros and `.include` need the whole program, so can not be streamed"
+                            .to_string();
+                    diagnostics.push(Diagnostic::new(message, file, span, &line));
+                }
+            }
+            // report the first word past the end of ROM
+            if row as i64 == Assembler::MAX_VALUE + 1 && pending.len() > row {
+                let message = "program does not fit in the 32768 words of ROM".to_string();
+                diagnostics.push(Diagnostic::new(message, file, span, &line));
+            }
+        }
+    }
+
+    let unresolved = constants
+        .iter()
+        .map(|c| (c.name.as_str(), &c.value, c))
+        .collect();
+    for (c, message, at) in table.resolve_constants(unresolved) {
+        let span = at.map_or(c.span, |s| Span {
+            line: c.span.line,
+            ..s
+        });
+        diagnostics.push(Diagnostic::new(message, file, span, &c.line));
+    }
+
+    let mut words = Vec::with_capacity(pending.len());
+    let mut warnings = Vec::new();
+    let mut variables = Variables::default();
+    for word in pending {
+        let value = match word {
+            Pending::Value(v) => Ok(v),
+            Pending::Symbol(name, span, line) => match table.get_address(&name) {
+                Some(addr) => Ok(addr as u16),
+                None => match variables.allocate(map, &mut table, &name) {
+                    Ok((addr, warning)) => {
+                        if let Some(message) = warning {
+                            warnings.push(Diagnostic::new(message, file, span, &line).warning());
+                        }
+                        Ok(addr as u16)
+                    }
+                    Err(message) => Err(Diagnostic::new(message, file, span, &line)),
+                },
+            },
+            Pending::Expr(expr, span, line) => {
+                match expr.eval(&|s: &str| table.get_address(s).map(|a| a as i64)) {
+                    Ok(v) if (0..=Assembler::MAX_VALUE).contains(&v) => Ok(v as u16),
+                    Ok(v) => {
+                        let message = format!("value {} does not fit in 15 bits", v);
+                        Err(Diagnostic::new(message, file, span, &line))
+                    }
+                    Err(e) => {
+                        let span = e.span.map_or(span, |s| Span {
+                            line: span.line,
+                            ..s
+                        });
+                        Err(Diagnostic::new(e.message, file, span, &line))
+                    }
+                }
+            }
+        };
+        match value {
+            Ok(v) => words.push(v),
+            Err(d) => diagnostics.push(d),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok((words, warnings))
+    } else {
+        diagnostics.sort_by_key(|d| (d.line, d.columns.start));
+        Err(Error::Assemble(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(source: &str) -> Result<Vec<u16>, Error> {
+        assemble(
+            "<input>",
+            source.as_bytes(),
+            Isa::Standard,
+            &MemoryMap::default(),
+        )
+        .map(|(words, _)| words)
+    }
+
+    #[test]
+    fn test_same_as_assembler() {
+        for source in [
+            include_str!("../asm/Max.asm"),
+            include_str!("../asm/Rect.asm"),
+            include_str!("../asm/Pong.asm"),
+            ".equ ROW 32\n.equ SIZE ROW*2\n@SCREEN+SIZE\nD=A\n@x\nM=D\n(END)\n@END+1\n0;JMP\n",
+        ] {
+            assert_eq!(
+                stream(source).unwrap(),
+                Assembler::new(source).assemble().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_variable_on_screen() {
+        let map = MemoryMap::parse("board.map", "variables = 16383..16390").unwrap();
+        let (words, warnings) =
+            assemble("<input>", "@a\n@b\n".as_bytes(), Isa::Standard, &map).unwrap();
+        assert_eq!(words, [16383, 16384]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 2);
+        assert!(
+            warnings[0]
+                .to_string()
+                .starts_with("warning: variable `b` is allocated at 16384")
+        );
+    }
+
+    #[test]
+    fn test_errors_show_code() {
+        let map = MemoryMap::parse("board.map", "variables = 16..17").unwrap();
+        let Err(Error::Assemble(errors)) = assemble(
+            "<input>",
+            "@a\n    @b // no room\n".as_bytes(),
+            Isa::Standard,
+            &map,
+        ) else {
+            panic!("expected errors");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].columns.start), (2, 5));
+        assert_eq!(errors[0].text, "@b");
+
+        let source = "@0\n".repeat(32768) + "D=A\n";
+        let Err(Error::Assemble(errors)) = stream(&source) else {
+            panic!("expected errors");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 32769);
+        assert_eq!(errors[0].text, "D=A");
+    }
+
+    #[test]
+    fn test_errors() {
+        let Err(Error::Assemble(errors)) =
+            stream("@0\nD=Q\n@(1\n.macro M\n@UNDEFINED+1\n.equ X Y\n")
+        else {
+            panic!("expected errors");
+        };
+        let found = errors
+            .iter()
+            .map(|d| (d.line, d.columns.start))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![(2, 3), (3, 3), (4, 1), (5, 2), (6, 8)]);
+        assert_eq!(errors[4].text, "Y");
+    }
+}
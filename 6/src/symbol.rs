@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::Assembler;
+use crate::ast::{Expr, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    /// R0..R15, SP, SCREEN, ...
+    Predefined,
+    /// (xxx), a ROM address
+    Label,
+    /// @xxx without a label, a RAM address
+    Variable,
+    /// .equ xxx value
+    Constant,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Predefined => "predefined",
+            SymbolKind::Label => "label",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Constant => "constant",
+        }
+    }
+}
+
+pub(crate) const DEFAULT_SYMBOL: &[(&str, usize)] = &[
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+#[derive(Debug, Clone)]
+pub(crate) struct SymbolTable {
+    map: BTreeMap<String, (usize, SymbolKind)>,
+    /// How far each constant moves when the labels move by one, if at all
+    moves: BTreeMap<String, i64>,
+}
+
+impl SymbolTable {
+    pub(crate) fn new() -> Self {
+        let map = DEFAULT_SYMBOL
+            .iter()
+            .map(|(s, i)| (s.to_string(), (*i, SymbolKind::Predefined)))
+            .collect();
+        Self {
+            map,
+            moves: BTreeMap::new(),
+        }
+    }
+
+    /// A table with `symbols` as the predefined symbols
+    pub(crate) fn with_predefined(symbols: &BTreeMap<String, usize>) -> Self {
+        let map = symbols
+            .iter()
+            .map(|(s, i)| (s.clone(), (*i, SymbolKind::Predefined)))
+            .collect();
+        Self {
+            map,
+            moves: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn add_entry(&mut self, key: &str, addr: usize, kind: SymbolKind) {
+        let _ = self.map.insert(key.to_string(), (addr, kind));
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub(crate) fn get_address(&self, s: &str) -> Option<usize> {
+        self.map.get(s).map(|(addr, _)| *addr)
+    }
+
+    pub(crate) fn get(&self, s: &str) -> Option<(usize, SymbolKind)> {
+        self.map.get(s).copied()
+    }
+
+    /// How far `s` moves when the labels move by one: 1 for a label or a
+    /// constant naming a ROM address, 0 for a RAM address or a plain value
+    pub(crate) fn moves(&self, s: &str) -> i64 {
+        match self.map.get(s) {
+            Some((_, SymbolKind::Label)) => 1,
+            _ => self.moves.get(s).copied().unwrap_or(0),
+        }
+    }
+
+    /// Add the label `name` at ROM address `row`, unless it is already defined
+    pub(crate) fn add_label(&mut self, name: &str, row: usize) -> Result<(), String> {
+        if row as i64 > Assembler::MAX_VALUE {
+            return Err(format!(
+                "label `{}` at ROM address {} does not fit in 15 bits",
+                name, row
+            ));
+        }
+        if !self.contains(name) {
+            self.add_entry(name, row, SymbolKind::Label);
+        }
+        Ok(())
+    }
+
+    /// Add the value of each `(name, value, at)` constant, in whatever order
+    /// they refer to each other
+    ///
+    /// Returns the `at` of each constant which could not be added, with the
+    /// message and the span of the symbol at fault, if any.
+    pub(crate) fn resolve_constants<T>(
+        &mut self,
+        mut constants: Vec<(&str, &Expr, T)>,
+    ) -> Vec<(T, String, Option<Span>)> {
+        let mut errors = Vec::new();
+        // resolve them until nothing changes
+        loop {
+            let before = constants.len();
+            let mut unresolved = Vec::new();
+            for (name, value, at) in constants {
+                let Ok(v) = value.eval(&|s: &str| self.get_address(s).map(|a| a as i64)) else {
+                    unresolved.push((name, value, at));
+                    continue;
+                };
+                if self.contains(name) {
+                    errors.push((at, format!("`{}` is already defined", name), None));
+                } else if !(0..=Assembler::MAX_VALUE).contains(&v) {
+                    let message = format!("value {} of `{}` does not fit in 15 bits", v, name);
+                    errors.push((at, message, None));
+                } else {
+                    // evaluate again with the labels moved by one to tell ROM addresses
+                    let moved = value
+                        .eval(&|s: &str| self.get_address(s).map(|a| a as i64 + self.moves(s)))
+                        .unwrap();
+                    if moved != v {
+                        self.moves.insert(name.to_string(), moved - v);
+                    }
+                    self.add_entry(name, v as usize, SymbolKind::Constant);
+                }
+            }
+            constants = unresolved;
+            if constants.len() == before {
+                break;
+            }
+        }
+        for (name, value, at) in constants {
+            let error = value
+                .eval(&|s: &str| self.get_address(s).map(|a| a as i64))
+                .unwrap_err();
+            let message = format!("can not resolve `{}`: {}", name, error.message);
+            errors.push((at, message, error.span));
+        }
+        errors
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, usize, SymbolKind)> {
+        self.map
+            .iter()
+            .map(|(name, (addr, kind))| (name.as_str(), *addr, *kind))
+    }
+
+    /// Write one `NAME ADDRESS KIND` line per symbol, grouped by kind and sorted by address
+    ///
+    /// This is the format read by [`crate::disasm::SymbolMap::parse`].
+    pub(crate) fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let mut symbols = self.map.iter().collect::<Vec<_>>();
+        // R0 before SP, R1 before LCL and so on, as listed in DEFAULT_SYMBOL
+        let position = |name: &str| DEFAULT_SYMBOL.iter().position(|(s, _)| *s == name);
+        symbols.sort_by_key(|(name, (addr, kind))| (*kind, *addr, position(name), *name));
+        let width = symbols
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, (addr, kind)) in symbols {
+            writeln!(writer, "{:width$} {:>5} {}", name, addr, kind.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut table = SymbolTable::new();
+        table.add_entry("LOOP", 4, SymbolKind::Label);
+        table.add_entry("i", 16, SymbolKind::Variable);
+
+        let mut buf = Vec::new();
+        table.write(&mut buf).unwrap();
+        let out = String::from_utf8(buf).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "R0         0 predefined");
+        assert_eq!(lines[1], "SP         0 predefined");
+        assert_eq!(lines[2], "R1         1 predefined");
+        assert_eq!(lines[3], "LCL        1 predefined");
+        assert_eq!(lines[lines.len() - 2], "LOOP       4 label");
+        assert_eq!(lines[lines.len() - 1], "i         16 variable");
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+/// An empty directory for the files of one test
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("assembler-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn assembler(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_assembler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_canonical_in_place() {
+    let dir = scratch("canonical");
+    let path = dir.join("Prog.asm");
+    std::fs::write(&path, "MD=A+D\n@LOOP\nMA=1+M;JMP\n").unwrap();
+    let path = path.to_str().unwrap();
+    let output = assembler(&["--canonical", "-o", path, path]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(path).unwrap(),
+        "DM=D+A\n@LOOP\nAM=M+1;JMP\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_error_keeps_output() {
+    let dir = scratch("error");
+    let (input, output) = (dir.join("Bad.asm"), dir.join("Bad.hack"));
+    std::fs::write(&input, "@(\n").unwrap();
+    std::fs::write(&output, "0000000000000000\n").unwrap();
+    let result = assembler(&["-o", output.to_str().unwrap(), input.to_str().unwrap()]);
+    assert_eq!(result.status.code(), Some(1));
+    assert!(
+        String::from_utf8(result.stderr)
+            .unwrap()
+            .ends_with("error: could not assemble due to 1 error\n")
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "0000000000000000\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_ignored_options() {
+    for args in [
+        &["-c", "--format", "hex"][..],
+        &["--stream", "--lint"],
+        &["--stream", "-O"],
+        &["--canonical", "--strict"],
+        &["--canonical", "--stream"],
+    ] {
+        assert_eq!(assembler(args).status.code(), Some(2), "{:?}", args);
+    }
+}
@@ -0,0 +1,15 @@
+// Tests PointerTest.asm on the CPU emulator.
+// Sets THIS and THAT through the pointer segment.
+
+load PointerTest.asm,
+output-file PointerTest.out,
+compare-to PointerTest.cmp,
+output-list RAM[256]%D1.6.1 RAM[3]%D1.6.1 RAM[4]%D1.6.1 RAM[3032]%D1.6.1 RAM[3046]%D1.6.1;
+
+set RAM[0] 256,
+
+repeat 450 {
+  ticktock;
+}
+
+output;
@@ -0,0 +1,15 @@
+// Tests PointerTest.vm on the VM emulator.
+// Sets THIS and THAT through the pointer segment.
+
+load PointerTest.vm,
+output-file PointerTestVME.out,
+compare-to PointerTest.cmp,
+output-list RAM[256]%D1.6.1 RAM[3]%D1.6.1 RAM[4]%D1.6.1 RAM[3032]%D1.6.1 RAM[3046]%D1.6.1;
+
+set sp 256,
+
+repeat 15 {
+  vmstep;
+}
+
+output;
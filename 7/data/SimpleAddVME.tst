@@ -0,0 +1,15 @@
+// Tests SimpleAdd.vm on the VM emulator.
+// Pushes two constants and adds them.
+
+load SimpleAdd.vm,
+output-file SimpleAddVME.out,
+compare-to SimpleAdd.cmp,
+output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2;
+
+set sp 256,
+
+repeat 3 {
+  vmstep;
+}
+
+output;
@@ -0,0 +1,15 @@
+// Tests StackTest.asm on the CPU emulator.
+// Runs comparisons and logical operations on the stack.
+
+load StackTest.asm,
+output-file StackTest.out,
+compare-to StackTest.cmp,
+output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2 RAM[257]%D2.6.2 RAM[258]%D2.6.2 RAM[259]%D2.6.2 RAM[260]%D2.6.2 RAM[261]%D2.6.2 RAM[262]%D2.6.2 RAM[263]%D2.6.2 RAM[264]%D2.6.2 RAM[265]%D2.6.2;
+
+set RAM[0] 256,
+
+repeat 1000 {
+  ticktock;
+}
+
+output;
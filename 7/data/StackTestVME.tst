@@ -0,0 +1,15 @@
+// Tests StackTest.vm on the VM emulator.
+// Runs comparisons and logical operations on the stack.
+
+load StackTest.vm,
+output-file StackTestVME.out,
+compare-to StackTest.cmp,
+output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2 RAM[257]%D2.6.2 RAM[258]%D2.6.2 RAM[259]%D2.6.2 RAM[260]%D2.6.2 RAM[261]%D2.6.2 RAM[262]%D2.6.2 RAM[263]%D2.6.2 RAM[264]%D2.6.2 RAM[265]%D2.6.2;
+
+set sp 256,
+
+repeat 38 {
+  vmstep;
+}
+
+output;
@@ -0,0 +1,15 @@
+// Tests StaticTest.asm on the CPU emulator.
+// Pushes and pops through the static segment.
+
+load StaticTest.asm,
+output-file StaticTest.out,
+compare-to StaticTest.cmp,
+output-list RAM[256]%D1.6.1;
+
+set RAM[0] 256,
+
+repeat 200 {
+  ticktock;
+}
+
+output;
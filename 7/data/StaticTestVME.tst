@@ -0,0 +1,15 @@
+// Tests StaticTest.vm on the VM emulator.
+// Pushes and pops through the static segment.
+
+load StaticTest.vm,
+output-file StaticTestVME.out,
+compare-to StaticTest.cmp,
+output-list RAM[256]%D1.6.1;
+
+set sp 256,
+
+repeat 11 {
+  vmstep;
+}
+
+output;
@@ -76,3 +76,87 @@ pub(crate) enum PushPop {
     Push,
     Pop,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Machine;
+
+    /// `source` translated to assembly
+    fn translate(ident: &str, source: &str) -> String {
+        let mut asm = Vec::new();
+        let parser = Parser::new(&mut source.as_bytes());
+        VmTranslator::new(parser, CodeWriter::new(&mut asm, ident.to_string()))
+            .translate()
+            .unwrap();
+        String::from_utf8(asm).unwrap()
+    }
+
+    /// Run the translation of `source` for `cycles` instructions after
+    /// setting `ram`, and read the words at `addresses`
+    fn run(
+        ident: &str,
+        source: &str,
+        ram: &[(usize, i16)],
+        cycles: u64,
+        addresses: &[usize],
+    ) -> Vec<i16> {
+        let mut machine = Machine::with_program(&translate(ident, source), ram).unwrap();
+        machine.run_for(cycles);
+        addresses.iter().map(|&a| machine.ram()[a] as i16).collect()
+    }
+
+    #[test]
+    fn test_simple_add() {
+        let source = include_str!("../data/SimpleAdd.vm");
+        let words = run("SimpleAdd", source, &[(0, 256)], 60, &[0, 256]);
+        assert_eq!(words, [257, 15]);
+    }
+
+    #[test]
+    fn test_stack_test() {
+        let source = include_str!("../data/StackTest.vm");
+        let words = run(
+            "StackTest",
+            source,
+            &[(0, 256)],
+            1000,
+            &[0, 256, 257, 258, 259, 260, 261, 262, 263, 264, 265],
+        );
+        assert_eq!(words, [266, -1, 0, 0, 0, -1, 0, -1, 0, 0, -91]);
+    }
+
+    #[test]
+    fn test_basic_test() {
+        let source = include_str!("../data/BasicTest.vm");
+        let ram = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+        let words = run(
+            "BasicTest",
+            source,
+            &ram,
+            600,
+            &[256, 300, 401, 402, 3006, 3012, 3015, 11],
+        );
+        assert_eq!(words, [472, 10, 21, 22, 36, 42, 45, 510]);
+    }
+
+    #[test]
+    fn test_pointer_test() {
+        let source = include_str!("../data/PointerTest.vm");
+        let words = run(
+            "PointerTest",
+            source,
+            &[(0, 256)],
+            450,
+            &[256, 3, 4, 3032, 3046],
+        );
+        assert_eq!(words, [6084, 3030, 3040, 32, 46]);
+    }
+
+    #[test]
+    fn test_static_test() {
+        let source = include_str!("../data/StaticTest.vm");
+        let words = run("StaticTest", source, &[(0, 256)], 200, &[256]);
+        assert_eq!(words, [1110]);
+    }
+}
@@ -63,10 +63,10 @@ impl Parsable for ArithmeticCommand {
 impl Parsable for PushPopCommand {
     type Output = Self;
     fn parse(line: &str) -> Option<Self::Output> {
-        let mut parts = line.trim().split_whitespace();
+        let mut parts = line.split_whitespace();
 
-        let kind = parts.next().and_then(|s| PushPop::parse(s))?;
-        let segment = parts.next().and_then(|s| Segment::parse(s))?;
+        let kind = parts.next().and_then(PushPop::parse)?;
+        let segment = parts.next().and_then(Segment::parse)?;
         let index = parts.next().and_then(|s| s.parse::<u16>().ok())?;
         if parts.next().is_some() {
             return None; // Ensure no extra parts are present
@@ -166,7 +166,6 @@ impl<W: std::io::Write> CodeWriter<W> {
                 self.write_line("@R13")?;
                 self.write_line("A=M")?;
                 self.write_line("M=D")?;
-
             }
             PushPop::Push => {
                 // Dレジスタにセグメントの値を読み込む
@@ -180,7 +179,7 @@ impl<W: std::io::Write> CodeWriter<W> {
                         self.write_line("D=M")?;
                     }
                 }
-                
+
                 self.set_stack_top()?;
                 self.write_line("M=D")?;
                 self.advance_stack()?;
@@ -193,10 +192,10 @@ impl<W: std::io::Write> CodeWriter<W> {
     /// 指定されたセグメントを指すようにAレジスタを設定する
     fn set_segment_addr(&mut self, segment: &Segment, index: u16) -> std::io::Result<()> {
         let addr = match segment {
-            Segment::Local => format!("@LCL"),
-            Segment::Argument => format!("@ARG"),
-            Segment::This => format!("@THIS"),
-            Segment::That => format!("@THAT"),
+            Segment::Local => "@LCL".to_string(),
+            Segment::Argument => "@ARG".to_string(),
+            Segment::This => "@THIS".to_string(),
+            Segment::That => "@THAT".to_string(),
             Segment::Constant => unreachable!("Constant segment can not be used for address"),
             Segment::Static => {
                 if index > 240 {
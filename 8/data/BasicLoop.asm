@@ -1,7 +1,3 @@
-@256
-D=A
-@SP
-M=D
 @0
 D=A
 @SP
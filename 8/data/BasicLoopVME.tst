@@ -0,0 +1,18 @@
+// Tests BasicLoop.vm on the VM emulator.
+// Sums 1 + 2 + ... + argument[0].
+
+load BasicLoop.vm,
+output-file BasicLoopVME.out,
+compare-to BasicLoop.cmp,
+output-list RAM[0]%D1.6.1 RAM[256]%D1.6.1;
+
+set sp 256,
+set local 300,
+set argument 400,
+set argument[0] 3,
+
+repeat 36 {
+  vmstep;
+}
+
+output;
@@ -1,7 +1,3 @@
-@256
-D=A
-@SP
-M=D
 @ARG
 D=M
 @1
@@ -0,0 +1,19 @@
+// Tests FibonacciSeries.vm on the VM emulator.
+// Stores argument[0] Fibonacci numbers from the address in argument[1].
+
+load FibonacciSeries.vm,
+output-file FibonacciSeriesVME.out,
+compare-to FibonacciSeries.cmp,
+output-list RAM[3000]%D1.6.2 RAM[3001]%D1.6.2 RAM[3002]%D1.6.2 RAM[3003]%D1.6.2 RAM[3004]%D1.6.2 RAM[3005]%D1.6.2;
+
+set sp 256,
+set local 300,
+set argument 400,
+set argument[0] 6,
+set argument[1] 3000,
+
+repeat 73 {
+  vmstep;
+}
+
+output;
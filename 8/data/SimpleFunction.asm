@@ -1,18 +1,16 @@
-@256
-D=A
+(SimpleFunction.test)
 @SP
-M=D
-(SimpleFunction.SimpleFunction.test)
-@LCL
-D=M
-@0
-A=D+A
+A=M
 M=0
-@LCL
-D=M
-@1
-A=D+A
+@SP
+M=M+1
+A=M
+@SP
+A=M
 M=0
+@SP
+M=M+1
+A=M
 @LCL
 D=M
 @0
@@ -0,0 +1,26 @@
+// Tests SimpleFunction.vm on the VM emulator.
+// Calls SimpleFunction.test from a frame set up by hand.
+
+load SimpleFunction.vm,
+output-file SimpleFunctionVME.out,
+compare-to SimpleFunction.cmp,
+output-list RAM[0]%D1.6.1 RAM[1]%D1.6.1 RAM[2]%D1.6.1 RAM[3]%D1.6.1 RAM[4]%D1.6.1 RAM[310]%D1.6.1;
+
+set sp 317,
+set local 317,
+set argument 310,
+set this 3000,
+set that 4000,
+set argument[0] 1234,
+set argument[1] 37,
+set argument[2] 1000,
+set argument[3] 305,
+set argument[4] 300,
+set argument[5] 3010,
+set argument[6] 4010,
+
+repeat 10 {
+  vmstep;
+}
+
+output;
@@ -7,15 +7,27 @@ pub use writer::CodeWriter;
 pub struct VmTranslator<W> {
     parser: parser::Parser,
     writer: writer::CodeWriter<W>,
+    bootstrap: bool,
 }
 
 impl<W: std::io::Write> VmTranslator<W> {
     pub fn new(parser: Parser, writer: writer::CodeWriter<W>) -> Self {
-        VmTranslator { parser, writer }
+        VmTranslator {
+            parser,
+            writer,
+            bootstrap: false,
+        }
+    }
+
+    /// 先頭にSys.initを呼び出すブートストラップコードを出力するかどうか
+    pub fn set_bootstrap(&mut self, bootstrap: bool) {
+        self.bootstrap = bootstrap;
     }
 
     pub fn translate(&mut self) -> std::io::Result<()> {
-        self.writer.init()?;
+        if self.bootstrap {
+            self.writer.init()?;
+        }
         while self.parser.has_more_lines() {
             if let Some(command) = self.parser.advance() {
                 match command {
@@ -92,3 +104,130 @@ pub(crate) enum PushPop {
     Push,
     Pop,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Machine;
+
+    /// `source` translated to assembly
+    fn translate(ident: &str, source: &str) -> String {
+        let mut asm = Vec::new();
+        let parser = Parser::new(&mut source.as_bytes());
+        VmTranslator::new(parser, CodeWriter::new(&mut asm, ident.to_string()))
+            .translate()
+            .unwrap();
+        String::from_utf8(asm).unwrap()
+    }
+
+    /// Run the translation of `source` for `cycles` instructions after
+    /// setting `ram`, and read the words at `addresses`
+    fn run(
+        ident: &str,
+        source: &str,
+        ram: &[(usize, i16)],
+        cycles: u64,
+        addresses: &[usize],
+    ) -> Vec<i16> {
+        let mut machine = Machine::with_program(&translate(ident, source), ram).unwrap();
+        machine.run_for(cycles);
+        addresses.iter().map(|&a| machine.ram()[a] as i16).collect()
+    }
+
+    #[test]
+    fn test_indented_lines() {
+        let source = "push constant 1\r\npush constant 2\r\npush constant 3\r\n        add\r\n";
+        assert_eq!(run("Indent", source, &[(0, 256)], 100, &[0, 257]), [258, 5]);
+    }
+
+    #[test]
+    fn test_function_locals() {
+        // locals start as 0 on the stack, below anything the function pushes
+        let source = "function Test.f 2\npush constant 9\n";
+        let ram = [(0, 256), (1, 256), (256, 5), (257, 5)];
+        let words = run("Test", source, &ram, 100, &[0, 256, 257, 258]);
+        assert_eq!(words, [259, 0, 0, 9]);
+    }
+
+    #[test]
+    fn test_call_arguments() {
+        let source = "push constant 3\npush constant 4\ncall Test.sub 2\n\
+                      label END\ngoto END\n\
+                      function Test.sub 0\npush argument 0\npush argument 1\nsub\nreturn\n";
+        let words = run("Test", source, &[(0, 256)], 300, &[0, 256]);
+        assert_eq!(words, [257, -1]);
+    }
+
+    #[test]
+    fn test_labels_after_return() {
+        // NONZERO follows a return but is still in Test.f
+        let source = "push constant 1\ncall Test.f 1\nlabel END\ngoto END\n\
+                      function Test.f 0\npush argument 0\nif-goto NONZERO\n\
+                      push constant 10\nreturn\n\
+                      label NONZERO\npush constant 20\nreturn\n";
+        let words = run("Test", source, &[(0, 256)], 300, &[0, 256]);
+        assert_eq!(words, [257, 20]);
+    }
+
+    #[test]
+    fn test_basic_loop() {
+        let source = include_str!("../data/BasicLoop.vm");
+        let ram = [(0, 256), (1, 300), (2, 400), (400, 3)];
+        assert_eq!(run("BasicLoop", source, &ram, 600, &[0, 256]), [257, 6]);
+    }
+
+    #[test]
+    fn test_fibonacci_series() {
+        let source = include_str!("../data/FibonacciSeries.vm");
+        let ram = [(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)];
+        let words = run(
+            "FibonacciSeries",
+            source,
+            &ram,
+            1100,
+            &[3000, 3001, 3002, 3003, 3004, 3005],
+        );
+        assert_eq!(words, [0, 1, 1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn test_simple_function() {
+        let source = include_str!("../data/SimpleFunction.vm");
+        let ram = [
+            (0, 317),
+            (1, 317),
+            (2, 310),
+            (3, 3000),
+            (4, 4000),
+            (310, 1234),
+            (311, 37),
+            (312, 1000),
+            (313, 305),
+            (314, 300),
+            (315, 3010),
+            (316, 4010),
+        ];
+        let words = run("SimpleFunction", source, &ram, 300, &[0, 1, 2, 3, 4, 310]);
+        assert_eq!(words, [311, 305, 300, 3010, 4010, 1196]);
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let source = "function Sys.init 0\npush constant 7\ncall Sys.double 1\n\
+                      label END\ngoto END\n\
+                      function Sys.double 1\npush argument 0\npush argument 0\nadd\n\
+                      pop local 0\npush local 0\nreturn\n";
+        let mut asm = Vec::new();
+        let mut translator = VmTranslator::new(
+            Parser::new(&mut source.as_bytes()),
+            CodeWriter::new(&mut asm, "Sys".to_string()),
+        );
+        translator.set_bootstrap(true);
+        translator.translate().unwrap();
+
+        let mut machine = Machine::with_program(&String::from_utf8(asm).unwrap(), &[]).unwrap();
+        machine.run_for(500);
+        // only the return value is left above the frame of Sys.init
+        assert_eq!([machine.ram()[0], machine.ram()[261]], [262, 14]);
+    }
+}
@@ -13,8 +13,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        let file = std::fs::File::open(path)?;
-        let parser = Parser::new(&mut std::io::BufReader::new(file));
+        let source = std::fs::read_to_string(path)?;
+        let parser = Parser::new(&mut source.as_bytes());
 
         let ident = path
             .file_stem()
@@ -26,6 +26,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let writer = CodeWriter::new(std::io::BufWriter::new(output_file), ident);
 
         let mut translator = VmTranslator::new(parser, writer);
+        // Sys.initがあるプログラムだけブートストラップコードから始める
+        translator.set_bootstrap(
+            source
+                .lines()
+                .any(|line| line.split_whitespace().take(2).eq(["function", "Sys.init"])),
+        );
         translator.translate()?;
     } else {
         eprintln!("Error: missing file argument");
@@ -17,10 +17,12 @@ impl Parser {
     }
 
     pub(crate) fn advance(&mut self) -> Option<Command> {
-        let line = self.source[self.cur_pos..].lines().next()?.trim();
-        let cmd = Command::parse(line);
-        self.cur_pos += line.len() + 1; // Move past the line and newline character
-        cmd
+        let rest = &self.source[self.cur_pos..];
+        let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let line = rest[..len].trim();
+        // インデントや改行コードも含めて行全体を読み進める
+        self.cur_pos += len;
+        Command::parse(line)
     }
 }
 
@@ -61,7 +63,7 @@ fn parse_jump(line: &str) -> Option<Command> {
 }
 
 fn parse_function(line: &str) -> Option<Command> {
-    let mut parts = line.trim().split_whitespace();
+    let mut parts = line.split_whitespace();
     let func_tag = parts.next()?;
     if func_tag != "function" {
         return None; // Ensure the command is a function declaration
@@ -79,7 +81,7 @@ fn parse_function(line: &str) -> Option<Command> {
 }
 
 fn parse_call(line: &str) -> Option<Command> {
-    let mut parts = line.trim().split_whitespace();
+    let mut parts = line.split_whitespace();
     let call_tag = parts.next()?;
     if call_tag != "call" {
         return None; // Ensure the command is a call declaration
@@ -97,7 +99,7 @@ fn parse_call(line: &str) -> Option<Command> {
 }
 
 fn parse_return(line: &str) -> Option<Command> {
-    let mut parts = line.trim().split_whitespace();
+    let mut parts = line.split_whitespace();
     let return_tag = parts.next()?;
     if return_tag != "return" {
         return None; // Ensure the command is a return declaration
@@ -124,7 +126,7 @@ fn is_not_comment(line: &str) -> bool {
 impl Parsable for ArithmeticCommand {
     type Output = Self;
     fn parse(line: &str) -> Option<Self::Output> {
-        let mut parts = line.trim().split_whitespace();
+        let mut parts = line.split_whitespace();
         let command = parts.next()?;
         if parts.next().map(is_not_comment).unwrap_or(false) {
             return None; // Ensure no extra parts are present
@@ -147,10 +149,10 @@ impl Parsable for ArithmeticCommand {
 impl Parsable for PushPopCommand {
     type Output = Self;
     fn parse(line: &str) -> Option<Self::Output> {
-        let mut parts = line.trim().split_whitespace();
+        let mut parts = line.split_whitespace();
 
-        let kind = parts.next().and_then(|s| PushPop::parse(s))?;
-        let segment = parts.next().and_then(|s| Segment::parse(s))?;
+        let kind = parts.next().and_then(PushPop::parse)?;
+        let segment = parts.next().and_then(Segment::parse)?;
         let index = parts.next().and_then(|s| s.parse::<u16>().ok())?;
         if parts.next().map(is_not_comment).unwrap_or(false) {
             return None; // Ensure no extra parts are present
@@ -3,7 +3,7 @@ use super::{ArithmeticCommand, PushPop, PushPopCommand, Segment};
 pub struct CodeWriter<W> {
     output: W,
     ident: String,
-    function_names: Vec<String>,
+    function_name: Option<String>,
     jmp_count: u16,
 }
 
@@ -12,7 +12,7 @@ impl<W: std::io::Write> CodeWriter<W> {
         CodeWriter {
             output,
             ident,
-            function_names: Vec::new(),
+            function_name: None,
             jmp_count: 0,
         }
     }
@@ -22,21 +22,21 @@ impl<W: std::io::Write> CodeWriter<W> {
         self.jmp_count = 0; // Reset jump count when identifier changes
     }
 
+    /// ラベルの接頭辞に使う現在の関数名。関数の外ではファイル名
     fn function_name(&self) -> String {
-        if let Some(name) = self.function_names.last() {
-            format!("{}.{}", self.ident, name)
-        } else {
-            self.ident.clone()
-        }
+        self.function_name
+            .clone()
+            .unwrap_or_else(|| self.ident.clone())
     }
 
+    /// ブートストラップコード。SPを256にしてSys.initを呼び出す
     pub fn init(&mut self) -> std::io::Result<()> {
         // スタックポインタを初期化
         writeln!(self.output, "@256")?;
         writeln!(self.output, "D=A")?;
         writeln!(self.output, "@SP")?;
         writeln!(self.output, "M=D")?;
-        Ok(())
+        self.write_call("Sys.init", 0)
     }
 
     pub fn finalize(&mut self) -> std::io::Result<()> {
@@ -84,7 +84,7 @@ impl<W: std::io::Write> CodeWriter<W> {
                 writeln!(self.output, "0;JMP")?;
 
                 // EQ_TRUEにジャンプした場合の処理(-1は補数で11111111)
-                writeln!(self.output, "{}", format!("({})", when_true))?;
+                writeln!(self.output, "({})", when_true)?;
                 writeln!(self.output, "D=-1")?;
 
                 // EQ_ENDにジャンプ
@@ -225,16 +225,14 @@ impl<W: std::io::Write> CodeWriter<W> {
 
     pub(crate) fn write_function(&mut self, name: &str, n_vars: u16) -> std::io::Result<()> {
         // 関数のラベルをつける
-        writeln!(self.output, "({}.{})", self.ident, name)?;
-        self.function_names.push(name.to_string());
+        writeln!(self.output, "({})", name)?;
+        self.function_name = Some(name.to_string());
 
-        // LCLを初期化
-        for i in 0..n_vars {
-            writeln!(self.output, "@LCL")?;
-            writeln!(self.output, "D=M")?;
-            writeln!(self.output, "@{}", i)?;
-            writeln!(self.output, "A=D+A")?;
+        // ローカル変数の数だけ0をプッシュする
+        for _ in 0..n_vars {
+            self.set_stack_top()?;
             writeln!(self.output, "M=0")?;
+            self.advance_stack()?;
         }
 
         Ok(())
@@ -267,7 +265,7 @@ impl<W: std::io::Write> CodeWriter<W> {
         // ARGを設定
         writeln!(self.output, "@SP")?;
         writeln!(self.output, "D=M")?;
-        writeln!(self.output, "@{}", 5 - n_args)?;
+        writeln!(self.output, "@{}", n_args + 5)?;
         writeln!(self.output, "D=D-A")?;
         writeln!(self.output, "@ARG")?;
         writeln!(self.output, "M=D")?;
@@ -279,7 +277,7 @@ impl<W: std::io::Write> CodeWriter<W> {
         writeln!(self.output, "M=D")?;
 
         // 関数を呼び出す
-        writeln!(self.output, "@{}.{}", self.ident, name)?;
+        writeln!(self.output, "@{}", name)?;
         writeln!(self.output, "0;JMP")?;
 
         // 戻りラベル
@@ -328,20 +326,16 @@ impl<W: std::io::Write> CodeWriter<W> {
         writeln!(self.output, "@R13")?;
         writeln!(self.output, "A=M")?;
         writeln!(self.output, "0;JMP")?;
-
-        // 関数から抜ける
-        self.function_names.pop();
-
         Ok(())
     }
 
     /// 指定されたセグメントを指すようにAレジスタを設定する
     fn set_segment_addr(&mut self, segment: &Segment, index: u16) -> std::io::Result<()> {
         let addr = match segment {
-            Segment::Local => format!("@LCL"),
-            Segment::Argument => format!("@ARG"),
-            Segment::This => format!("@THIS"),
-            Segment::That => format!("@THAT"),
+            Segment::Local => "@LCL".to_string(),
+            Segment::Argument => "@ARG".to_string(),
+            Segment::This => "@THIS".to_string(),
+            Segment::That => "@THAT".to_string(),
             Segment::Constant => unreachable!("Constant segment can not be used for address"),
             Segment::Static => {
                 if index > 240 {
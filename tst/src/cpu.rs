@@ -0,0 +1,122 @@
+//! The CPU emulator backend, running a Hack program on [`emulator::Machine`]
+//!
+//! Scripts can read `RAM[N]`, `ROM32K[N]`, `A`, `D`, `PC` and `time`, the
+//! number of instructions run, set `RAM[N]`, `A`, `D` and `PC`, and step with `ticktock`.
+
+use std::path::Path;
+
+use assembler::{Assembler, disasm};
+use emulator::Machine;
+
+use crate::{Backend, indexed};
+
+pub struct Cpu {
+    machine: Machine,
+}
+
+impl Cpu {
+    /// Load a `.asm` source, assembling it, or a `.hack` binary
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let name = path.display().to_string();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", name, e))?;
+        Self::parse(&name, &source)
+    }
+
+    /// Assemble `source` if `name` is a `.asm` file, or read it as a `.hack` binary
+    pub fn parse(name: &str, source: &str) -> Result<Self, String> {
+        let words = if name.ends_with(".asm") {
+            let mut asm = Assembler::new(source);
+            asm.set_file_name(name);
+            asm.assemble().map_err(|e| e.to_string())?
+        } else {
+            disasm::read_hack(name, source).map_err(|diagnostics| {
+                diagnostics
+                    .iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })?
+        };
+        if words.len() > emulator::ROM_SIZE {
+            return Err(format!("{} does not fit in ROM", name));
+        }
+        Ok(Self::new(&words))
+    }
+
+    pub fn new(words: &[u16]) -> Self {
+        let mut machine = Machine::new();
+        machine.load_rom(words);
+        Self { machine }
+    }
+}
+
+impl Backend for Cpu {
+    fn get(&self, variable: &str) -> Result<i16, String> {
+        let word = match variable {
+            "A" => self.machine.a(),
+            "D" => self.machine.d(),
+            "PC" => self.machine.pc(),
+            "time" => self.machine.cycles() as u16,
+            _ => match indexed(variable) {
+                Some(("RAM", address)) if address < 0x8000 => {
+                    self.machine.ram().get(address).copied().unwrap_or(0)
+                }
+                Some(("ROM32K", address)) if address < emulator::ROM_SIZE => {
+                    self.machine.rom()[address]
+                }
+                _ => return Err(format!("unknown variable `{}`", variable)),
+            },
+        };
+        Ok(word as i16)
+    }
+
+    fn set(&mut self, variable: &str, value: i16) -> Result<(), String> {
+        let word = value as u16;
+        match variable {
+            "A" => self.machine.set_a(word),
+            "D" => self.machine.set_d(word),
+            "PC" => self.machine.set_pc(word),
+            _ => match indexed(variable) {
+                Some(("RAM", address)) if address < self.machine.ram().len() => {
+                    self.machine.ram_mut()[address] = word;
+                }
+                _ => return Err(format!("cannot set `{}`", variable)),
+            },
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, command: &str) -> Result<(), String> {
+        match command {
+            "ticktock" => {
+                self.machine.step();
+                Ok(())
+            }
+            _ => Err(format!("`{}` is not a CPU emulator command", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_registers() {
+        // starting at D=D+A skips @5
+        let mut cpu = Cpu::parse("Add.asm", "@5\nD=D+A\nM=D\n@2\nM=D\n").unwrap();
+        cpu.set("PC", 1).unwrap();
+        cpu.set("A", 3).unwrap();
+        cpu.set("D", 4).unwrap();
+        for _ in 0..2 {
+            cpu.step("ticktock").unwrap();
+        }
+        assert_eq!(cpu.get("PC"), Ok(3));
+        assert_eq!(cpu.get("RAM[3]"), Ok(7));
+        assert_eq!(
+            cpu.set("ROM32K[0]", 1),
+            Err("cannot set `ROM32K[0]`".into())
+        );
+    }
+}
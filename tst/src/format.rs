@@ -0,0 +1,147 @@
+//! Columns of an `output-list`
+//!
+//! A column is a variable and a format `%FL.W.R`: `F` is `B` binary, `D`
+//! decimal, `X` hexadecimal or `S` string, with `L` spaces to the left of a
+//! field `W` characters wide and `R` spaces to the right. Each line of output
+//! puts a `|` before every column and after the last.
+
+use std::fmt;
+
+/// One column of output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub format: char,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+impl Column {
+    /// Parse `NAME` or `NAME%FL.W.R`, where a bare name is `%D1.6.1`
+    pub fn parse(item: &str) -> Result<Self, String> {
+        let Some((name, format)) = item.split_once('%') else {
+            return Ok(Self {
+                name: item.to_string(),
+                format: 'D',
+                pad_left: 1,
+                len: 6,
+                pad_right: 1,
+            });
+        };
+        let bad = || format!("bad output format `{}`, expected NAME%FL.W.R", item);
+        let mut chars = format.chars();
+        let kind = chars
+            .next()
+            .filter(|c| matches!(c, 'B' | 'D' | 'X' | 'S'))
+            .ok_or_else(bad)?;
+        let sizes = chars
+            .as_str()
+            .split('.')
+            .map(|n| n.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+        let [pad_left, len, pad_right] = sizes[..] else {
+            return Err(bad());
+        };
+        if name.is_empty() || len == 0 {
+            return Err(bad());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            format: kind,
+            pad_left,
+            len,
+            pad_right,
+        })
+    }
+
+    fn width(&self) -> usize {
+        self.pad_left + self.len + self.pad_right
+    }
+
+    /// The name centred in the column, cut to fit
+    pub fn header(&self) -> String {
+        let name = self.name.chars().take(self.width()).collect::<String>();
+        let space = self.width() - name.chars().count();
+        let left = space / 2;
+        format!("{:left$}{}{:right$}", "", name, "", right = space - left)
+    }
+
+    /// `value` in the format of the column
+    pub fn cell(&self, value: i16) -> String {
+        let len = self.len;
+        let text = match self.format {
+            'B' => format!("{:0len$b}", value as u16),
+            'X' => format!("{:0len$X}", value as u16),
+            'S' => format!("{:<len$}", value),
+            _ => format!("{:>len$}", value),
+        };
+        // binary and hexadecimal keep their lowest digits
+        let text = match self.format {
+            'B' | 'X' => text[text.len() - len..].to_string(),
+            _ => text,
+        };
+        format!(
+            "{:left$}{}{:right$}",
+            "",
+            text,
+            "",
+            left = self.pad_left,
+            right = self.pad_right
+        )
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}%{}{}.{}.{}",
+            self.name, self.format, self.pad_left, self.len, self.pad_right
+        )
+    }
+}
+
+/// A line of output with one cell per column
+pub fn line(cells: impl IntoIterator<Item = String>) -> String {
+    let mut line = String::from("|");
+    for cell in cells {
+        line.push_str(&cell);
+        line.push('|');
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns() {
+        let ram = Column::parse("RAM[256]%D2.6.2").unwrap();
+        let sp = Column::parse("RAM[0]%D2.6.2").unwrap();
+        assert_eq!(line([sp.header(), ram.header()]), "|  RAM[0]  | RAM[256] |");
+        assert_eq!(
+            line([sp.cell(257), ram.cell(-91)]),
+            "|     257  |     -91  |"
+        );
+
+        let bits = Column::parse("out%B1.16.1").unwrap();
+        assert_eq!(bits.cell(-2), " 1111111111111110 ");
+        let hex = Column::parse("x%X2.2.0").unwrap();
+        assert_eq!(hex.cell(0x1ab), "  AB");
+        assert_eq!(hex.to_string(), "x%X2.2.0");
+
+        let plain = Column::parse("RAM[3]").unwrap();
+        assert_eq!(
+            (plain.header(), plain.cell(7)),
+            (" RAM[3] ".into(), "      7 ".into())
+        );
+        assert_eq!(
+            Column::parse("a%D1.1").map(|c| c.len),
+            Err("bad output format `a%D1.1`, expected NAME%FL.W.R".into())
+        );
+        assert!(Column::parse("a%Q1.6.1").is_err());
+    }
+}
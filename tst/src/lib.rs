@@ -0,0 +1,293 @@
+//! Running the course's `.tst` test scripts
+//!
+//! A script loads a program into a backend, the CPU emulator for `.asm` and
+//! `.hack` files or the VM emulator for `.vm` files, sets memory, runs it and
+//! writes lines of chosen variables in the column format of the course tools.
+//! With `compare-to`, each line is checked against the `.cmp` file as it is
+//! written and the first which differs stops the script.
+
+pub mod cpu;
+pub mod format;
+pub mod script;
+pub mod vm;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use format::Column;
+use script::{Command, Script, Statement};
+
+/// A problem in a test script, or a line of output which did not compare
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A simulator a script can drive
+pub trait Backend {
+    /// The value of a variable such as `RAM[256]`
+    fn get(&self, variable: &str) -> Result<i16, String>;
+    fn set(&mut self, variable: &str, value: i16) -> Result<(), String>;
+    /// Advance the simulation, by `ticktock` or `vmstep`
+    fn step(&mut self, command: &str) -> Result<(), String>;
+}
+
+/// Split `NAME[N]` into its name and index
+pub(crate) fn indexed(variable: &str) -> Option<(&str, usize)> {
+    let (name, index) = variable.strip_suffix(']')?.split_once('[')?;
+    Some((name, index.parse().ok()?))
+}
+
+/// Runs scripts with files named relative to a directory
+pub struct Runner {
+    dir: PathBuf,
+    backend: Option<Box<dyn Backend>>,
+    columns: Vec<Column>,
+    output_file: Option<PathBuf>,
+    output: Vec<String>,
+    compare: Option<Vec<String>>,
+    echoes: Vec<String>,
+}
+
+impl Runner {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            backend: None,
+            columns: Vec::new(),
+            output_file: None,
+            output: Vec::new(),
+            compare: None,
+            echoes: Vec::new(),
+        }
+    }
+
+    /// Where `output-file` said to write the output
+    pub fn output_file(&self) -> Option<&Path> {
+        self.output_file.as_deref()
+    }
+
+    /// The lines written so far, starting with the header of `output-list`
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    /// Whether the script compares its output with a `.cmp` file
+    pub fn compares(&self) -> bool {
+        self.compare.is_some()
+    }
+
+    /// Messages from `echo`
+    pub fn echoes(&self) -> &[String] {
+        &self.echoes
+    }
+
+    pub fn run(&mut self, script: &Script) -> Result<(), Error> {
+        self.run_block(&script.statements)
+    }
+
+    fn run_block(&mut self, statements: &[Statement]) -> Result<(), Error> {
+        for statement in statements {
+            let at = |message| Error {
+                line: statement.line,
+                message,
+            };
+            match &statement.command {
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.run_block(body)?;
+                    }
+                }
+                Command::While(condition, body) => {
+                    while condition.holds(self.get(&condition.variable).map_err(at)?) {
+                        self.run_block(body)?;
+                    }
+                }
+                command => self.execute(command).map_err(at)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn backend(&mut self) -> Result<&mut Box<dyn Backend>, String> {
+        self.backend
+            .as_mut()
+            .ok_or_else(|| "no program is loaded".to_string())
+    }
+
+    fn get(&self, variable: &str) -> Result<i16, String> {
+        match &self.backend {
+            Some(backend) => backend.get(variable),
+            None => Err("no program is loaded".to_string()),
+        }
+    }
+
+    fn execute(&mut self, command: &Command) -> Result<(), String> {
+        match command {
+            Command::Load(file) => self.backend = Some(self.load(file.as_deref())?),
+            Command::OutputFile(file) => self.output_file = Some(self.dir.join(file)),
+            Command::CompareTo(file) => {
+                let path = self.dir.join(file);
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+                self.compare = Some(text.lines().map(str::to_string).collect());
+            }
+            Command::OutputList(columns) => {
+                self.columns = columns.clone();
+                let header = format::line(self.columns.iter().map(Column::header));
+                self.write(header)?;
+            }
+            Command::Set(variable, value) => self.backend()?.set(variable, *value)?,
+            Command::Step(step) => self.backend()?.step(step)?,
+            Command::Output => {
+                let cells = self
+                    .columns
+                    .iter()
+                    .map(|column| Ok(column.cell(self.get(&column.name)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                self.write(format::line(cells))?;
+            }
+            Command::Echo(text) => self.echoes.push(text.clone()),
+            Command::Repeat(..) | Command::While(..) => unreachable!("run by run_block"),
+        }
+        Ok(())
+    }
+
+    /// A backend running `file`, or every `.vm` file in the directory
+    fn load(&self, file: Option<&str>) -> Result<Box<dyn Backend>, String> {
+        let path = match file {
+            Some(file) => self.dir.join(file),
+            None => self.dir.clone(),
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("asm" | "hack") => Ok(Box::new(cpu::Cpu::load(&path)?)),
+            Some("vm") => Ok(Box::new(vm::Vm::load(&[path])?)),
+            _ if path.is_dir() => {
+                let mut files = std::fs::read_dir(&path)
+                    .map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .filter(|p| p.extension().is_some_and(|e| e == "vm"))
+                    .collect::<Vec<_>>();
+                if files.is_empty() {
+                    return Err(format!("no .vm files in {}", path.display()));
+                }
+                files.sort();
+                Ok(Box::new(vm::Vm::load(&files)?))
+            }
+            _ => Err(format!("cannot load {}", path.display())),
+        }
+    }
+
+    /// Add a line of output, checking it against the compare file
+    fn write(&mut self, line: String) -> Result<(), String> {
+        let number = self.output.len() + 1;
+        let expected = self
+            .compare
+            .as_ref()
+            .map(|lines| lines.get(number - 1).map(|l| l.trim_end()));
+        self.output.push(line);
+        match expected {
+            Some(expected) if expected != Some(self.output[number - 1].trim_end()) => {
+                Err(format!("comparison failure at line {}", number))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse and run the script at `path`, writing its output file if it names one
+pub fn run_file(path: &Path) -> Result<Runner, Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(path)?;
+    let script = Script::parse(&source)?;
+    let mut runner = Runner::new(path.parent().unwrap_or(Path::new(".")));
+    let result = runner.run(&script);
+    // the output so far is written even when a comparison fails
+    if let Some(out) = runner.output_file() {
+        let mut text = runner.output().join("\n");
+        text.push('\n');
+        std::fs::write(out, text)?;
+    }
+    result?;
+    Ok(runner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [&str; 2] = ["../7/data", "../8/data"];
+
+    fn run(dir: &str, source: &str) -> (Runner, Result<(), Error>) {
+        let mut runner = Runner::new(Path::new(env!("CARGO_MANIFEST_DIR")).join(dir));
+        let result = runner.run(&Script::parse(source).unwrap());
+        (runner, result)
+    }
+
+    #[test]
+    fn test_course_scripts() {
+        let mut count = 0;
+        for dir in DATA {
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|e| e == "tst") {
+                    let source = std::fs::read_to_string(&path).unwrap();
+                    let mut runner = Runner::new(&dir);
+                    let result = runner.run(&Script::parse(&source).unwrap());
+                    assert_eq!(result, Ok(()), "{}", path.display());
+                    assert!(runner.compares() && runner.output().len() == 2);
+                    count += 1;
+                }
+            }
+        }
+        // a CPU and a VM emulator script for each program
+        assert_eq!(count, 16);
+    }
+
+    #[test]
+    fn test_comparison_failure() {
+        let source = "load SimpleAdd.asm, compare-to SimpleAdd.cmp,\n\
+                      output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2;\n\
+                      set RAM[0] 256, repeat 4 { ticktock; }\n\
+                      echo \"too soon\";\n\
+                      output;\n";
+        let (runner, result) = run("../7/data", source);
+        assert_eq!(
+            result,
+            Err(Error {
+                line: 5,
+                message: "comparison failure at line 2".into()
+            })
+        );
+        assert_eq!(runner.output()[1], "|     256  |       0  |");
+        assert_eq!(runner.echoes(), ["too soon"]);
+    }
+
+    #[test]
+    fn test_while() {
+        let source = "load BasicLoop.vm, output-list sp%D1.3.1 local[0]%D1.3.1;\n\
+                      set sp 256, set local 300, set argument 400, set argument[0] 4,\n\
+                      while argument[0] <> 0 { vmstep; }\n\
+                      output;\n";
+        let (runner, result) = run("../8/data", source);
+        assert_eq!(result, Ok(()));
+        assert_eq!(runner.output(), ["| sp  |local|", "| 256 |  10 |"]);
+
+        let (_, result) = run("../8/data", "set RAM[0] 1;\n");
+        assert_eq!(result.unwrap_err().message, "no program is loaded");
+        let (_, result) = run("../8/data", "load BasicLoop.vm, ticktock;\n");
+        assert_eq!(
+            result.unwrap_err().message,
+            "`ticktock` is not a VM emulator command"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+fn main() {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("usage: tst SCRIPT.tst...");
+        std::process::exit(2);
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        match tst::run_file(Path::new(path)) {
+            Ok(runner) => {
+                for echo in runner.echoes() {
+                    println!("{}: {}", path, echo);
+                }
+                if runner.compares() {
+                    println!("{}: End of script - Comparison ended successfully", path);
+                } else {
+                    println!("{}: End of script", path);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,364 @@
+//! Parsing test scripts
+//!
+//! A script is a list of commands, each ended by `,` or by `;`, which also ends
+//! a step of the course tools' single stepping. `repeat N { ... }` and
+//! `while VARIABLE OP VALUE { ... }` run their block again and again, and
+//! `//` and `/* */` start comments.
+
+use crate::Error;
+use crate::format::Column;
+
+/// A command of a test script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Load a program, or every `.vm` file next to the script
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, i16),
+    /// Advance the simulation: `ticktock` on the CPU, `vmstep` on the VM
+    Step(String),
+    Output,
+    Echo(String),
+    Repeat(u64, Vec<Statement>),
+    While(Condition, Vec<Statement>),
+}
+
+/// A command and the line it starts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub line: usize,
+    pub command: Command,
+}
+
+/// The test of a `while` loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub variable: String,
+    pub op: Op,
+    pub value: i16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl Condition {
+    pub fn holds(&self, value: i16) -> bool {
+        match self.op {
+            Op::Eq => value == self.value,
+            Op::Ne => value != self.value,
+            Op::Lt => value < self.value,
+            Op::Gt => value > self.value,
+            Op::Le => value <= self.value,
+            Op::Ge => value >= self.value,
+        }
+    }
+}
+
+/// A parsed test script
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    End,
+    Open,
+    Close,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut tokens = tokenize(source)?.into_iter().peekable();
+        let statements = parse_block(&mut tokens, None)?;
+        Ok(Self { statements })
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> Error {
+    Error {
+        line,
+        message: message.into(),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            ',' | ';' => tokens.push((line, Token::End)),
+            '{' => tokens.push((line, Token::Open)),
+            '}' => tokens.push((line, Token::Close)),
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('*') if chars.next_if_eq(&'/').is_some() => break,
+                        Some('\n') => line += 1,
+                        Some(_) => {}
+                        None => return Err(error(start, "unterminated comment")),
+                    }
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => return Err(error(line, "unterminated string")),
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push((line, Token::Text(text)));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !",;{}\"".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>;
+
+/// Statements up to the `}` closing a block opened on line `open`, or to the end
+fn parse_block(tokens: &mut Tokens, open: Option<usize>) -> Result<Vec<Statement>, Error> {
+    let mut statements = Vec::new();
+    loop {
+        let line = match tokens.peek() {
+            None => {
+                return match open {
+                    Some(open) => Err(error(open, "`{` is never closed")),
+                    None => Ok(statements),
+                };
+            }
+            Some((line, Token::Close)) => {
+                let line = *line;
+                tokens.next();
+                return match open {
+                    Some(_) => Ok(statements),
+                    None => Err(error(line, "unexpected `}`")),
+                };
+            }
+            Some((line, _)) => *line,
+        };
+
+        let mut words = Vec::new();
+        let mut text = None;
+        let opens_block = loop {
+            match tokens.next() {
+                Some((_, Token::Word(word))) => words.push(word),
+                Some((_, Token::Text(t))) => text = Some(t),
+                Some((_, Token::End)) => break false,
+                Some((_, Token::Open)) => break true,
+                Some((line, Token::Close)) => {
+                    return Err(error(line, "expected `,` or `;` before `}`"));
+                }
+                None => return Err(error(line, "expected `,` or `;` at the end of the script")),
+            }
+        };
+        let Some((name, args)) = words.split_first() else {
+            if opens_block || text.is_some() {
+                return Err(error(line, "expected a command"));
+            }
+            continue;
+        };
+
+        let command = match (name.as_str(), opens_block) {
+            ("repeat", true) => {
+                let [count] = args else {
+                    return Err(error(line, "expected `repeat N {`"));
+                };
+                let count = count
+                    .parse()
+                    .map_err(|_| error(line, format!("bad repeat count `{}`", count)))?;
+                Command::Repeat(count, parse_block(tokens, Some(line))?)
+            }
+            ("while", true) => {
+                let condition = parse_condition(args).map_err(|m| error(line, m))?;
+                Command::While(condition, parse_block(tokens, Some(line))?)
+            }
+            (_, true) => return Err(error(line, format!("`{}` does not take a block", name))),
+            ("echo", _) => Command::Echo(text.unwrap_or_else(|| args.join(" "))),
+            // the course tools' display commands do nothing here
+            ("clear-echo" | "breakpoint" | "clear-breakpoints", _) => continue,
+            _ => parse_command(name, args).map_err(|m| error(line, m))?,
+        };
+        statements.push(Statement { line, command });
+    }
+}
+
+fn parse_command(name: &str, args: &[String]) -> Result<Command, String> {
+    let one = || match args {
+        [arg] => Ok(arg.clone()),
+        _ => Err(format!("expected `{} FILE`", name)),
+    };
+    let none = |command| match args {
+        [] => Ok(command),
+        [extra, ..] => Err(format!("unexpected `{}` after `{}`", extra, name)),
+    };
+    match name {
+        "load" => match args {
+            [] => Ok(Command::Load(None)),
+            [file] => Ok(Command::Load(Some(file.clone()))),
+            _ => Err("expected `load [FILE]`".to_string()),
+        },
+        "output-file" => one().map(Command::OutputFile),
+        "compare-to" => one().map(Command::CompareTo),
+        "output-list" => args
+            .iter()
+            .map(|item| Column::parse(item))
+            .collect::<Result<_, _>>()
+            .map(Command::OutputList),
+        "set" => match args {
+            [variable, value] => Ok(Command::Set(variable.clone(), parse_value(value)?)),
+            _ => Err("expected `set VARIABLE VALUE`".to_string()),
+        },
+        "output" => none(Command::Output),
+        "ticktock" | "vmstep" => none(Command::Step(name.to_string())),
+        // half cycles and chip evaluation belong to the hardware simulator
+        "tick" | "tock" | "eval" => Err(format!(
+            "`{}` is a hardware simulator command, step with `ticktock` or `vmstep`",
+            name
+        )),
+        _ => Err(format!("unknown command `{}`", name)),
+    }
+}
+
+/// A decimal number, or one written `%D`, `%X` or `%B` and its digits
+pub fn parse_value(value: &str) -> Result<i16, String> {
+    let (radix, digits) = match value.strip_prefix('%') {
+        Some(rest) if rest.len() > 1 => match rest.split_at(1) {
+            ("B", digits) => (2, digits),
+            ("X", digits) => (16, digits),
+            ("D", digits) => (10, digits),
+            _ => return Err(format!("bad value `{}`", value)),
+        },
+        Some(_) => return Err(format!("bad value `{}`", value)),
+        None => (10, value),
+    };
+    // a word may be written unsigned
+    i32::from_str_radix(digits, radix)
+        .ok()
+        .filter(|n| (-0x8000..=0xffff).contains(n))
+        .map(|n| n as i16)
+        .ok_or_else(|| format!("bad value `{}`", value))
+}
+
+fn parse_condition(args: &[String]) -> Result<Condition, String> {
+    let [variable, op, value] = args else {
+        return Err("expected `while VARIABLE OP VALUE {`".to_string());
+    };
+    let op = match op.as_str() {
+        "=" => Op::Eq,
+        "<>" => Op::Ne,
+        "<" => Op::Lt,
+        ">" => Op::Gt,
+        "<=" => Op::Le,
+        ">=" => Op::Ge,
+        _ => return Err(format!("unknown comparison `{}`", op)),
+    };
+    Ok(Condition {
+        variable: variable.clone(),
+        op,
+        value: parse_value(value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(statements: &[Statement]) -> Vec<&Command> {
+        statements.iter().map(|s| &s.command).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(
+            "// header\nload Add.asm, /* block\ncomment */ output-file Add.out,\n\
+             output-list RAM[0]%D2.6.2;\necho \"adding, twice\";\n\
+             set RAM[0] %X100,\nrepeat 2 {\n  ticktock;\n}\n\
+             while RAM[0] <> -1 { vmstep; }\noutput;\n",
+        )
+        .unwrap();
+        let lines = script.statements.iter().map(|s| s.line).collect::<Vec<_>>();
+        assert_eq!(lines, [2, 3, 4, 5, 6, 7, 10, 11]);
+        let [load, _, list, echo, set, repeat, whiles, output] = &commands(&script.statements)[..]
+        else {
+            panic!("{:?}", script);
+        };
+        assert_eq!(**load, Command::Load(Some("Add.asm".into())));
+        assert!(matches!(list, Command::OutputList(columns) if columns[0].len == 6));
+        assert_eq!(**echo, Command::Echo("adding, twice".into()));
+        assert_eq!(**set, Command::Set("RAM[0]".into(), 256));
+        let Command::Repeat(2, body) = repeat else {
+            panic!("{:?}", repeat);
+        };
+        assert_eq!(commands(body), [&Command::Step("ticktock".into())]);
+        let Command::While(condition, _) = whiles else {
+            panic!("{:?}", whiles);
+        };
+        assert!(condition.holds(0) && !condition.holds(-1));
+        assert_eq!(**output, Command::Output);
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(parse_value("-91"), Ok(-91));
+        assert_eq!(parse_value("65535"), Ok(-1));
+        assert_eq!(parse_value("%B101"), Ok(5));
+        assert_eq!(parse_value("%D-3"), Ok(-3));
+        assert!(parse_value("65536").is_err());
+        assert!(parse_value("%Q1").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let line = |source: &str| Script::parse(source).unwrap_err().line;
+        assert_eq!(line("load a.asm,\nrepeat 3 {\nticktock;\n"), 2);
+        assert_eq!(line("output;\n}"), 2);
+        assert_eq!(line("load a.asm,\n\noutput"), 3);
+        assert_eq!(line("\nfrobnicate;"), 2);
+        assert_eq!(line("set RAM[0];"), 1);
+        assert_eq!(line("repeat x { ticktock; }"), 1);
+        assert_eq!(line("/* open\n\n"), 1);
+        assert_eq!(
+            Script::parse("set a 1,\ntick,\ntock;").unwrap_err(),
+            Error {
+                line: 2,
+                message: "`tick` is a hardware simulator command, step with `ticktock` or `vmstep`"
+                    .to_string()
+            }
+        );
+        assert_eq!(
+            Script::parse("ticktock; output-list a%D1.6;")
+                .unwrap_err()
+                .message,
+            "bad output format `a%D1.6`, expected NAME%FL.W.R"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! The VM emulator backend, running VM commands one at a time
+//!
+//! Memory is laid out as the translators lay it out: `SP`, `LCL`, `ARG`,
+//! `THIS` and `THAT` in `RAM[0..5]`, `temp` at `RAM[5..13]` and statics from
+//! `RAM[16]`, given out to each `File.i` in the order they first appear.
+//! Scripts can read and set `RAM[N]`, `sp`, `local`, `argument`, `this`,
+//! `that`, and `local[N]`, `argument[N]`, `this[N]`, `that[N]` and `temp[N]`,
+//! and step with `vmstep`.
+//!
+//! A program with `Sys.init` starts in it as though called from the bootstrap
+//! code, with `SP` 256; any other starts at its first command. Stepping
+//! after the last command, or returning to an address outside the program,
+//! does nothing.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{Backend, indexed};
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: usize = 5;
+const STATIC: usize = 16;
+
+/// Where a push or pop goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Constant,
+    /// A segment based at the address in a pointer register
+    Based(usize),
+    /// `pointer`, `temp` and `static`, at fixed addresses
+    Fixed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instruction {
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(String),
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    Function(u16),
+    Call(usize, u16),
+    Return,
+}
+
+pub struct Vm {
+    program: Vec<Instruction>,
+    ram: Vec<i16>,
+    pc: usize,
+}
+
+impl Vm {
+    /// Load `.vm` files, each a class whose statics are `FILE.i`
+    pub fn load(paths: &[impl AsRef<Path>]) -> Result<Self, String> {
+        let mut files = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let source = std::fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            let class = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("main")
+                .to_string();
+            files.push((class, source));
+        }
+        Self::new(
+            files
+                .iter()
+                .map(|(class, source)| (class.as_str(), source.as_str())),
+        )
+    }
+
+    /// Parse the source of each class in turn
+    pub fn new<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self, String> {
+        // commands with their label names still to be resolved
+        let mut commands = Vec::new();
+        let mut statics = HashMap::new();
+        let mut targets = HashMap::new();
+        for (class, source) in files {
+            let mut function = String::new();
+            for (i, line) in source.lines().enumerate() {
+                let code = line.split("//").next().unwrap_or_default();
+                let words = code.split_whitespace().collect::<Vec<_>>();
+                if words.is_empty() {
+                    continue;
+                }
+                let at = |m| format!("{}.vm:{}: {}", class, i + 1, m);
+                let command = parse(&words, class, &mut function, &mut statics).map_err(at)?;
+                if let Some(Label::Defines(name)) = &command.1
+                    && targets.insert(name.clone(), commands.len()).is_some()
+                {
+                    return Err(at(format!("`{}` is defined twice", name)));
+                }
+                commands.push(command);
+            }
+        }
+
+        let program = commands
+            .iter()
+            .map(|(instruction, label)| {
+                let Some(Label::RefersTo(name)) = label else {
+                    return Ok(instruction.clone());
+                };
+                let target = *targets
+                    .get(name.as_str())
+                    .ok_or_else(|| format!("`{}` is not defined", name))?;
+                Ok(match instruction {
+                    Instruction::Goto(_) => Instruction::Goto(target),
+                    Instruction::IfGoto(_) => Instruction::IfGoto(target),
+                    Instruction::Call(_, n_args) => Instruction::Call(target, *n_args),
+                    other => other.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut vm = Self {
+            program,
+            ram: vec![0; 0x8000],
+            pc: 0,
+        };
+        if let Some(&init) = targets.get("Sys.init") {
+            vm.ram[SP] = 256;
+            vm.call(init, 0, vm.program.len());
+        }
+        Ok(vm)
+    }
+
+    /// The word at `address`, wrapping around the end of RAM
+    fn at(&mut self, address: usize) -> &mut i16 {
+        let len = self.ram.len();
+        &mut self.ram[address % len]
+    }
+
+    fn word(&self, pointer: usize) -> usize {
+        self.ram[pointer] as u16 as usize
+    }
+
+    fn push(&mut self, value: i16) {
+        *self.at(self.word(SP)) = value;
+        self.ram[SP] = self.ram[SP].wrapping_add(1);
+    }
+
+    fn pop(&mut self) -> i16 {
+        self.ram[SP] = self.ram[SP].wrapping_sub(1);
+        *self.at(self.word(SP))
+    }
+
+    fn call(&mut self, target: usize, n_args: u16, ret: usize) {
+        self.push(ret as i16);
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[pointer]);
+        }
+        self.ram[ARG] = self.ram[SP].wrapping_sub(5 + n_args as i16);
+        self.ram[LCL] = self.ram[SP];
+        self.pc = target;
+    }
+
+    fn address(&self, segment: Segment, index: u16) -> usize {
+        match segment {
+            Segment::Based(pointer) => self.word(pointer) + usize::from(index),
+            _ => usize::from(index),
+        }
+    }
+
+    fn execute(&mut self) {
+        let Some(instruction) = self.program.get(self.pc).cloned() else {
+            return;
+        };
+        self.pc += 1;
+        match instruction {
+            Instruction::Push(Segment::Constant, value) => self.push(value as i16),
+            Instruction::Push(segment, index) => {
+                let value = *self.at(self.address(segment, index));
+                self.push(value);
+            }
+            Instruction::Pop(segment, index) => {
+                let value = self.pop();
+                *self.at(self.address(segment, index)) = value;
+            }
+            Instruction::Arithmetic(op) => {
+                let y = self.pop();
+                let value = match op.as_str() {
+                    "neg" => y.wrapping_neg(),
+                    "not" => !y,
+                    _ => {
+                        let x = self.pop();
+                        match op.as_str() {
+                            "add" => x.wrapping_add(y),
+                            "sub" => x.wrapping_sub(y),
+                            "eq" => -i16::from(x == y),
+                            "gt" => -i16::from(x > y),
+                            "lt" => -i16::from(x < y),
+                            "and" => x & y,
+                            _ => x | y,
+                        }
+                    }
+                };
+                self.push(value);
+            }
+            Instruction::Label => {}
+            Instruction::Goto(target) => self.pc = target,
+            Instruction::IfGoto(target) => {
+                if self.pop() != 0 {
+                    self.pc = target;
+                }
+            }
+            Instruction::Function(n_vars) => {
+                for _ in 0..n_vars {
+                    self.push(0);
+                }
+            }
+            Instruction::Call(target, n_args) => self.call(target, n_args, self.pc),
+            Instruction::Return => {
+                // RAM is a power of two long, so wrapping keeps addresses right
+                let frame = self.word(LCL);
+                let ret = *self.at(frame.wrapping_sub(5)) as u16 as usize;
+                let value = self.pop();
+                *self.at(self.word(ARG)) = value;
+                self.ram[SP] = self.ram[ARG].wrapping_add(1);
+                for (i, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+                    self.ram[pointer] = *self.at(frame.wrapping_sub(1 + i));
+                }
+                // a return address outside the program ends it
+                self.pc = ret.min(self.program.len());
+            }
+        }
+    }
+}
+
+/// A label a command defines, as `label` and `function` do, or refers to
+enum Label {
+    Defines(String),
+    RefersTo(String),
+}
+
+fn parse(
+    words: &[&str],
+    class: &str,
+    function: &mut String,
+    statics: &mut HashMap<String, u16>,
+) -> Result<(Instruction, Option<Label>), String> {
+    let number = |word: &str| {
+        word.parse::<u16>()
+            .map_err(|_| format!("expected a number, found `{}`", word))
+    };
+    let scoped = |name: &str| format!("{}${}", function, name);
+    let command = match words {
+        ["push" | "pop", segment, index] => {
+            let index = number(index)?;
+            let (segment, index) = match *segment {
+                "constant" if words[0] == "push" && index < 0x8000 => (Segment::Constant, index),
+                "local" => (Segment::Based(LCL), index),
+                "argument" => (Segment::Based(ARG), index),
+                "this" => (Segment::Based(THIS), index),
+                "that" => (Segment::Based(THAT), index),
+                "pointer" if index < 2 => (Segment::Fixed, THIS as u16 + index),
+                "temp" if index < 8 => (Segment::Fixed, TEMP as u16 + index),
+                "static" => {
+                    let next = STATIC as u16 + statics.len() as u16;
+                    let address = *statics
+                        .entry(format!("{}.{}", class, index))
+                        .or_insert(next);
+                    (Segment::Fixed, address)
+                }
+                _ => return Err(format!("bad segment `{} {}`", segment, index)),
+            };
+            let instruction = if words[0] == "push" {
+                Instruction::Push(segment, index)
+            } else {
+                Instruction::Pop(segment, index)
+            };
+            (instruction, None)
+        }
+        [op @ ("add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not")] => {
+            (Instruction::Arithmetic(op.to_string()), None)
+        }
+        ["label", name] => (Instruction::Label, Some(Label::Defines(scoped(name)))),
+        ["goto", name] => (Instruction::Goto(0), Some(Label::RefersTo(scoped(name)))),
+        ["if-goto", name] => (Instruction::IfGoto(0), Some(Label::RefersTo(scoped(name)))),
+        ["function", name, n_vars] => {
+            *function = name.to_string();
+            (
+                Instruction::Function(number(n_vars)?),
+                Some(Label::Defines(name.to_string())),
+            )
+        }
+        ["call", name, n_args] => (
+            Instruction::Call(0, number(n_args)?),
+            Some(Label::RefersTo(name.to_string())),
+        ),
+        ["return"] => (Instruction::Return, None),
+        _ => return Err(format!("unknown command `{}`", words.join(" "))),
+    };
+    Ok(command)
+}
+
+/// The address of the pointer register named in a script
+fn pointer(name: &str) -> Option<usize> {
+    match name {
+        "sp" => Some(SP),
+        "local" => Some(LCL),
+        "argument" => Some(ARG),
+        "this" => Some(THIS),
+        "that" => Some(THAT),
+        _ => None,
+    }
+}
+
+impl Vm {
+    /// The RAM address a script variable names
+    fn variable(&self, variable: &str) -> Option<usize> {
+        if let Some(pointer) = pointer(variable) {
+            return Some(pointer);
+        }
+        let address = match indexed(variable)? {
+            ("RAM", address) => address,
+            ("temp", index) if index < 8 => TEMP + index,
+            (name, index) if name != "sp" => self.word(pointer(name)?) + index,
+            _ => return None,
+        };
+        (address < self.ram.len()).then_some(address)
+    }
+}
+
+impl Backend for Vm {
+    fn get(&self, variable: &str) -> Result<i16, String> {
+        self.variable(variable)
+            .map(|address| self.ram[address])
+            .ok_or_else(|| format!("unknown variable `{}`", variable))
+    }
+
+    fn set(&mut self, variable: &str, value: i16) -> Result<(), String> {
+        let address = self
+            .variable(variable)
+            .ok_or_else(|| format!("unknown variable `{}`", variable))?;
+        self.ram[address] = value;
+        Ok(())
+    }
+
+    fn step(&mut self, command: &str) -> Result<(), String> {
+        match command {
+            "vmstep" => {
+                self.execute();
+                Ok(())
+            }
+            _ => Err(format!("`{}` is not a VM emulator command", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(files: &[(&str, &str)], steps: usize) -> Vm {
+        let mut vm = Vm::new(files.iter().copied()).unwrap();
+        for _ in 0..steps {
+            vm.step("vmstep").unwrap();
+        }
+        vm
+    }
+
+    #[test]
+    fn test_call_and_statics() {
+        let main = "function Sys.init 0\npush constant 7\ncall Main.double 1\n\
+                    pop static 1\nlabel END\ngoto END\n";
+        let double = "function Main.double 1 // x + x\npush argument 0\n\
+                      push argument 0\nadd\npop static 0\npush static 0\nreturn\n";
+        let vm = run(&[("Sys", main), ("Main", double)], 40);
+        assert_eq!(vm.get("sp"), Ok(261));
+        // Sys.1 is seen first and takes RAM[16]
+        assert_eq!((vm.ram[16], vm.ram[17]), (14, 14));
+    }
+
+    #[test]
+    fn test_end_of_program() {
+        let mut vm = run(&[("Add", "push constant 2\npush constant 3\nadd\n")], 0);
+        vm.set("sp", 256).unwrap();
+        for _ in 0..5 {
+            vm.step("vmstep").unwrap();
+        }
+        assert_eq!((vm.get("sp"), vm.get("RAM[256]")), (Ok(257), Ok(5)));
+        assert!(vm.step("ticktock").is_err());
+        assert!(Vm::new([("Bad", "goto NOWHERE\n")]).is_err());
+        assert!(Vm::new([("Bad", "pop constant 1\n")]).is_err());
+        assert_eq!(
+            Vm::new([
+                ("Sys", "function Sys.f 0\nreturn\n"),
+                ("Two", "function Sys.f 0\n")
+            ])
+            .err(),
+            Some("Two.vm:1: `Sys.f` is defined twice".into())
+        );
+        assert_eq!(
+            Vm::new([("Loop", "label A\nlabel A\n")]).err(),
+            Some("Loop.vm:2: `$A` is defined twice".into())
+        );
+    }
+}